@@ -0,0 +1,21 @@
+#version 450
+
+// Temporal accumulation: averages the frame just rendered into the history of the previous ones. Runs while the camera
+// rests, see src/render_core/accumulation.rs, which copies the result back for tone mapping.
+
+layout(push_constant) uniform PushConstants {
+    // Weight of the new frame, 1 / samples, so every sample counts the same; 1 starts over.
+    float weight;
+} push;
+
+layout(set = 0, binding = 0) uniform sampler2D current;
+layout(set = 0, binding = 1) uniform sampler2D history;
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec4 color = texelFetch(current, texel, 0);
+    // When starting over the history may hold anything, NaNs included, so it isn't read at all.
+    f_color = push.weight >= 1.0 ? color : mix(texelFetch(history, texel, 0), color, push.weight);
+}
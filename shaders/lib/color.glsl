@@ -0,0 +1,34 @@
+// Output color space. Shaders compute in linear light and write f_color through outputColor(): linear RGB, straight alpha.
+// The render target does the encoding: _SRGB targets in hardware, float targets (EXR) keep the values linear, and for
+// UNORM targets, used when a surface offers no sRGB format, the pipeline sets ENCODE_SRGB and outputColor() encodes.
+#ifndef LIB_COLOR_GLSL
+#define LIB_COLOR_GLSL
+
+// Set by render_core::pipeline for UNORM render targets; keep the id in sync with ENCODE_SRGB_CONSTANT there.
+layout(constant_id = 0) const bool ENCODE_SRGB = false;
+
+vec3 linearToSrgb( vec3 c )
+{
+    c = clamp(c, 0.0, 1.0);
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0/2.4)) - 0.055, step(vec3(0.0031308), c));
+}
+
+// Inverse of linearToSrgb(), for colors that come display encoded, like the output of Shadertoy passes.
+vec3 srgbToLinear( vec3 c )
+{
+    c = clamp(c, 0.0, 1.0);
+    return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(vec3(0.04045), c));
+}
+
+// Relative luminance of linear Rec. 709 / sRGB primaries.
+float luminance( vec3 c )
+{
+    return dot(c, vec3(0.2126, 0.7152, 0.0722));
+}
+
+vec4 outputColor( vec4 linear )
+{
+    return ENCODE_SRGB ? vec4(linearToSrgb(linear.rgb), linear.a) : linear;
+}
+
+#endif
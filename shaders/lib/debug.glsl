@@ -0,0 +1,60 @@
+// Colors of the debug views and the legend bar drawn under them. The viewer shows debug views without tone mapping,
+// so the colors on screen are the ones in the legend.
+#ifndef LIB_DEBUG_GLSL
+#define LIB_DEBUG_GLSL
+
+#include <color.glsl>
+
+// Height of the legend bar at the bottom of the frame, in pixels.
+#define LEGEND_HEIGHT 16.0
+
+// Anton Mikhailov's polynomial fit of the Turbo colormap: dark blue at 0 over green to dark red at 1. Linear output.
+vec3 heatmap( float x )
+{
+    const vec4 red4 = vec4(0.13572138, 4.61539260, -42.66032258, 132.13108234);
+    const vec4 green4 = vec4(0.09140261, 2.19418839, 4.84296658, -14.18503333);
+    const vec4 blue4 = vec4(0.10667330, 12.64194608, -60.58204836, 110.36276771);
+    const vec2 red2 = vec2(-152.94239396, 59.28637943);
+    const vec2 green2 = vec2(4.27729857, 2.82956604);
+    const vec2 blue2 = vec2(-89.90310912, 27.34824973);
+
+    x = clamp(x, 0.0, 1.0);
+    vec4 v4 = vec4(1.0, x, x * x, x * x * x);
+    vec2 v2 = v4.zw * v4.z;
+    vec3 encoded = vec3(dot(v4, red4) + dot(v2, red2), dot(v4, green4) + dot(v2, green2), dot(v4, blue4) + dot(v2, blue2));
+    return srgbToLinear(encoded);
+}
+
+// A color per category that neighbouring indices don't share. Linear output.
+vec3 categoryColor( uint index )
+{
+    const vec3 palette[8] = vec3[8](
+        vec3(0.90, 0.10, 0.29), vec3(0.24, 0.71, 0.29), vec3(1.00, 0.88, 0.10), vec3(0.26, 0.39, 0.85),
+        vec3(0.96, 0.51, 0.19), vec3(0.57, 0.12, 0.71), vec3(0.27, 0.94, 0.94), vec3(0.94, 0.20, 0.90));
+    vec3 color = palette[index % 8u];
+    // Darker after every 8 categories, so up to 24 stay apart.
+    return srgbToLinear(color * (1.0 - 0.3 * float((index / 8u) % 3u)));
+}
+
+// Whether the pixel at `coord` (gl_FragCoord) is on the legend bar of a frame `resolution` pixels large.
+bool onLegend( vec2 coord, vec2 resolution )
+{
+    return coord.y > resolution.y - LEGEND_HEIGHT;
+}
+
+// Position along the legend bar, 0 at the left edge and 1 at the right.
+float legendPosition( vec2 coord, vec2 resolution )
+{
+    return coord.x / resolution.x;
+}
+
+// Darkens `color` on the top edge of the bar and on the `ticks` - 1 lines that divide it into equal parts.
+vec3 legendMarks( vec3 color, vec2 coord, vec2 resolution, float ticks )
+{
+    float x = legendPosition(coord, resolution) * ticks;
+    bool tick = abs(x - round(x)) * resolution.x / ticks < 1.0 && x > 0.5 && x < ticks - 0.5;
+    bool edge = coord.y < resolution.y - LEGEND_HEIGHT + 1.0;
+    return tick || edge ? color * 0.1 : color;
+}
+
+#endif
@@ -0,0 +1,41 @@
+// Per-frame data, `Constants` on the Rust side, in set 1; one buffer per frame in flight.
+// Shared by the 3D scene shader and the Shadertoy wrapper.
+#ifndef LIB_FRAME_GLSL
+#define LIB_FRAME_GLSL
+
+// time and delta_time are scene time in seconds, which the viewer can pause, slow down and scrub.
+// mouse.xy is the cursor in pixels from the top left, like gl_FragCoord, mouse.zw where the left button was last pressed;
+// mouse_buttons has bit 0 set for the left, bit 1 for the right and bit 2 for the middle button.
+// params holds the scene's `params`, see param().
+// The quality members are `Quality` in src/render_core/quality.rs; fov is the vertical field of view in degrees.
+// jitter offsets the rays by a fraction of a pixel while the viewer accumulates frames, see src/render_core/accumulation.rs.
+layout(std140, set = 1, binding = 0) uniform Frame {
+    mat4 view;
+    vec3 camera_pos;
+    vec2 resolution;
+    float time;
+    float delta_time;
+    vec4 mouse;
+    uint frame_index;
+    uint mouse_buttons;
+    vec4 params[4];
+    uint max_steps;
+    float far_plane;
+    float hit_epsilon;
+    float normal_epsilon;
+    uint shadow_steps;
+    float shadow_softness;
+    uint ao_taps;
+    uint bounces;
+    float fov;
+    // Spelled out so the offsets match `Constants`, where the alignment of jitter needs explicit padding.
+    uint padding2;
+    vec2 jitter;
+} frame;
+
+// The scene's user parameter `i`, 0 to 15; unset ones are 0.
+float param(int i) {
+    return frame.params[i / 4][i % 4];
+}
+
+#endif
@@ -0,0 +1,57 @@
+// Normals, soft shadows and ambient occlusion of the scene.
+// Needs `vec4 map( in vec3 p )` to be defined before this file is included.
+#ifndef LIB_LIGHTING_GLSL
+#define LIB_LIGHTING_GLSL
+
+float f( in vec3 p )
+{
+    return map(p).x;
+}
+
+//vec3 calcNormal( in vec3 p ) // for function f(p)
+//{
+//    const float eps = 0.00001; // or some other value
+//    const vec2 h = vec2(eps,0);
+//    return normalize( vec3(f(p+h.xyy) - f(p-h.xyy),
+//                           f(p+h.yxy) - f(p-h.yxy),
+//                           f(p+h.yyx) - f(p-h.yyx) ) );
+//}
+
+// `h` is the offset of the finite differences.
+vec3 calcNormal( in vec3 p, float h ) // for function f(p)
+{
+    const vec2 k = vec2(1,-1);
+    return normalize( k.xyy*f( p + k.xyy*h ) +
+    k.yyx*f( p + k.yyx*h ) +
+    k.yxy*f( p + k.yxy*h ) +
+    k.xxx*f( p + k.xxx*h ) );
+}
+
+// `light` is the direction towards the light; occluders further away than `max_distance` don't count.
+// Takes at most `steps` steps; higher `softness` gives harder edges.
+float calculateShadow(vec3 point, vec3 light, float max_distance, int steps, float softness) {
+    float t = 0.02;
+    float result = 1.0;
+    for (int i = 0; i < steps && t < max_distance; i++) {
+        float d = f(point + light * t);
+        result = min(result, softness * d / t);
+        if (result < 0.004) {
+            break;
+        }
+        t += d;
+    }
+    return result;
+}
+
+// Samples `taps` points along the normal; with 0 taps there is no occlusion.
+float calculateAO(vec3 point, vec3 normal, int taps) {
+    float start = 0.01;
+    float step = 0.03;
+    float ao = 0;
+    for (int i = 0; i < taps; i++) {
+        ao += 1/exp2(i) * float(i) * step - f(point + normal * float(i) * (start + step));
+    }
+    return 1 - 5*ao;
+}
+
+#endif
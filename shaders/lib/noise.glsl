@@ -0,0 +1,77 @@
+// Hashes and 2D noise.
+#ifndef LIB_NOISE_GLSL
+#define LIB_NOISE_GLSL
+
+vec3 hash3( in vec3 p )      // this hash is not production ready, please
+{                        // replace this by something better
+     p = vec3( dot(p,vec3(127.1,311.7, 74.7)),
+               dot(p,vec3(269.5,183.3,246.1)),
+               dot(p,vec3(113.5,271.9,124.6)));
+
+     return -1.0 + 2.0*fract(sin(p)*43758.5453123);
+}
+
+vec2 hash2(in vec2 p) {
+    return hash3(vec3(p, 0)).xy;
+}
+
+vec2 hash(in vec2 p){
+    return hash2(p);
+}
+
+float hash1( vec2 p )
+{
+    p  = 50.0*fract( p*0.3183099 );
+    return fract( p.x*p.y*(p.x+p.y) );
+}
+
+
+// https://iquilezles.org/articles/gradientnoise/
+// returns 3D value noise (in .x)  and its derivatives (in .yz)
+float noised( in vec2 x )
+{
+    vec2 i = floor( x );
+    vec2 f = fract( x );
+
+    vec2 u = f*f*f*(f*(f*6.0-15.0)+10.0);
+    //vec2 du = 30.0*f*f*(f*(f-2.0)+1.0);
+
+    vec2 ga = hash( i + vec2(0.0,0.0) );
+    vec2 gb = hash( i + vec2(1.0,0.0) );
+    vec2 gc = hash( i + vec2(0.0,1.0) );
+    vec2 gd = hash( i + vec2(1.0,1.0) );
+
+    float va = dot( ga, f - vec2(0.0,0.0) );
+    float vb = dot( gb, f - vec2(1.0,0.0) );
+    float vc = dot( gc, f - vec2(0.0,1.0) );
+    float vd = dot( gd, f - vec2(1.0,1.0) );
+
+    return va + u.x*(vb-va) + u.y*(vc-va) + u.x*u.y*(va-vb-vc+vd); //,   // value
+//                 ga + u.x*(gb-ga) + u.y*(gc-ga) + u.x*u.y*(ga-gb-gc+gd) +  // derivatives
+//                 du * (u.yx*(va-vb-vc+vd) + vec2(vb,vc) - va));
+}
+
+// https://www.shadertoy.com/view/4ttSWf
+float noise( in vec2 x )
+{
+    vec2 p = floor(x);
+    vec2 w = fract(x);
+    #if 1
+    vec2 u = w*w*w*(w*(w*6.0-15.0)+10.0);
+    #else
+    vec2 u = w*w*(3.0-2.0*w);
+    #endif
+
+    float a = hash1(p+vec2(0,0));
+    float b = hash1(p+vec2(1,0));
+    float c = hash1(p+vec2(0,1));
+    float d = hash1(p+vec2(1,1));
+
+    return -1.0+2.0*(a + (b-a)*u.x + (c-a)*u.y + (a - b - c + d)*u.x*u.y);
+}
+
+float rand(vec2 co){
+    return fract(sin(dot(co, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+#endif
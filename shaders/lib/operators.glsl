@@ -0,0 +1,38 @@
+// Combining distances. The vec4 variants carry the distance in .x and a color in .yzw, like map().
+#ifndef LIB_OPERATORS_GLSL
+#define LIB_OPERATORS_GLSL
+
+float smin( float a, float b, float k )
+{
+    float h = max( k-abs(a-b), 0.0 )/k;
+    return min( a, b ) - h*h*k*(1.0/4.0);
+}
+
+vec4 opU( vec4 d1, vec4 d2 )
+{
+    return (d1.x<d2.x) ? d1 : d2;
+}
+
+// Repeats space every `period` units; an axis with a period of 0 is left as is.
+vec3 opRepeat( vec3 p, vec3 period )
+{
+    return p - period * floor(p / max(period, vec3(0.000001)) + 0.5);
+}
+
+vec4 opSmoothUnion( vec4 a, vec4 b, float k )
+{
+    float blend = clamp(0.5 + 0.5 * (a.x - b.x) / k, 0.0, 1.0);
+    return vec4(smin(a.x, b.x, k), mix(a.yzw, b.yzw, blend));
+}
+
+vec4 opSubtraction( vec4 a, vec4 b )
+{
+    return vec4(max(a.x, -b.x), a.yzw);
+}
+
+vec4 opIntersection( vec4 a, vec4 b )
+{
+    return (a.x > b.x) ? a : b;
+}
+
+#endif
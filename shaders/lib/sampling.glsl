@@ -0,0 +1,112 @@
+// Random numbers and importance sampling for the path tracer: a per-pixel random number generator, the diffuse and
+// GGX specular lobes with their pdfs, and directions inside the cone of a sun disc.
+#ifndef LIB_SAMPLING_GLSL
+#define LIB_SAMPLING_GLSL
+
+#define PI 3.14159265359
+
+// PCG hash (Jarzynski and Olano, "Hash Functions for GPU Rendering").
+uint pcgHash( uint v )
+{
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// State of the generator for `pixel` in frame `frame`; a new frame index gives new numbers.
+uint randomSeed( uvec2 pixel, uint frame )
+{
+    return pcgHash(pixel.x + pcgHash(pixel.y + pcgHash(frame)));
+}
+
+// Uniform in [0, 1); advances `state`.
+float random( inout uint state )
+{
+    state = pcgHash(state);
+    // 24 bits, so the result is never rounded up to 1.
+    return float(state >> 8u) / 16777216.0;
+}
+
+vec2 random2( inout uint state )
+{
+    return vec2(random(state), random(state));
+}
+
+// Two unit vectors that make an orthonormal basis with `n` (Duff et al., "Building an Orthonormal Basis, Revisited").
+void basis( vec3 n, out vec3 t, out vec3 b )
+{
+    float s = n.z >= 0.0 ? 1.0 : -1.0;
+    float a = -1.0 / (s + n.z);
+    float c = n.x * n.y * a;
+    t = vec3(1.0 + s * n.x * n.x * a, s * c, -s * n.x);
+    b = vec3(c, s + n.y * n.y * a, -n.y);
+}
+
+// `local` in the frame whose z axis is `n`.
+vec3 toWorld( vec3 local, vec3 n )
+{
+    vec3 t, b;
+    basis(n, t, b);
+    return local.x * t + local.y * b + local.z * n;
+}
+
+// Cosine weighted direction around `n`, with the pdf cosineHemispherePdf().
+vec3 sampleCosineHemisphere( vec3 n, vec2 u )
+{
+    float r = sqrt(u.x);
+    float phi = 2.0 * PI * u.y;
+    return toWorld(vec3(r * cos(phi), r * sin(phi), sqrt(max(1.0 - u.x, 0.0))), n);
+}
+
+float cosineHemispherePdf( float n_dot_l )
+{
+    return max(n_dot_l, 0.0) / PI;
+}
+
+// Uniform direction in the cone of half angle acos(`cos_max`) around `axis`.
+vec3 sampleCone( vec3 axis, float cos_max, vec2 u )
+{
+    float cos_theta = mix(1.0, cos_max, u.x);
+    float sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    float phi = 2.0 * PI * u.y;
+    return toWorld(vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta), axis);
+}
+
+// GGX normal distribution with `alpha` = roughness^2.
+float ggxD( float n_dot_h, float alpha )
+{
+    float a2 = alpha * alpha;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Height correlated Smith visibility term, G / (4 n.l n.v).
+float smithVisibility( float n_dot_l, float n_dot_v, float alpha )
+{
+    float a2 = alpha * alpha;
+    float l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    float v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    return 0.5 / max(l + v, 1e-8);
+}
+
+float fresnelSchlick( float f0, float cos_theta )
+{
+    return f0 + (1.0 - f0) * pow(1.0 - clamp(cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Half vector around `n` distributed like ggxD() * n.h; reflecting the view direction about it gives ggxPdf().
+vec3 sampleGgxHalfVector( vec3 n, float alpha, vec2 u )
+{
+    float cos_theta = sqrt((1.0 - u.x) / (1.0 + (alpha * alpha - 1.0) * u.x));
+    float sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    float phi = 2.0 * PI * u.y;
+    return toWorld(vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta), n);
+}
+
+// Pdf of the reflected direction for a half vector from sampleGgxHalfVector().
+float ggxPdf( float n_dot_h, float v_dot_h, float alpha )
+{
+    return ggxD(n_dot_h, alpha) * n_dot_h / max(4.0 * v_dot_h, 1e-8);
+}
+
+#endif
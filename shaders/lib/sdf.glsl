@@ -0,0 +1,55 @@
+// Signed distance functions. 3D shapes are centered at the origin unless noted otherwise.
+#ifndef LIB_SDF_GLSL
+#define LIB_SDF_GLSL
+
+#include "noise.glsl"
+
+float sphere_sdf(vec3 observer, vec3 target, float r) {
+    return length(observer - target) - r;
+}
+
+float sdVerticalCapsule( vec3 p, float h, float r )
+{
+    p.y -= clamp( p.y, 0.0, h );
+    return length( p ) - r;
+}
+
+float sdBox( vec3 p, vec3 b )
+{
+    vec3 q = abs(p) - b;
+    return length(max(q,0.0)) + min(max(q.x,max(q.y,q.z)),0.0);
+}
+
+float sdTorus( vec3 p, vec2 t )
+{
+    vec2 q = vec2(length(p.xz)-t.x,p.y);
+    return length(q)-t.y;
+}
+
+// params: max height, offset, frequency; extra: snow color; color: grass color
+vec4 sdTerrain(vec3 p, vec4 params, vec3 snow_color, vec3 grass_color) {
+    float noise_val = noised(p.xz*params.z);
+    float height_factor = (noise_val+1)/2;
+    float d = p.y - (noise_val * params.x - params.y);
+    vec3 color;
+    if (height_factor < 0.5) {
+        color = grass_color;
+    } else {
+        height_factor = (height_factor - 0.5) * 2;
+        color = snow_color * height_factor + grass_color * 1 - height_factor;
+    }
+    return vec4(d, color);
+}
+
+float sdCircle( vec2 p, float r )
+{
+    return length(p) - r;
+}
+
+float sdRect( vec2 p, vec2 b )
+{
+    vec2 q = abs(p) - b;
+    return length(max(q,0.0)) + min(max(q.x,q.y),0.0);
+}
+
+#endif
@@ -0,0 +1,39 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// First pass of auto exposure: the log luminance of the HDR frame, summed over blocks of pixels. The target is a power of
+// two large, so blitting it down to 1x1 averages every texel with the same weight, see src/render_core/exposure.rs.
+
+layout(push_constant) uniform PushConstants {
+    // Side of the block of HDR pixels each texel covers.
+    uint block;
+} push;
+
+layout(set = 0, binding = 0) uniform sampler2D hdr;
+
+layout(location = 0) out vec4 f_color;
+
+#include <color.glsl>
+
+// Black pixels would pull the average to minus infinity.
+#define MIN_LUMINANCE 1e-4
+
+void main() {
+    ivec2 size = textureSize(hdr, 0);
+    ivec2 origin = ivec2(gl_FragCoord.xy) * int(push.block);
+    float sum = 0.0;
+    float count = 0.0;
+    for (int y = 0; y < int(push.block); y++) {
+        for (int x = 0; x < int(push.block); x++) {
+            ivec2 texel = origin + ivec2(x, y);
+            if (all(lessThan(texel, size))) {
+                sum += log(max(luminance(texelFetch(hdr, texel, 0).rgb), MIN_LUMINANCE));
+                count += 1.0;
+            }
+        }
+    }
+    // Both scaled by the block's area: the mean of .r over .g is the mean log luminance of the pixels in the frame,
+    // texels past its edge count as neither.
+    float area = float(push.block * push.block);
+    f_color = vec4(sum / area, count / area, 0.0, 1.0);
+}
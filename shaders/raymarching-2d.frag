@@ -1,21 +1,51 @@
 #version 450
+#extension GL_GOOGLE_include_directive : require
+
+layout(push_constant) uniform PushConstants {
+    vec2 resolution;
+    vec2 pan;
+    float zoom;
+    float time;
+} push;
 
 layout(location = 0) out vec4 f_color;
 
-float circle_sdf(vec2 p, float r) {
-    return length(gl_FragCoord.xy - p) - r;
+#include <color.glsl>
+#include <sdf.glsl>
+
+// Shapes supplied by the application, see src/scene/shapes_2d.rs; keep the constants and the layout in sync.
+#define SHAPE_CIRCLE 0u
+#define SHAPE_RECT 1u
+
+struct Shape {
+    vec4 params;
+    uint kind;
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer ShapeBuffer {
+    Shape shapes[];
+} scene;
+
+float sdShape(Shape shape, vec2 p) {
+    vec2 q = p - shape.params.xy;
+    if (shape.kind == SHAPE_RECT) {
+        return sdRect(q, shape.params.zw);
+    }
+    return sdCircle(q, shape.params.z);
 }
 
 void main() {
-    float circle1 = circle_sdf(vec2(150, 150), 30.0);
-    float circle2 = circle_sdf(vec2(350, 255), 100.0);
-    float circle3 = circle_sdf(vec2(244, 400), 70.0);
+    // Pixels at zoom 1 with y down, like gl_FragCoord; `pan` is the point in the top left corner.
+    vec2 p = gl_FragCoord.xy / push.zoom + push.pan;
 
-    float d = min(circle1, min(circle2, circle3));
+    float d = 1e10;
+    for (int i = 0; i < scene.shapes.length(); i++) {
+        d = min(d, sdShape(scene.shapes[i], p));
+    }
 
     if (d < 0.0) {
-        f_color = vec4(0.0, 1.0, 0.0, 1.0);
+        f_color = outputColor(vec4(0.0, 1.0, 0.0, 1.0));
     } else {
-        f_color = vec4(vec3(1.0, 0.0, 0.0) * sin(d), 1.0);
+        f_color = outputColor(vec4(vec3(1.0, 0.0, 0.0) * sin(d - 2.0 * push.time), 1.0));
     }
 }
\ No newline at end of file
@@ -1,246 +1,511 @@
 #version 450
-
-layout(push_constant) uniform PushConstants {
-    mat4 view;
-    vec3 camera_pos;
-    vec2 resolution;
-} push;
+#extension GL_GOOGLE_include_directive : require
 
 layout(location = 0) out vec4 f_color;
 
-float terrain_max_height = 50;
-float terrain_offset = 10;
-
-vec3 hash3( in vec3 p )      // this hash is not production ready, please
-{                        // replace this by something better
-     p = vec3( dot(p,vec3(127.1,311.7, 74.7)),
-               dot(p,vec3(269.5,183.3,246.1)),
-               dot(p,vec3(113.5,271.9,124.6)));
-
-     return -1.0 + 2.0*fract(sin(p)*43758.5453123);
+#include <color.glsl>
+#include <frame.glsl>
+#include <noise.glsl>
+#include <sdf.glsl>
+#include <operators.glsl>
+#include <debug.glsl>
+#include <sampling.glsl>
+
+// The scene's material table and lights, `MaterialData` and `LightData` in src/scene/mod.rs; set 0 also holds the
+// interpreter's program at binding 0.
+struct Material {
+    vec4 color;
+};
+
+// .w of position is 0 for directional lights, with the normalized direction towards the light in .xyz, 1 for point lights.
+// The color is premultiplied by the intensity.
+struct Light {
+    vec4 position;
+    vec4 color;
+};
+
+layout(std430, set = 0, binding = 1) readonly buffer MaterialBuffer {
+    Material materials[];
+} scene_materials;
+
+layout(std430, set = 0, binding = 2) readonly buffer LightBuffer {
+    Light lights[];
+} scene_lights;
+
+// Features of the shader variant, set by render_core::variants; keep the ids in sync with the constants there.
+// Turning one off compiles its code out of the pipeline instead of branching on it per pixel.
+layout(constant_id = 1) const bool SHADOWS = true;
+layout(constant_id = 2) const bool AO = true;
+layout(constant_id = 3) const bool FOG = false;
+layout(constant_id = 4) const bool TERRAIN = true;
+// What to draw instead of the shaded scene; keep the values in sync with `DebugView` in src/render_core/debug_view.rs.
+layout(constant_id = 5) const uint DEBUG_VIEW = 0;
+// Path trace the scene with pathTrace() instead of shading it; the viewer accumulates the frames into the image.
+layout(constant_id = 6) const bool PATH_TRACING = false;
+
+#define DEBUG_VIEW_OFF 0u
+#define DEBUG_VIEW_STEPS 1u
+#define DEBUG_VIEW_DISTANCE 2u
+#define DEBUG_VIEW_NORMALS 3u
+#define DEBUG_VIEW_SHADOW 4u
+#define DEBUG_VIEW_AO 5u
+#define DEBUG_VIEW_DEPTH 6u
+#define DEBUG_VIEW_MATERIAL 7u
+#define DEBUG_VIEW_MISSES 8u
+
+// sdTerrain() for the scene's terrain, or without TERRAIN the flat ground at its base height, which is much cheaper to march.
+vec4 terrain(vec3 p, vec4 params, vec3 snow_color, vec3 grass_color) {
+    return TERRAIN ? sdTerrain(p, params, snow_color, grass_color) : vec4(p.y + params.y, grass_color);
 }
 
-vec2 hash2(in vec2 p) {
-    return hash3(vec3(p, 0)).xy;
-}
+// The scene: distance in .x, surface color in .yzw.
+// scene::codegen replaces everything between the BEGIN SCENE and END SCENE lines with a map() generated for one scene,
+// the code in between is the generic version that interprets a scene program from a storage buffer.
+// BEGIN SCENE
+// Scene program compiled from a scene file by `scene::Scene::compile`, in postfix order:
+// primitives push (distance, color) onto a stack, operators pop two entries and push the combination,
+// transforms and repetitions push the point they map into onto a second stack until the matching pop.
+// Keep the constants and the struct layout in sync with src/scene/mod.rs.
+#define OP_PRIMITIVE 0u
+#define OP_UNION 1u
+#define OP_SMOOTH_UNION 2u
+#define OP_SUBTRACTION 3u
+#define OP_INTERSECTION 4u
+
+#define SHAPE_SPHERE 0u
+#define SHAPE_CAPSULE 1u
+#define SHAPE_BOX 2u
+#define SHAPE_TORUS 3u
+#define SHAPE_PLANE 4u
+#define SHAPE_TERRAIN 5u
+
+#define OP_PUSH_TRANSFORM 5u
+#define OP_PUSH_REPEAT 6u
+#define OP_POP_DOMAIN 7u
+
+#define MAX_STACK 16
+#define MAX_DOMAINS 8
+
+struct Instruction {
+    mat4 inverse_transform;
+    vec4 params;
+    vec4 extra;
+    uint opcode;
+    uint shape;
+    uint material;
+    float scale;
+    float k;
+};
+
+layout(std430, set = 0, binding = 0) readonly buffer SceneBuffer {
+    Instruction instructions[];
+} scene;
+
+vec4 sdPrimitive(Instruction ins, vec3 p) {
+    vec3 q = (ins.inverse_transform * vec4(p, 1.0)).xyz;
+    vec3 color = scene_materials.materials[ins.material].color.rgb;
+    vec4 result = vec4(1000, color);
+
+    if (ins.shape == SHAPE_SPHERE) {
+        result.x = length(q) - ins.params.x;
+    } else if (ins.shape == SHAPE_CAPSULE) {
+        result.x = sdVerticalCapsule(q, ins.params.x, ins.params.y);
+    } else if (ins.shape == SHAPE_BOX) {
+        result.x = sdBox(q, ins.params.xyz);
+    } else if (ins.shape == SHAPE_TORUS) {
+        result.x = sdTorus(q, ins.params.xy);
+    } else if (ins.shape == SHAPE_PLANE) {
+        result.x = q.y;
+    } else if (ins.shape == SHAPE_TERRAIN) {
+        result = terrain(q, ins.params, ins.extra.rgb, color);
+    }
 
-vec2 hash(in vec2 p){
-    return hash2(p);
+    result.x *= ins.scale;
+    return result;
 }
 
-float hash1( vec2 p )
-{
-    p  = 50.0*fract( p*0.3183099 );
-    return fract( p.x*p.y*(p.x+p.y) );
+vec4 opCombine(uint opcode, vec4 a, vec4 b, float k) {
+    if (opcode == OP_SMOOTH_UNION) {
+        return opSmoothUnion(a, b, k);
+    } else if (opcode == OP_SUBTRACTION) {
+        return opSubtraction(a, b);
+    } else if (opcode == OP_INTERSECTION) {
+        return opIntersection(a, b);
+    }
+    return opU(a, b);
 }
 
-
-// https://iquilezles.org/articles/gradientnoise/
-// returns 3D value noise (in .x)  and its derivatives (in .yz)
-float noised( in vec2 x )
+vec4 map( in vec3 p )
 {
-    vec2 i = floor( x );
-    vec2 f = fract( x );
-
-    vec2 u = f*f*f*(f*(f*6.0-15.0)+10.0);
-    //vec2 du = 30.0*f*f*(f*(f-2.0)+1.0);
+    vec4 stack[MAX_STACK];
+    int top = 0;
+    // Point in the current frame in .xyz, accumulated scale in .w.
+    vec4 domains[MAX_DOMAINS + 1];
+    int domain = 0;
+    domains[0] = vec4(p, 1.0);
+
+    for (int i = 0; i < scene.instructions.length(); i++) {
+        Instruction ins = scene.instructions[i];
+        if (ins.opcode == OP_PRIMITIVE) {
+            stack[top] = sdPrimitive(ins, domains[domain].xyz);
+            stack[top].x *= domains[domain].w;
+            top++;
+        } else if (ins.opcode == OP_PUSH_TRANSFORM) {
+            vec3 q = (ins.inverse_transform * vec4(domains[domain].xyz, 1.0)).xyz;
+            domains[domain + 1] = vec4(q, domains[domain].w * ins.scale);
+            domain++;
+        } else if (ins.opcode == OP_PUSH_REPEAT) {
+            domains[domain + 1] = vec4(opRepeat(domains[domain].xyz, ins.params.xyz), domains[domain].w);
+            domain++;
+        } else if (ins.opcode == OP_POP_DOMAIN) {
+            domain--;
+        } else {
+            // Blend radii scale with the enclosing transforms, like the shapes they blend.
+            top--;
+            stack[top - 1] = opCombine(ins.opcode, stack[top - 1], stack[top], ins.k * domains[domain].w);
+        }
+    }
 
-    vec2 ga = hash( i + vec2(0.0,0.0) );
-    vec2 gb = hash( i + vec2(1.0,0.0) );
-    vec2 gc = hash( i + vec2(0.0,1.0) );
-    vec2 gd = hash( i + vec2(1.0,1.0) );
+    return stack[0];
+}
+// END SCENE
 
-    float va = dot( ga, f - vec2(0.0,0.0) );
-    float vb = dot( gb, f - vec2(1.0,0.0) );
-    float vc = dot( gc, f - vec2(0.0,1.0) );
-    float vd = dot( gd, f - vec2(1.0,1.0) );
+#include <lighting.glsl>
 
-    return va + u.x*(vb-va) + u.y*(vc-va) + u.x*u.y*(va-vb-vc+vd); //,   // value
-//                 ga + u.x*(gb-ga) + u.y*(gc-ga) + u.x*u.y*(ga-gb-gc+gd) +  // derivatives
-//                 du * (u.yx*(va-vb-vc+vd) + vec2(vb,vc) - va));
+float getCheckerboard(vec2 p) {
+    vec2 pattern = 1+sin(p);
+    return mod(int(pattern.x) + int(pattern.y), 2);
 }
 
-// https://www.shadertoy.com/view/4ttSWf
-float noise( in vec2 x )
-{
-    vec2 p = floor(x);
-    vec2 w = fract(x);
-    #if 1
-    vec2 u = w*w*w*(w*(w*6.0-15.0)+10.0);
-    #else
-    vec2 u = w*w*(3.0-2.0*w);
-    #endif
+const vec3 BACKGROUND = vec3(0.5);
+
+// The path tracer's environment, which its rays that hit nothing show. Directional lights are sun discs of
+// SUN_ANGULAR_RADIUS radians that only direct light sampling reaches, so the sky doesn't contain them.
+const vec3 SKY_ZENITH = vec3(0.10, 0.18, 0.35);
+const vec3 SKY_HORIZON = vec3(0.35, 0.38, 0.42);
+const vec3 SKY_GROUND = vec3(0.08, 0.07, 0.06);
+const float SUN_ANGULAR_RADIUS = 0.00465;
+
+// Materials only have a color, so the path tracer gives every surface the same dielectric: the color as a Lambertian base
+// under a glossy coat that reflects 4% head-on, like the highlight of shade().
+const float COAT_F0 = 0.04;
+const float COAT_ALPHA = 0.09;
+
+// Colors of the misses view.
+const vec3 MISS_STEPS_COLOR = vec3(0.9, 0.05, 0.05);
+const vec3 MISS_FAR_COLOR = vec3(0.05, 0.1, 0.6);
+
+// `steps` is the number of steps taken and `travelled` the distance covered. On a miss a `travelled` short of the far
+// plane means the step limit stopped the ray.
+bool march(in vec3 ray, vec3 start, out vec3 hit, out vec3 color, out int steps, out float travelled) {
+    float dist = 0;
+    int i = 0;
+    for (; i < int(frame.max_steps) && dist < frame.far_plane; i++) {
+        vec3 pos = start + ray * dist;
+        vec4 result = map(pos);
+        if (result.x < frame.hit_epsilon * dist) {
+            hit = pos;
+            color = result.yzw;
+            steps = i + 1;
+            travelled = dist;
+            return true;
+        }
+        dist += result.x;
+    }
+    steps = i;
+    travelled = dist;
+    return false;
+}
 
-    float a = hash1(p+vec2(0,0));
-    float b = hash1(p+vec2(1,0));
-    float c = hash1(p+vec2(0,1));
-    float d = hash1(p+vec2(1,1));
+// Direction from `point` towards `light`, the light's falloff and how far away occluders still cast shadows.
+vec3 toLight(Light light, vec3 point, out float attenuation, out float shadow_distance) {
+    attenuation = 1.0;
+    shadow_distance = 10.0;
+    if (light.position.w == 0.0) {
+        return light.position.xyz;
+    }
+    vec3 to_light = light.position.xyz - point;
+    float light_distance = length(to_light);
+    attenuation = 1.0 / (light_distance * light_distance);
+    shadow_distance = min(shadow_distance, light_distance);
+    return to_light / light_distance;
+}
 
-    return -1.0+2.0*(a + (b-a)*u.x + (c-a)*u.y + (a - b - c + d)*u.x*u.y);
+// The shadow and AO terms before shading clamps them, 1 if the variant leaves the feature out.
+float shadowTerm(vec3 point, vec3 to_light, float shadow_distance) {
+    return SHADOWS ? calculateShadow(point, to_light, shadow_distance, int(frame.shadow_steps), frame.shadow_softness) : 1.0;
 }
 
-float rand(vec2 co){
-    return fract(sin(dot(co, vec2(12.9898, 78.233))) * 43758.5453);
+float aoTerm(vec3 point, vec3 normal) {
+    return AO ? calculateAO(point, normal, int(frame.ao_taps)) : 1.0;
 }
 
-float sphere_sdf(vec3 observer, vec3 target, float r) {
-    return length(observer - target) - r;
+vec3 shade(vec3 hit, vec3 color, vec3 ray, vec3 origin) {
+    vec3 normal = calcNormal(hit, frame.normal_epsilon);
+    float ao = clamp(aoTerm(hit, normal), 0.1, 1.0);
+
+    vec3 lit = vec3(0.0);
+    for (int i = 0; i < scene_lights.lights.length(); i++) {
+        Light light = scene_lights.lights[i];
+        float attenuation, shadow_distance;
+        vec3 to_light = toLight(light, hit, attenuation, shadow_distance);
+
+        float diffuse = clamp(dot(normal, to_light), 0.0, 1.0);
+        float specular = pow(clamp(dot(normal, to_light-ray), 0.0, 1.0), 16);
+        float shadow = clamp(shadowTerm(hit, to_light, shadow_distance), 0.2, 1.0);
+        vec3 radiance = light.color.rgb * attenuation;
+        lit += 0.7 * color * radiance * diffuse * shadow * ao;
+        lit += 0.04 * radiance * specular;
+    }
+
+    float ambient_light = 0.5;
+    vec3 shaded = lit + 0.2 * color * ambient_light;
+    if (FOG) {
+        // Fades into the background from about 70 units on.
+        float distance = length(hit - origin);
+        float fog = min(1, 5000.0 / (distance * distance));
+        shaded = mix(BACKGROUND, shaded, fog);
+    }
+    return shaded;
 }
 
-float sdVerticalCapsule( vec3 p, float h, float r )
-{
-    p.y -= clamp( p.y, 0.0, h );
-    return length( p ) - r;
+vec3 sky(vec3 direction) {
+    if (direction.y < 0.0) {
+        return mix(SKY_HORIZON, SKY_GROUND, min(-direction.y * 10.0, 1.0));
+    }
+    return mix(SKY_HORIZON, SKY_ZENITH, sqrt(direction.y));
 }
 
-float smin( float a, float b, float k )
-{
-    float h = max( k-abs(a-b), 0.0 )/k;
-    return min( a, b ) - h*h*k*(1.0/4.0);
+// Whether nothing blocks the way from `point` along `direction` for `max_distance`: the path tracer's shadow rays, which
+// unlike calculateShadow() give hard shadows, soft only where the light is large.
+bool visible(vec3 point, vec3 direction, float max_distance) {
+    float t = 0.0;
+    max_distance = min(max_distance, frame.far_plane);
+    for (int i = 0; i < int(frame.max_steps) && t < max_distance; i++) {
+        float d = map(point + direction * t).x;
+        if (d < frame.hit_epsilon * t) {
+            return false;
+        }
+        t += d;
+    }
+    return true;
 }
 
-vec4 opU( vec4 d1, vec4 d2 )
-{
-    return (d1.x<d2.x) ? d1 : d2;
+vec3 surfaceBrdf(vec3 albedo, vec3 normal, vec3 to_eye, vec3 to_light) {
+    float n_dot_l = dot(normal, to_light);
+    float n_dot_v = max(dot(normal, to_eye), 1e-4);
+    if (n_dot_l <= 0.0) {
+        return vec3(0.0);
+    }
+    vec3 h = normalize(to_eye + to_light);
+    float fresnel = fresnelSchlick(COAT_F0, dot(to_eye, h));
+    float specular = ggxD(max(dot(normal, h), 0.0), COAT_ALPHA) * smithVisibility(n_dot_l, n_dot_v, COAT_ALPHA) * fresnel;
+    return (1.0 - fresnel) * albedo / PI + specular;
 }
 
-vec4 sdDong(vec3 p) {
-    float d = 1000;
+// Chance of sampling the coat instead of the base, higher at grazing angles where the coat reflects more.
+float specularProbability(vec3 normal, vec3 to_eye) {
+    return mix(0.1, 0.9, fresnelSchlick(COAT_F0, dot(normal, to_eye)));
+}
 
-    d = smin(d, sphere_sdf(p, vec3(-0.3, 0.5, 0), 0.5), 0.1);
-    d = smin(d, sphere_sdf(p, vec3(0.3, 0.5, 0), 0.5), 0.1);
-    d = smin(d, sdVerticalCapsule(p - vec3(0, 1, 0), 2, 0.3), 0.1);
+// Next direction of a path, from the coat's GGX lobe or the base's cosine lobe; surfacePdf() is the pdf of both together.
+vec3 sampleSurface(vec3 normal, vec3 to_eye, float specular_probability, inout uint rng) {
+    if (random(rng) < specular_probability) {
+        return reflect(-to_eye, sampleGgxHalfVector(normal, COAT_ALPHA, random2(rng)));
+    }
+    return sampleCosineHemisphere(normal, random2(rng));
+}
 
-    return vec4(d, vec3(0.9, 0.3,0.35));
+float surfacePdf(vec3 normal, vec3 to_eye, vec3 direction, float specular_probability) {
+    vec3 h = normalize(to_eye + direction);
+    float specular = ggxPdf(max(dot(normal, h), 0.0), max(dot(to_eye, h), 0.0), COAT_ALPHA);
+    return mix(cosineHemispherePdf(dot(normal, direction)), specular, specular_probability);
 }
 
-vec4 sdFloor(vec3 p) {
-    float noise_val = noised(p.xz*0.01).x;
-    float height_factor = (noise_val+1)/2;
-    float d = p.y - (noise_val * terrain_max_height - terrain_offset);
-    vec3 color;// = min(5.5 * height_factor, 1.0) * vec3(0.9, 0.95, 0.9) + (1 - height_factor) * vec3(0.1, 0.5, 0.15);
-    if (height_factor < 0.5) {
-        color = vec3(0.1, 0.5, 0.15);
-    } else {
-        height_factor = (height_factor - 0.5) * 2;
-        color = vec3(0.9, 0.95, 0.9) * height_factor + vec3(0.1, 0.5, 0.15) * 1 - height_factor;
+// Light arriving straight from the scene's lights, one sample per light. A light's color is what a white surface facing
+// it reflects, as in shade(), so its irradiance is PI times that.
+vec3 directLight(vec3 point, vec3 normal, vec3 to_eye, vec3 albedo, inout uint rng) {
+    vec3 lit = vec3(0.0);
+    for (int i = 0; i < scene_lights.lights.length(); i++) {
+        Light light = scene_lights.lights[i];
+        vec3 to_light;
+        float light_distance = frame.far_plane;
+        float attenuation = 1.0;
+        if (light.position.w == 0.0) {
+            to_light = sampleCone(light.position.xyz, cos(SUN_ANGULAR_RADIUS), random2(rng));
+        } else {
+            to_light = light.position.xyz - point;
+            light_distance = length(to_light);
+            to_light /= light_distance;
+            attenuation = 1.0 / (light_distance * light_distance);
+        }
+        float n_dot_l = dot(normal, to_light);
+        if (n_dot_l > 0.0 && visible(point, to_light, light_distance)) {
+            lit += surfaceBrdf(albedo, normal, to_eye, to_light) * n_dot_l * PI * light.color.rgb * attenuation;
+        }
     }
-    return vec4(d, color);
+    return lit;
 }
 
-vec4 map( in vec3 p )
-{
-    vec4 result = vec4(1000);
+// Radiance along the unit direction `ray` from its first hit at `hit` with the surface color `albedo`: direct light at
+// every vertex, up to `frame.bounces` bounces importance sampled from the surface, and the sky where paths escape.
+// `travelled` is what march() reported, in whatever units its ray had, so `frame.hit_epsilon * travelled` is the hit
+// threshold that march applied. The frame index seeds the random numbers, so every accumulated frame adds a new sample.
+vec3 pathTrace(vec3 ray, vec3 hit, vec3 albedo, float travelled) {
+    uint rng = randomSeed(uvec2(gl_FragCoord.xy), frame.frame_index);
+    vec3 radiance = vec3(0.0);
+    vec3 throughput = vec3(1.0);
+    for (uint bounce = 0u; ; bounce++) {
+        vec3 to_eye = -ray;
+        vec3 normal = calcNormal(hit, frame.normal_epsilon);
+        // Where the march overshot into the surface the normal can face away from the ray.
+        if (dot(normal, to_eye) < 0.0) {
+            normal = -normal;
+        }
+        // Off the surface, so the rays leaving it don't hit it right away.
+        vec3 point = hit + normal * 4.0 * max(frame.normal_epsilon, frame.hit_epsilon * travelled);
+        radiance += throughput * directLight(point, normal, to_eye, albedo, rng);
+        if (bounce >= frame.bounces) {
+            break;
+        }
 
-    result = opU(result, sdFloor(p));
-    result = opU(result, sdDong(p));
+        float specular_probability = specularProbability(normal, to_eye);
+        vec3 next = sampleSurface(normal, to_eye, specular_probability, rng);
+        float n_dot_l = dot(normal, next);
+        float pdf = surfacePdf(normal, to_eye, next, specular_probability);
+        if (n_dot_l <= 0.0 || pdf <= 0.0) {
+            break;
+        }
+        throughput *= surfaceBrdf(albedo, normal, to_eye, next) * n_dot_l / pdf;
+
+        // Russian roulette: paths that carry little light end early, the ones that go on carry more to make up for it.
+        if (bounce > 0u) {
+            float survival = clamp(max(throughput.r, max(throughput.g, throughput.b)), 0.05, 0.95);
+            if (random(rng) >= survival) {
+                break;
+            }
+            throughput /= survival;
+        }
 
-    return result;
+        ray = next;
+        int steps;
+        if (!march(ray, point, hit, albedo, steps, travelled)) {
+            radiance += throughput * sky(ray);
+            break;
+        }
+    }
+    return radiance;
 }
 
-float f( in vec3 p )
-{
-    return map(p).x;
+// The scene's material whose color is closest to a surface color. map() returns colors, not material indices, so
+// blended surfaces and terrain snow show the material they are closest to.
+uint nearestMaterial(vec3 color) {
+    uint nearest = 0u;
+    float nearest_distance = 1e30;
+    for (int i = 0; i < scene_materials.materials.length(); i++) {
+        vec3 difference = scene_materials.materials[i].color.rgb - color;
+        float distance = dot(difference, difference);
+        if (distance < nearest_distance) {
+            nearest = uint(i);
+            nearest_distance = distance;
+        }
+    }
+    return nearest;
 }
 
-//vec3 calcNormal( in vec3 p ) // for function f(p)
-//{
-//    const float eps = 0.00001; // or some other value
-//    const vec2 h = vec2(eps,0);
-//    return normalize( vec3(f(p+h.xyy) - f(p-h.xyy),
-//                           f(p+h.yxy) - f(p-h.yxy),
-//                           f(p+h.yyx) - f(p-h.yyx) ) );
-//}
-
-vec3 calcNormal( in vec3 p ) // for function f(p)
-{
-    const float h = 0.0001; // replace by an appropriate value
-    const vec2 k = vec2(1,-1);
-    return normalize( k.xyy*f( p + k.xyy*h ) +
-    k.yyx*f( p + k.yyx*h ) +
-    k.yxy*f( p + k.yxy*h ) +
-    k.xxx*f( p + k.xxx*h ) );
+// Surface normals of the six axis directions, in the order the legend shows them: +X, -X, +Y, -Y, +Z, -Z.
+vec3 axisNormal(uint index) {
+    vec3 axis = vec3(equal(uvec3(index / 2u), uvec3(0u, 1u, 2u)));
+    return (index % 2u == 0u ? axis : -axis) * 0.5 + 0.5;
 }
 
-float getCheckerboard(vec2 p) {
-    vec2 pattern = 1+sin(p);
-    return mod(int(pattern.x) + int(pattern.y), 2);
-}
+vec3 debugView(bool has_hit, vec3 hit, vec3 color, vec3 ray, int steps, float travelled) {
+    if (DEBUG_VIEW == DEBUG_VIEW_STEPS) {
+        return heatmap(float(steps) / float(frame.max_steps));
+    }
+    if (DEBUG_VIEW == DEBUG_VIEW_MISSES && !has_hit) {
+        return travelled < frame.far_plane ? MISS_STEPS_COLOR : MISS_FAR_COLOR;
+    }
+    if (!has_hit) {
+        // A dark checkerboard where there is no surface to show a value for.
+        return vec3(0.01 + 0.03 * getCheckerboard(gl_FragCoord.xy / 8.0));
+    }
 
-float calculateShadow(vec3 point, vec3 light) {
-    float t = 0.02;
-    float result = 1.0;
-    for (int i = 0; i < 25, t < 10; i++) {
-        float d = f(point + light * t);
-        result = min(result, 16.0 * d / t);
-        if (result < 0.004) {
-            break;
+    vec3 normal = calcNormal(hit, frame.normal_epsilon);
+    if (DEBUG_VIEW == DEBUG_VIEW_DISTANCE) {
+        // map() at the hit relative to the threshold that made it a hit; below 0 the ray overshot into the surface.
+        float threshold = max(frame.hit_epsilon * travelled, 1e-20);
+        return heatmap(0.5 + 0.5 * map(hit).x / threshold);
+    } else if (DEBUG_VIEW == DEBUG_VIEW_NORMALS) {
+        return normal * 0.5 + 0.5;
+    } else if (DEBUG_VIEW == DEBUG_VIEW_SHADOW) {
+        float shadow = 0.0;
+        for (int i = 0; i < scene_lights.lights.length(); i++) {
+            float attenuation, shadow_distance;
+            vec3 to_light = toLight(scene_lights.lights[i], hit, attenuation, shadow_distance);
+            shadow += clamp(shadowTerm(hit, to_light, shadow_distance), 0.0, 1.0);
         }
-        t += d;
+        return vec3(shadow / max(float(scene_lights.lights.length()), 1.0));
+    } else if (DEBUG_VIEW == DEBUG_VIEW_AO) {
+        return vec3(clamp(aoTerm(hit, normal), 0.0, 1.0));
+    } else if (DEBUG_VIEW == DEBUG_VIEW_DEPTH) {
+        return heatmap(log2(1.0 + travelled) / log2(1.0 + frame.far_plane));
+    } else if (DEBUG_VIEW == DEBUG_VIEW_MATERIAL) {
+        return categoryColor(nearestMaterial(color));
     }
-    return result;
+    // DEBUG_VIEW_MISSES: hits in grey, lit from the camera so the shapes stay recognizable.
+    return vec3(0.2 + 0.4 * max(dot(normal, -ray), 0.0));
 }
 
-float calculateAO(vec3 point, vec3 normal) {
-    float start = 0.01;
-    float step = 0.03;
-    float ao = 0;
-    for (int i = 0; i < 5; i++) {
-        ao += 1/exp2(i) * float(i) * step - f(point + normal * float(i) * (start + step));
+// The bar under the debug views: the scale with ticks at the quarters, or one segment per category.
+// src/render_core/debug_view.rs describes it in the window title.
+vec3 legend(vec2 coord) {
+    float x = legendPosition(coord, frame.resolution);
+    if (DEBUG_VIEW == DEBUG_VIEW_MATERIAL) {
+        uint count = max(uint(scene_materials.materials.length()), 1u);
+        uint index = min(uint(x * float(count)), count - 1u);
+        return legendMarks(categoryColor(index), coord, frame.resolution, float(count));
+    } else if (DEBUG_VIEW == DEBUG_VIEW_MISSES) {
+        const vec3 colors[3] = vec3[3](vec3(0.4), MISS_STEPS_COLOR, MISS_FAR_COLOR);
+        return legendMarks(colors[min(uint(x * 3.0), 2u)], coord, frame.resolution, 3.0);
+    } else if (DEBUG_VIEW == DEBUG_VIEW_NORMALS) {
+        return legendMarks(axisNormal(min(uint(x * 6.0), 5u)), coord, frame.resolution, 6.0);
+    } else if (DEBUG_VIEW == DEBUG_VIEW_SHADOW || DEBUG_VIEW == DEBUG_VIEW_AO) {
+        return legendMarks(vec3(x), coord, frame.resolution, 4.0);
     }
-    return 1 - 5*ao;
+    return legendMarks(heatmap(x), coord, frame.resolution, 4.0);
 }
 
-bool march(in vec3 ray, vec3 start, out vec3 hit, out vec3 color) {
-    // objects
-    float d = 0, dist = 0;
-    for (int i = 0; i < 256, dist < 1000; i++) {
-        vec3 pos = start + ray * dist;
-        vec4 result = map(pos);
-        d = result.x;
-        if (d < 0.0001 * dist) {
-            hit = pos;
-            color = result.yzw;
-            return true;
-        }
-        dist += d;
+void main() {
+    if (DEBUG_VIEW != DEBUG_VIEW_OFF && onLegend(gl_FragCoord.xy, frame.resolution)) {
+        f_color = outputColor(vec4(legend(gl_FragCoord.xy), 1.0));
+        return;
     }
-    return false;
-}
 
-void main() {
-    float camera_fov = 90;
-    vec2 aspectRatio = vec2(push.resolution.x / push.resolution.y, 1.0);
-    vec2 uv = (gl_FragCoord.xy / push.resolution.xy) * 2.0 - 1.0;
+    float camera_fov = frame.fov;
+    vec2 aspectRatio = vec2(frame.resolution.x / frame.resolution.y, 1.0);
+    vec2 uv = ((gl_FragCoord.xy + frame.jitter) / frame.resolution.xy) * 2.0 - 1.0;
     uv *= aspectRatio;
     uv.y = -uv.y;
 
-    vec3 origin = push.camera_pos;
-    vec3 ray = (push.view * normalize(vec4(vec3(uv, 1.0 / tan(radians(camera_fov) / 2.0)), 1.0))).xyz;
+    vec3 origin = frame.camera_pos;
+    vec3 ray = (frame.view * normalize(vec4(vec3(uv, 1.0 / tan(radians(camera_fov) / 2.0)), 1.0))).xyz;
 
     vec3 hit;
     vec3 color;
-    bool has_hit = march(ray, origin, hit, color);
-
-    if (has_hit) {
-        vec3 normal = calcNormal(hit);
-        vec3 light = normalize(vec3(-1.0, 1.0, -1));
-        float diffuse = clamp(dot(normal, light), 0.0, 1.0);
-        float specular = pow(clamp(dot(normal, light-ray), 0.0, 1.0), 16);
-        float shadow = clamp(calculateShadow(hit, light), 0.2, 1.0);
-        float ao = clamp(calculateAO(hit, normal), 0.1, 1.0);
-
-        float distance = length(hit - origin);
-        float fog = min(1, 5000.0 / (distance * distance));
-
-        float ambient_light = 0.5;
-        f_color = vec4(
-            0.7 * color * diffuse * shadow * ao
-            + 0.04 * vec3(1.0, 1.0, 1.0) * specular
-            + 0.2 * color * ambient_light
-            //+ vec3(0.5, 0.5, 0.5)*(1-fog)
-        , 1.0);
+    int steps;
+    float travelled;
+    bool has_hit = march(ray, origin, hit, color, steps, travelled);
+
+    if (DEBUG_VIEW != DEBUG_VIEW_OFF) {
+        f_color = outputColor(vec4(debugView(has_hit, hit, color, ray, steps, travelled), 1.0));
+    } else if (PATH_TRACING) {
+        // The camera ray isn't unit length, which the shading above tolerates; the BRDF, pdfs and sky need a direction.
+        vec3 direction = normalize(ray);
+        f_color = outputColor(vec4(has_hit ? pathTrace(direction, hit, color, travelled) : sky(direction), 1.0));
+    } else if (has_hit) {
+        f_color = outputColor(vec4(shade(hit, color, ray, origin), 1.0));
     } else {
-        f_color = vec4(0.5, 0.5, 0.5, 1.0);
+        f_color = outputColor(vec4(BACKGROUND, 1.0));
     }
-}
\ No newline at end of file
+}
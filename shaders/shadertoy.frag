@@ -0,0 +1,63 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// Runs a Shadertoy pass on the full-screen quad. src/shadertoy replaces the section marked BEGIN SHADERTOY ... END
+// SHADERTOY below with the pass's source, preceded by the project's common code, and defines SHADERTOY_IMAGE for the
+// Image pass, which is drawn to the screen; the buffer passes write float targets that other passes read as channels.
+// The mainImage() in between only makes this file compile on its own.
+
+layout(location = 0) out vec4 f_color;
+
+#include <color.glsl>
+#include <frame.glsl>
+
+// Channels without an input read a black texel. Textures and buffers are stored bottom row first, like OpenGL does,
+// so texture(iChannel0, fragCoord / iResolution.xy) reads what Shadertoy would.
+layout(set = 0, binding = 0) uniform sampler2D iChannel0;
+layout(set = 0, binding = 1) uniform sampler2D iChannel1;
+layout(set = 0, binding = 2) uniform sampler2D iChannel2;
+layout(set = 0, binding = 3) uniform sampler2D iChannel3;
+
+// Shadertoy's origin is the bottom left corner; frame.mouse is measured from the top left.
+vec4 shadertoyMouse() {
+    vec2 position = vec2(frame.mouse.x, frame.resolution.y - frame.mouse.y);
+    vec2 click = vec2(frame.mouse.z, frame.resolution.y - frame.mouse.w);
+    // The click position is negative while the left button is up.
+    return vec4(position, (frame.mouse_buttons & 1u) != 0u ? click : -click);
+}
+
+#define iResolution vec3(frame.resolution, 1.0)
+#define iTime frame.time
+#define iTimeDelta frame.delta_time
+#define iFrame int(frame.frame_index)
+#define iMouse shadertoyMouse()
+
+// Filled in by main() before mainImage() runs.
+vec3 iChannelResolution[4];
+
+// BEGIN SHADERTOY
+void mainImage( out vec4 fragColor, in vec2 fragCoord )
+{
+    vec2 uv = fragCoord / iResolution.xy;
+    fragColor = vec4(uv, 0.5 + 0.5 * sin(iTime), 1.0);
+}
+// END SHADERTOY
+
+void main() {
+    iChannelResolution[0] = vec3(textureSize(iChannel0, 0), 1.0);
+    iChannelResolution[1] = vec3(textureSize(iChannel1, 0), 1.0);
+    iChannelResolution[2] = vec3(textureSize(iChannel2, 0), 1.0);
+    iChannelResolution[3] = vec3(textureSize(iChannel3, 0), 1.0);
+
+    vec4 color = vec4(0.0, 0.0, 0.0, 1.0);
+#ifdef SHADERTOY_IMAGE
+    // Flipped, so the bottom of the image ends up at the bottom of the window.
+    mainImage(color, vec2(gl_FragCoord.x, frame.resolution.y - gl_FragCoord.y));
+    // Shadertoy output is display encoded and opaque.
+    f_color = outputColor(vec4(srgbToLinear(color.rgb), 1.0));
+#else
+    // Row 0 is the bottom row, see the channels above; buffers keep the values as they are, alpha included.
+    mainImage(color, gl_FragCoord.xy);
+    f_color = color;
+#endif
+}
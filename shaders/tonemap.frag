@@ -0,0 +1,90 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// Maps the HDR frame to the display range; the last pass of the viewer. Keep the operators in sync with
+// src/render_core/tonemap.rs, which applies the same ones to offline renders.
+
+layout(push_constant) uniform PushConstants {
+    // Linear scale, 2^EV; with auto exposure on top of the one that maps the average to middle grey.
+    float exposure;
+    uint operator;
+    uint auto_exposure;
+    // Mip level of log_luminance that holds the 1x1 average.
+    float average_lod;
+} push;
+
+layout(set = 0, binding = 0) uniform sampler2D hdr;
+// Written by luminance.frag: the mean log luminance of the frame scaled by .g, the share of texels it covers.
+layout(set = 0, binding = 1) uniform sampler2D log_luminance;
+
+layout(location = 0) out vec4 f_color;
+
+#include <color.glsl>
+
+#define OPERATOR_REINHARD 0u
+#define OPERATOR_ACES 1u
+#define OPERATOR_AGX 2u
+// Debug views: their colors are values, so they are shown as they are.
+#define OPERATOR_NONE 3u
+
+#define MIDDLE_GREY 0.18
+
+vec3 reinhard( vec3 c )
+{
+    return c / (1.0 + c);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+vec3 aces( vec3 c )
+{
+    return clamp((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14), 0.0, 1.0);
+}
+
+// AgX with the default look, after Benjamin Wrensch's minimal version. The curve is fitted in display encoding,
+// so the result is decoded back to linear for outputColor().
+vec3 agx( vec3 c )
+{
+    const mat3 inset = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+    const mat3 outset = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+
+    vec3 x = clamp(log2(max(inset * c, vec3(1e-10))), min_ev, max_ev);
+    x = (x - min_ev) / (max_ev - min_ev);
+
+    vec3 x2 = x * x;
+    vec3 x4 = x2 * x2;
+    x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+
+    return pow(max(outset * x, vec3(0.0)), vec3(2.2));
+}
+
+void main() {
+    vec3 color = texelFetch(hdr, ivec2(gl_FragCoord.xy), 0).rgb;
+    if (push.operator == OPERATOR_NONE) {
+        f_color = outputColor(vec4(color, 1.0));
+        return;
+    }
+
+    float exposure = push.exposure;
+    if (push.auto_exposure != 0u) {
+        vec2 average = textureLod(log_luminance, vec2(0.5), push.average_lod).rg;
+        exposure *= MIDDLE_GREY / exp(average.r / max(average.g, 1e-6));
+    }
+    color *= exposure;
+
+    if (push.operator == OPERATOR_AGX) {
+        color = agx(color);
+    } else if (push.operator == OPERATOR_ACES) {
+        color = aces(color);
+    } else {
+        color = reinhard(color);
+    }
+    f_color = outputColor(vec4(color, 1.0));
+}
@@ -0,0 +1,11 @@
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+    vec2 uv = fragCoord / iResolution.xy;
+    vec4 previous = texture(iChannel0, uv);
+
+    float brush = 0.0;
+    if (iMouse.z > 0.0) {
+        brush = smoothstep(12.0, 8.0, length(fragCoord - iMouse.xy));
+    }
+    vec3 color = mix(previous.rgb * exp(-iTimeDelta), palette(0.1 * iTime), brush);
+    fragColor = vec4(color, 1.0);
+}
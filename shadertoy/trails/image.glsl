@@ -0,0 +1,6 @@
+void mainImage(out vec4 fragColor, in vec2 fragCoord) {
+    vec2 uv = fragCoord / iResolution.xy;
+    vec3 strokes = texture(iChannel0, uv).rgb;
+    vec3 background = mix(vec3(0.05, 0.05, 0.08), vec3(0.15, 0.12, 0.2), uv.y);
+    fragColor = vec4(background + strokes, 1.0);
+}
@@ -0,0 +1,130 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::time::Instant;
+use nalgebra::Vector3;
+
+/// Source of `delta_time`: wall-clock time for the viewer, fixed steps for deterministic offline renders.
+pub enum Clock {
+    Wall { last: Instant },
+    Fixed { step: f32 },
+}
+
+impl Clock {
+    pub fn wall() -> Self {
+        Clock::Wall { last: Instant::now() }
+    }
+
+    pub fn fixed(fps: f32) -> Self {
+        Clock::Fixed { step: 1.0 / fps }
+    }
+
+    /// Seconds since the previous tick.
+    pub fn tick(&mut self) -> f32 {
+        match self {
+            Clock::Wall { last } => {
+                let now = Instant::now();
+                let delta = now - *last;
+                *last = now;
+                delta.as_secs_f32()
+            }
+            Clock::Fixed { step } => *step,
+        }
+    }
+}
+
+/// Playback speeds `Timeline::slower` and `Timeline::faster` step between, halving and doubling.
+const MIN_SPEED: f32 = 1.0 / 16.0;
+const MAX_SPEED: f32 = 4.0;
+
+/// Scene time as the shaders see it: the viewer's clock drives it, but it can be paused, slowed down, sped up and scrubbed,
+/// while the camera keeps moving in real time.
+pub struct Timeline {
+    pub time: f32,
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Timeline {
+    pub fn new(start: f32) -> Self {
+        Self { time: start, speed: 1.0, paused: false }
+    }
+
+    /// Moves the scene time on by `delta_time` seconds of wall-clock time and returns how far it moved.
+    pub fn advance(&mut self, delta_time: f32) -> f32 {
+        if self.paused {
+            return 0.0;
+        }
+        let delta = delta_time * self.speed;
+        self.time += delta;
+        delta
+    }
+
+    /// Jumps `seconds` forward, or back for negative values, without going before 0; works while paused.
+    pub fn scrub(&mut self, seconds: f32) {
+        self.time = (self.time + seconds).max(0.0);
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed / 2.0).max(MIN_SPEED);
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed * 2.0).min(MAX_SPEED);
+    }
+}
+
+impl fmt::Display for Timeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2} s at {}x", self.time, self.speed)?;
+        if self.paused {
+            write!(f, ", paused")?;
+        }
+        Ok(())
+    }
+}
+
+/// Camera position and the `camera_front` vector fed to the view matrix. The camera looks down `-front`.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraPose {
+    pub position: Vector3<f32>,
+    pub front: Vector3<f32>,
+}
+
+pub enum CameraPath {
+    Static(CameraPose),
+    /// One full orbit around `center`, keeping the start pose's distance and height.
+    Turntable { center: Vector3<f32>, start: Vector3<f32> },
+    /// Straight line from `start.position` to `end` with a fixed orientation.
+    Flythrough { start: CameraPose, end: Vector3<f32> },
+}
+
+impl CameraPath {
+    /// `progress` runs from 0 at the first frame to 1 at the end of the animation.
+    pub fn pose(&self, progress: f32) -> CameraPose {
+        match self {
+            CameraPath::Static(pose) => *pose,
+            CameraPath::Turntable { center, start } => {
+                let offset = start - center;
+                let radius = offset.xz().norm();
+                let angle = offset.z.atan2(offset.x) + progress * 2.0 * PI;
+                let position = Vector3::new(
+                    center.x + radius * angle.cos(),
+                    start.y,
+                    center.z + radius * angle.sin(),
+                );
+                CameraPose {
+                    position,
+                    front: (position - center).normalize(),
+                }
+            }
+            CameraPath::Flythrough { start, end } => CameraPose {
+                position: start.position.lerp(end, progress),
+                front: start.front,
+            },
+        }
+    }
+}
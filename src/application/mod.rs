@@ -1,42 +1,102 @@
 use std::collections::HashSet;
-use std::env::join_paths;
-use std::f32::consts::PI;
-use std::ops::{Add, RangeInclusive};
+use std::fmt;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{SystemTime, UNIX_EPOCH};
 use event::WindowEvent;
-use nalgebra::{Matrix, Matrix4, Rotation3, UnitQuaternion, Vector, Vector3};
-use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use nalgebra::{Matrix4, Vector3};
+use vulkano::pipeline::graphics::viewport::Viewport;
 use vulkano::{swapchain, Validated};
-use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage};
-use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
-use vulkano::command_buffer::{CommandBufferUsage, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents, SubpassEndInfo};
-use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
-use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
-use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
-use vulkano::pipeline::graphics::rasterization::RasterizationState;
-use vulkano::pipeline::graphics::vertex_input::{Vertex, VertexDefinition};
-use vulkano::pipeline::{DynamicState, GraphicsPipeline, PipelineLayout, PipelineShaderStageCreateInfo};
-use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState};
-use vulkano::pipeline::graphics::multisample::MultisampleState;
-use vulkano::pipeline::layout::{PipelineLayoutCreateInfo, PushConstantRange};
-use vulkano::render_pass::Subpass;
-use vulkano::shader::{EntryPoint, ShaderStages};
-use vulkano::swapchain::{SwapchainCreateInfo, SwapchainPresentInfo};
-use winit::event::{DeviceEvent, ElementState, Event, MouseButton, RawKeyEvent};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::device::DeviceExtensions;
+use vulkano::format::Format;
+use vulkano::image::{Image, ImageUsage};
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::swapchain::{PresentMode, SwapchainCreateInfo, SwapchainPresentInfo};
+use winit::event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, RawKeyEvent};
 use winit::window::{CursorGrabMode, Window};
-use crate::{render_core, window};
-use crate::render_core::vulkano_core::window_size_dependent_setup;
+use crate::{capture, reference, render_core, window};
+use crate::animation::{CameraPath, CameraPose, Clock, Timeline};
+use crate::capture::{CaptureError, ImageFileFormat};
+use crate::capture::sequence::SequenceWriter;
+use crate::cli::Options;
+use crate::render_core::accumulation;
+use crate::render_core::accumulation::Accumulation;
+use crate::render_core::exposure::ExposureAverage;
+use crate::render_core::frames::FrameRing;
+use crate::render_core::headless::HeadlessRenderer;
+use crate::render_core::hot_reload::ShaderWatcher;
+use crate::render_core::pipeline::{Constants, Constants2d, DEFAULT_FOV, MAX_FOV, MIN_FOV, MOUSE_LEFT, MOUSE_MIDDLE, MOUSE_RIGHT};
+use crate::render_core::pipeline_cache;
+use crate::render_core::quality::Quality;
+use crate::render_core::shaders;
+use crate::render_core::shadertoy::ShadertoyRenderer;
+use crate::render_core::tonemap;
+use crate::render_core::tonemap::{HdrTarget, ToneMapping};
+use crate::render_core::debug_view::DebugView;
+use crate::render_core::variants::{Feature, ScenePipelines, Variant};
+use crate::render_core::vulkano_core;
+use crate::render_core::vulkano_core::{window_size_dependent_setup, InitError};
+use crate::scene::shapes_2d;
+use crate::scene::{CompiledScene, Scene};
+use crate::shadertoy::{Channel, Project, ShadertoyError};
 use vulkano::sync::GpuFuture;
 use winit::event;
 use winit::keyboard::{KeyCode, PhysicalKey};
 
-pub fn run() {
-    let (window, event_loop) = window::init();
-    let (instance, surface) = render_core::vulkano_core::init(&event_loop, window.clone());
-    let (device, queue) = render_core::vulkano_core::init_device(instance);
-    let (mut swapchain, images) = render_core::vulkano_core::init_swapchain(device.clone(), surface.clone());
-    let render_pass = render_core::vulkano_core::init_render_pass(device.clone(), swapchain.clone());
+/// What the viewer shows; `Tab` cycles through the modes, Shadertoy only if a project was given.
+#[derive(Clone, Copy, PartialEq)]
+enum ViewMode {
+    Scene,
+    Sdf2d,
+    Shadertoy,
+}
+
+impl ViewMode {
+    fn next(self, shadertoy: bool) -> Self {
+        match self {
+            ViewMode::Scene => ViewMode::Sdf2d,
+            ViewMode::Sdf2d if shadertoy => ViewMode::Shadertoy,
+            ViewMode::Sdf2d | ViewMode::Shadertoy => ViewMode::Scene,
+        }
+    }
+}
+
+impl fmt::Display for ViewMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ViewMode::Scene => write!(f, "3D"),
+            ViewMode::Sdf2d => write!(f, "2D"),
+            ViewMode::Shadertoy => write!(f, "Shadertoy"),
+        }
+    }
+}
+
+pub fn run(options: &Options) {
+    let scene = load_scene(options);
+    let shapes = shapes_2d::default_shapes();
+    let (window, event_loop) = window::init(options.resolution);
+    let (instance, surface, _debug_messenger) = render_core::vulkano_core::init(&event_loop, window.clone(), options.validation)
+        .unwrap_or_else(init_failed);
+    let (device, queue) = render_core::vulkano_core::init_device(instance, DeviceExtensions {
+        khr_swapchain: true,
+        ..DeviceExtensions::empty()
+    }, Some(&surface), options.device.as_ref()).unwrap_or_else(init_failed);
+    let (mut swapchain, mut images) = render_core::vulkano_core::init_swapchain(device.clone(), surface.clone(), options.swapchain)
+        .unwrap_or_else(init_failed);
+    let mut present_mode = swapchain.present_mode();
+    // What V switches to from vsync: the configured mode if that doesn't wait for vsync, otherwise mailbox.
+    let unsynced_present_mode = if vulkano_core::is_vsync(options.swapchain.present_mode) {
+        PresentMode::Mailbox
+    } else {
+        options.swapchain.present_mode
+    };
+    // The scene is rendered into `hdr_render_pass`, the tone mapping pass draws the result into `render_pass`, the swapchain's.
+    let render_pass = render_core::vulkano_core::init_render_pass(device.clone(), swapchain.image_format()).unwrap_or_else(init_failed);
+    let hdr_render_pass = render_core::vulkano_core::init_render_pass(device.clone(), tonemap::HDR_FORMAT).unwrap_or_else(init_failed);
 
     let mut viewport = Viewport {
         offset: [0.0, 0.0],
@@ -44,115 +104,137 @@ pub fn run() {
         depth_range: RangeInclusive::new(0.0, 1.0)
     };
 
-    let command_buffer_allocator = StandardCommandBufferAllocator::new(device.clone(), StandardCommandBufferAllocatorCreateInfo::default());
     let buffer_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+    let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone(), Default::default());
 
-    let mut framebuffers = window_size_dependent_setup(&images, render_pass.clone(), &mut viewport);
+    let mut framebuffers = window_size_dependent_setup(&images, render_pass.clone(), &mut viewport).unwrap_or_else(init_failed);
+    let mut hdr_target = HdrTarget::new(buffer_allocator.clone(), hdr_render_pass.clone(), swapchain.image_extent()).unwrap_or_else(init_failed);
     let mut recreate_swapchain = false;
-    let mut previous_frame_end = Some(Box::new(vulkano::sync::now(device.clone())) as Box<dyn GpuFuture>);
-
-
-    let vertex_shader: EntryPoint = render_core::shaders::vs_raymarching::load(device.clone())
-        .expect("Failed to create vertex shader")
-        .entry_point("main").unwrap();
-    let fragment_shader: EntryPoint = render_core::shaders::fs_raymarching::load(device.clone())
-        .expect("Failed to create frag shader")
-        .entry_point("main").unwrap();
-
-    let vertex_input_state = MyVertex::per_vertex()
-        .definition(&vertex_shader.info().input_interface).unwrap();
-
-    let stages = vec![
-        PipelineShaderStageCreateInfo::new(vertex_shader),
-        PipelineShaderStageCreateInfo::new(fragment_shader)
-    ];
-
-
-
-    let pipeline_layout = PipelineLayout::new(device.clone(), PipelineLayoutCreateInfo {
-        push_constant_ranges: vec![PushConstantRange {
-            stages: ShaderStages::FRAGMENT,
-            offset: 0,
-            size: std::mem::size_of::<Constants>() as u32,
-        }],
-        ..PipelineLayoutCreateInfo::default()
-    }).unwrap();
-
-    let subpass = Subpass::from(render_pass.clone(), 0).unwrap();
-
-    let pipeline = GraphicsPipeline::new(
-        device.clone(),
-        None,
-        GraphicsPipelineCreateInfo {
-            flags: Default::default(),
-            stages: stages.into(),
-            vertex_input_state: Some(vertex_input_state),
-            viewport_state: Some(ViewportState::default()),
-            multisample_state: Some(MultisampleState::default()),
-            input_assembly_state: Some(InputAssemblyState::default()),
-            rasterization_state: Some(RasterizationState::default()),
-            color_blend_state: Some(ColorBlendState::with_attachment_states(
-                subpass.num_color_attachments(),
-                ColorBlendAttachmentState {
-                    blend: Some(AttachmentBlend::alpha()),
-                    ..ColorBlendAttachmentState::default()
-                }
-            )),
-            subpass: Some(subpass.into()),
-            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
-            ..GraphicsPipelineCreateInfo::layout(pipeline_layout.clone())
-        }
-    ).expect("Failed to create graphics pipeline");
-
-    let vertices = vec![
-        MyVertex { position: [-1.0, -1.0] },
-        MyVertex { position: [-1.0, 1.0] },
-        MyVertex { position: [1.0, -1.0] },
-        MyVertex { position: [1.0, 1.0] }
-    ];
-    let indices = vec![0u32, 1, 2, 1, 2, 3];
-
-    let vertex_buffer = Buffer::from_iter(
-        buffer_allocator.clone(),
-        BufferCreateInfo {
-            usage: BufferUsage::VERTEX_BUFFER,
-            ..BufferCreateInfo::default()
-        },
-        AllocationCreateInfo {
-            memory_type_filter: MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-            ..AllocationCreateInfo::default()
-        },
-        vertices
-    ).expect("Failed to create vertex buffer");
-
-    let index_buffer = Buffer::from_iter(
-        buffer_allocator.clone(),
-        BufferCreateInfo {
-            usage: BufferUsage::INDEX_BUFFER,
-            ..BufferCreateInfo::default()
-        },
-        AllocationCreateInfo {
-            memory_type_filter: MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
-            ..AllocationCreateInfo::default()
-        },
-        indices.clone()
-    ).expect("Failed to create index buffer");
 
 
-    let mut delta_time = 0.0;
-    let mut now = Instant::now();
+    let shader_dir = shader_dir(options);
+    let mut mode = if options.shadertoy.is_some() {
+        ViewMode::Shadertoy
+    } else if options.sdf_2d {
+        ViewMode::Sdf2d
+    } else {
+        ViewMode::Scene
+    };
+    let pipeline_cache_path = options.pipeline_cache.clone();
+    let pipeline_cache = pipeline_cache::load(device.clone(), pipeline_cache_path.as_deref());
+    let mut variant = options.variant;
+    let mut pending_variant: Option<Variant> = None;
+    // The pipelines of the variants shown since the scene shaders were last built; `None` until the 3D mode is shown.
+    let mut scene_pipelines: Option<ScenePipelines> = None;
+    // Shadertoy mode keeps the scene pipeline for switching back.
+    let (mut pipeline_layout, mut pipeline, mut scene_set) = match mode {
+        ViewMode::Scene | ViewMode::Shadertoy => {
+            let (mut pipelines, scene_set) = render_core::pipeline::init_scene_pipelines(
+                device.clone(),
+                hdr_render_pass.clone(),
+                buffer_allocator.clone(),
+                &descriptor_set_allocator,
+                &scene,
+                !options.interpret_scene,
+                shader_dir.as_deref(),
+                Some(pipeline_cache.clone())
+            );
+            let pipeline = pipelines.get(variant).expect("Failed to create graphics pipeline");
+            let layout = pipelines.layout();
+            scene_pipelines = Some(pipelines);
+            (layout, pipeline, scene_set)
+        }
+        ViewMode::Sdf2d => render_core::pipeline::init_2d_pipeline(
+            device.clone(),
+            hdr_render_pass.clone(),
+            buffer_allocator.clone(),
+            &descriptor_set_allocator,
+            &shapes,
+            shader_dir.as_deref()
+        ),
+    };
+    let project_path = options.shadertoy.clone();
+    let channels = options.channels.clone();
+    let mut shadertoy = project_path.as_deref().map(|path| {
+        load_project(path, &channels)
+            .and_then(|project| ShadertoyRenderer::new(
+                device.clone(),
+                queue.clone(),
+                buffer_allocator.clone(),
+                render_pass.clone(),
+                &project,
+                swapchain.image_extent(),
+                shader_dir.as_deref()
+            ))
+            .unwrap_or_else(|e| {
+                eprintln!("Failed to load {}: {}", path.display(), e);
+                std::process::exit(1);
+            })
+    });
+    let mut frames = FrameRing::new(device.clone(), buffer_allocator.clone(), options.frames_in_flight);
+    match &shadertoy {
+        Some(renderer) if mode == ViewMode::Shadertoy => frames.bind(&descriptor_set_allocator, renderer.pipeline()),
+        _ => frames.bind(&descriptor_set_allocator, &pipeline),
+    }
+    let (mut tonemap_layout, mut tonemap_pipeline) = tonemap::init_pipeline(device.clone(), render_pass.clone(), shader_dir.as_deref());
+    let sampler = tonemap::init_sampler(device.clone());
+    let mut exposure_average = ExposureAverage::new(device.clone(), buffer_allocator.clone(), &hdr_target, shader_dir.as_deref())
+        .unwrap_or_else(init_failed);
+    let mut tonemap_set = hdr_target.descriptor_set(&descriptor_set_allocator, &tonemap_pipeline, sampler.clone(), &exposure_average);
+    let mut tone_mapping = options.tone_mapping;
+    let mut accumulation = Accumulation::new(device.clone(), buffer_allocator.clone(), &hdr_target, options.accumulate_samples, shader_dir.as_deref())
+        .unwrap_or_else(init_failed);
+    let mut accumulate = options.accumulation;
+    let mut pending_mode: Option<ViewMode> = None;
+    let mut shader_watcher = shader_dir.as_deref().and_then(|dir| match ShaderWatcher::new(dir) {
+        Ok(watcher) => {
+            println!("Watching {} for shader changes", dir.display());
+            Some(watcher)
+        }
+        Err(e) => {
+            eprintln!("Failed to watch {}, shaders won't be reloaded: {}", dir.display(), e);
+            None
+        }
+    });
+    let mut project_watcher = project_path.as_deref().and_then(|path| {
+        let project = load_project(path, &channels).ok()?;
+        watch_project(path, &project)
+    });
+    let (vertex_buffer, index_buffer) = render_core::pipeline::init_quad(buffer_allocator.clone());
+
+    let mut clock = Clock::wall();
+    let mut timeline = Timeline::new(options.start_time);
 
     let mut pressed_keys: HashSet<KeyCode> = HashSet::new();
-    let mut pitch_yaw = [0.0f32, 0.0];
+    let mut pitch_yaw = options.pitch_yaw;
 
-    let mut camera_position = Vector3::new(0.0, 1.6, -5.0);
+    let mut camera_position = Vector3::from(options.camera_position);
     let camera_up = Vector3::new(0.0, 1.0, 0.0);
-    let mut camera_front = Vector3::new(0.0, 0.0, 1.0);
+    let mut camera_front = direction_from_pitch_yaw(pitch_yaw);
+
+    let screenshot_dir = options.screenshot_dir.clone();
+    let interpret_scene = options.interpret_scene;
+    let mut capture_request: Option<ImageFileFormat> = None;
+    let mut quality = options.quality.clone();
 
-    let mut push_constants = Constants {
-        view_matrix: get_view_matrix(camera_position, camera_front, camera_up).into(),
-        camera_position: [camera_position.x, camera_position.y, camera_position.z, 0.0],
+    let mut constants = Constants {
+        time: timeline.time,
+        params: scene.packed_params(),
+        quality: quality.quality(),
+        fov: options.fov,
+        ..still_constants(CameraPose { position: camera_position, front: camera_front }, swapchain.image_extent())
+    };
+    // One segment of the material debug view's legend each.
+    let material_count = scene.compile().expect("scene was validated when it was loaded").materials.len();
+    show_legend(&window, mode, variant, &constants.quality, material_count);
+    // What the accumulated frames were rendered with, and the scene time they all show.
+    let mut accumulated_state: Option<SceneState> = None;
+    let mut accumulated_time = timeline.time;
+    let mut push_constants_2d = Constants2d {
         resolution: [viewport.extent[0], viewport.extent[1]],
+        pan: [0.0, 0.0],
+        zoom: 1.0,
+        time: timeline.time,
     };
 
     event_loop.run(move |event, event_loop_window_target| {
@@ -163,6 +245,13 @@ pub fn run() {
             } => {
                 event_loop_window_target.exit();
             }
+            Event::LoopExiting => {
+                if let Some(path) = pipeline_cache_path.as_deref() {
+                    if let Err(e) = pipeline_cache::save(&pipeline_cache, path) {
+                        eprintln!("Failed to save the pipeline cache to {}: {}", path.display(), e);
+                    }
+                }
+            }
             Event::DeviceEvent {
                 event: DeviceEvent::Key(RawKeyEvent { physical_key: PhysicalKey::Code(kc), state, .. }),
                 ..
@@ -171,6 +260,61 @@ pub fn run() {
                     (KeyCode::Escape, ElementState::Pressed) => {
                         event_loop_window_target.exit();
                     }
+                    (KeyCode::F12, ElementState::Pressed) => {
+                        capture_request = Some(ImageFileFormat::Png);
+                    }
+                    (KeyCode::F11, ElementState::Pressed) => {
+                        capture_request = Some(ImageFileFormat::Exr);
+                    }
+                    (KeyCode::Tab, ElementState::Pressed) => {
+                        pending_mode = Some(mode.next(shadertoy.is_some()));
+                    }
+                    (KeyCode::KeyV, ElementState::Pressed) => {
+                        let wanted = if vulkano_core::is_vsync(present_mode) { unsynced_present_mode } else { PresentMode::Fifo };
+                        present_mode = vulkano_core::choose_present_mode(device.physical_device(), &surface, wanted);
+                        println!("VSync {} ({:?})", if vulkano_core::is_vsync(present_mode) { "on" } else { "off" }, present_mode);
+                        recreate_swapchain = true;
+                    }
+                    (KeyCode::KeyT | KeyCode::KeyE | KeyCode::Minus | KeyCode::Equal, ElementState::Pressed) => {
+                        match kc {
+                            KeyCode::KeyT => tone_mapping.operator = tone_mapping.operator.next(),
+                            KeyCode::KeyE => tone_mapping.auto_exposure = !tone_mapping.auto_exposure,
+                            KeyCode::Minus => tone_mapping.exposure -= 0.5,
+                            _ => tone_mapping.exposure += 0.5,
+                        }
+                        println!("Tone mapping: {}", tone_mapping);
+                    }
+                    (KeyCode::KeyP | KeyCode::BracketLeft | KeyCode::BracketRight, ElementState::Pressed) => {
+                        match kc {
+                            KeyCode::KeyP => timeline.toggle_pause(),
+                            KeyCode::BracketLeft => timeline.slower(),
+                            _ => timeline.faster(),
+                        }
+                        println!("Time: {}", timeline);
+                    }
+                    (KeyCode::KeyQ, ElementState::Pressed) => {
+                        quality.preset = quality.preset.next();
+                        constants.quality = quality.quality();
+                        println!("Quality: {}", quality);
+                        show_legend(&window, mode, variant, &constants.quality, material_count);
+                    }
+                    (KeyCode::Digit1 | KeyCode::Digit2 | KeyCode::Digit3 | KeyCode::Digit4 | KeyCode::Digit5 | KeyCode::Digit6, ElementState::Pressed) => {
+                        let mut next_variant = pending_variant.unwrap_or(variant);
+                        match kc {
+                            KeyCode::Digit1 => next_variant.toggle(Feature::Shadows),
+                            KeyCode::Digit2 => next_variant.toggle(Feature::Ao),
+                            KeyCode::Digit3 => next_variant.toggle(Feature::Fog),
+                            KeyCode::Digit4 => next_variant.toggle(Feature::Terrain),
+                            KeyCode::Digit6 => next_variant.path_tracing = !next_variant.path_tracing,
+                            _ => next_variant.debug_view = next_variant.debug_view.next(),
+                        }
+                        pending_variant = Some(next_variant);
+                    }
+                    (KeyCode::KeyR, ElementState::Pressed) => {
+                        accumulate = !accumulate;
+                        accumulation.reset();
+                        println!("Accumulation {}", if accumulate { "on" } else { "off" });
+                    }
                     (KeyCode::KeyF, ElementState::Pressed) => {
                         if window.fullscreen().is_some() {
                             window.set_fullscreen(None);
@@ -186,11 +330,33 @@ pub fn run() {
                     }
                 }
             }
+            Event::WindowEvent {
+                event: WindowEvent::CursorMoved { position, .. },
+                ..
+            } => {
+                constants.mouse[0] = position.x as f32;
+                constants.mouse[1] = position.y as f32;
+            }
             Event::WindowEvent {
                 event: WindowEvent::MouseInput { state, button, .. },
                 ..
             } => {
+                let bit = match button {
+                    MouseButton::Left => MOUSE_LEFT,
+                    MouseButton::Right => MOUSE_RIGHT,
+                    MouseButton::Middle => MOUSE_MIDDLE,
+                    _ => 0,
+                };
+                match state {
+                    ElementState::Pressed => constants.mouse_buttons |= bit,
+                    ElementState::Released => constants.mouse_buttons &= !bit,
+                }
                 if button == MouseButton::Left && state == ElementState::Pressed {
+                    constants.mouse[2] = constants.mouse[0];
+                    constants.mouse[3] = constants.mouse[1];
+                }
+                // Shadertoy passes read the cursor, so it stays free; the other modes grab it to look around.
+                if button == MouseButton::Left && state == ElementState::Pressed && mode != ViewMode::Shadertoy {
                     window.set_cursor_grab(CursorGrabMode::Confined)
                         .or_else(|_e| window.set_cursor_grab(CursorGrabMode::Locked))
                         .unwrap();
@@ -202,10 +368,27 @@ pub fn run() {
                         .unwrap();
                 }
             }
+            Event::WindowEvent {
+                event: WindowEvent::MouseWheel { delta, .. },
+                ..
+            } => {
+                let steps = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 50.0,
+                };
+                match mode {
+                    ViewMode::Sdf2d => zoom_2d(&mut push_constants_2d, 1.1f32.powf(steps)),
+                    ViewMode::Scene => {
+                        constants.fov = (constants.fov / 1.1f32.powf(steps)).clamp(MIN_FOV, MAX_FOV);
+                        println!("Field of view: {:.0} degrees", constants.fov);
+                    }
+                    ViewMode::Shadertoy => {}
+                }
+            }
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion { delta },
                 ..
-            } => {
+            } if mode == ViewMode::Scene => {
                 let sensitivity = 0.01 * 3.0;
                 pitch_yaw[0] += delta.1 as f32 * sensitivity;
                 pitch_yaw[1] -= delta.0 as f32 * sensitivity;
@@ -218,13 +401,8 @@ pub fn run() {
 
                 print!("{:?}\r", pitch_yaw);
 
-                let direction = Vector3::new(
-                    pitch_yaw[1].to_radians().cos() * pitch_yaw[0].to_radians().cos(),
-                    pitch_yaw[0].to_radians().sin(),
-                    pitch_yaw[1].to_radians().sin() * pitch_yaw[0].to_radians().cos()
-                );
-                camera_front = direction.normalize();
-                push_constants.view_matrix = get_view_matrix(camera_position, camera_front, camera_up).into();
+                camera_front = direction_from_pitch_yaw(pitch_yaw);
+                constants.view_matrix = get_view_matrix(camera_position, camera_front, camera_up).into();
             }
             Event::WindowEvent {
                 event: WindowEvent::Resized(_),
@@ -233,36 +411,200 @@ pub fn run() {
                 recreate_swapchain = true;
             }
             Event::AboutToWait => {
-                delta_time = {
-                    let new_now = Instant::now();
-                    let delta = new_now - now;
-                    now = new_now;
-                    delta.as_secs_f32()
-                };
-                camera_position = update_camera_position(&pressed_keys, &mut camera_position, camera_front, delta_time);
-                push_constants.view_matrix = get_view_matrix(camera_position, camera_front, camera_up).into();
-                push_constants.camera_position = [camera_position.x, camera_position.y, camera_position.z, 0.0];
+                let shaders_changed = shader_watcher.as_mut().is_some_and(|watcher| watcher.poll());
+                let project_changed = project_watcher.as_mut().is_some_and(|watcher| watcher.poll());
+                if let (Some(renderer), Some(path)) = (shadertoy.as_mut(), project_path.as_deref()) {
+                    if shaders_changed || project_changed {
+                        let project = load_project(path, &channels);
+                        // The project file may now reference other files; if it doesn't parse, the old ones stay watched.
+                        if let Ok(project) = &project {
+                            project_watcher = watch_project(path, project);
+                        }
+                        match project.and_then(|project| renderer.reload(&project, shader_dir.as_deref())) {
+                            Ok(()) => {
+                                if mode == ViewMode::Shadertoy {
+                                    frames.bind(&descriptor_set_allocator, renderer.pipeline());
+                                }
+                                println!("Reloaded {}", path.display());
+                            }
+                            Err(e) => eprintln!("Failed to reload {}, keeping the previous passes:\n{}", path.display(), e),
+                        }
+                    }
+                }
+                // Shadertoy passes were reloaded above; the scene pipeline is rebuilt anyway when switching back to it.
+                let reload_mode = (shaders_changed && mode != ViewMode::Shadertoy).then_some(mode);
+                if let Some(next_mode) = pending_mode.take().or(reload_mode) {
+                    // On failure the previous pipeline stays in use, so the viewer keeps running while the shader is being fixed.
+                    let result = match next_mode {
+                        // New shaders start without pipelines; the other variants are built again when they are shown.
+                        ViewMode::Scene => render_core::pipeline::build_scene_pipelines(
+                            device.clone(),
+                            hdr_render_pass.clone(),
+                            buffer_allocator.clone(),
+                            &descriptor_set_allocator,
+                            &scene,
+                            !interpret_scene,
+                            shader_dir.as_deref(),
+                            Some(pipeline_cache.clone())
+                        ).and_then(|(mut pipelines, new_scene_set)| {
+                            let new_pipeline = pipelines.get(variant)?;
+                            let new_layout = pipelines.layout();
+                            scene_pipelines = Some(pipelines);
+                            Ok(Some((new_layout, new_pipeline, new_scene_set)))
+                        }),
+                        ViewMode::Sdf2d => render_core::pipeline::build_2d_pipeline(
+                            device.clone(),
+                            hdr_render_pass.clone(),
+                            buffer_allocator.clone(),
+                            &descriptor_set_allocator,
+                            &shapes,
+                            shader_dir.as_deref()
+                        ).map(Some),
+                        // The passes are built already, only the frame uniforms have to be bound to them.
+                        ViewMode::Shadertoy => Ok(None),
+                    };
+                    match result {
+                        Ok(new_pipeline) => {
+                            match new_pipeline {
+                                Some((new_layout, new_pipeline, new_scene_set)) => {
+                                    pipeline_layout = new_layout;
+                                    pipeline = new_pipeline;
+                                    scene_set = new_scene_set;
+                                    frames.bind(&descriptor_set_allocator, &pipeline);
+                                }
+                                None => {
+                                    let renderer = shadertoy.as_ref().expect("Shadertoy mode needs a project");
+                                    frames.bind(&descriptor_set_allocator, renderer.pipeline());
+                                }
+                            }
+                            if next_mode == mode {
+                                println!("Reloaded shaders");
+                            } else {
+                                println!("Switched to {} mode", next_mode);
+                            }
+                            mode = next_mode;
+                            show_legend(&window, mode, variant, &constants.quality, material_count);
+                        }
+                        Err(e) if next_mode == mode => eprintln!("Failed to reload shaders, keeping the previous ones:\n{}", e),
+                        Err(e) => eprintln!("Failed to switch to {} mode, staying in {} mode:\n{}", next_mode, mode, e),
+                    }
+                }
+                if let Some(next_variant) = pending_variant.take() {
+                    // Outside of the 3D mode the variant is only remembered, it is built when switching back.
+                    let result = match scene_pipelines.as_mut() {
+                        Some(pipelines) if mode == ViewMode::Scene => pipelines.get(next_variant).map(Some),
+                        _ => Ok(None),
+                    };
+                    match result {
+                        Ok(new_pipeline) => {
+                            // All variants share the pipeline layout, so the bound descriptor sets stay valid.
+                            if let Some(new_pipeline) = new_pipeline {
+                                pipeline = new_pipeline;
+                            }
+                            variant = next_variant;
+                            println!("Variant: {}", variant);
+                            show_legend(&window, mode, variant, &constants.quality, material_count);
+                        }
+                        Err(e) => eprintln!("Failed to build the {} variant, keeping {}:\n{}", next_variant, variant, e),
+                    }
+                }
+                if shaders_changed {
+                    if let Err(e) = exposure_average.reload(&hdr_target, shader_dir.as_deref()) {
+                        eprintln!("Failed to reload the luminance shader, keeping the previous one:\n{}", e);
+                    }
+                    match tonemap::build_pipeline(device.clone(), render_pass.clone(), shader_dir.as_deref()) {
+                        Ok((new_layout, new_pipeline)) => {
+                            tonemap_set = hdr_target.descriptor_set(&descriptor_set_allocator, &new_pipeline, sampler.clone(), &exposure_average);
+                            tonemap_layout = new_layout;
+                            tonemap_pipeline = new_pipeline;
+                        }
+                        Err(e) => eprintln!("Failed to reload the tone mapping shader, keeping the previous one:\n{}", e),
+                    }
+                    if let Err(e) = accumulation.reload(&hdr_target, shader_dir.as_deref()) {
+                        eprintln!("Failed to reload the accumulation shader, keeping the previous one:\n{}", e);
+                    }
+                }
+
+                let delta_time = clock.tick();
+                // Scrubbing runs at twice real time in either direction, whether the time is paused or not.
+                let scrub_keys = [(KeyCode::ArrowLeft, -2.0), (KeyCode::ArrowRight, 2.0)];
+                for (key, rate) in scrub_keys {
+                    if pressed_keys.contains(&key) {
+                        timeline.scrub(rate * delta_time);
+                    }
+                }
+                constants.delta_time = timeline.advance(delta_time);
+                constants.time = timeline.time;
+                match mode {
+                    ViewMode::Scene => {
+                        camera_position = update_camera_position(&pressed_keys, &mut camera_position, camera_front, delta_time);
+                        constants.view_matrix = get_view_matrix(camera_position, camera_front, camera_up).into();
+                        constants.camera_position = [camera_position.x, camera_position.y, camera_position.z, 0.0];
+                    }
+                    ViewMode::Sdf2d => update_pan(&pressed_keys, &mut push_constants_2d, delta_time),
+                    ViewMode::Shadertoy => {}
+                }
+                push_constants_2d.time = timeline.time;
+
+                // Any change to what the frame shows starts the accumulation over, the scene time included: frames add up
+                // while the timeline is paused, a running one keeps animating.
+                let state = SceneState::new(&constants, variant, mode);
+                if shaders_changed || constants.time != accumulated_time || accumulated_state.as_ref() != Some(&state) {
+                    accumulation.reset();
+                    accumulated_state = Some(state);
+                    accumulated_time = constants.time;
+                }
                 window.request_redraw();
             }
             Event::WindowEvent {
                 event: WindowEvent::RedrawRequested,
                 ..
             } => {
-                previous_frame_end.as_mut().unwrap().cleanup_finished();
                 if recreate_swapchain {
                     let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
                     let extent: [u32; 2] = window.inner_size().into();
-                    push_constants.resolution = [extent[0] as f32, extent[1] as f32];
+                    constants.resolution = [extent[0] as f32, extent[1] as f32];
+                    push_constants_2d.resolution = constants.resolution;
 
                     let (new_swapchain, new_images) = match swapchain.recreate(SwapchainCreateInfo {
                         image_extent: extent,
+                        present_mode,
                         ..swapchain.create_info()
                     }) {
                         Ok(r) => r,
                         Err(e) => panic!("Failed to recreate swapchain: {:?}", e),
                     };
                     swapchain = new_swapchain;
-                    framebuffers = window_size_dependent_setup(&new_images, render_pass.clone(), &mut viewport);
+                    images = new_images;
+                    let targets = window_size_dependent_setup(&images, render_pass.clone(), &mut viewport)
+                        .and_then(|framebuffers| Ok((framebuffers, HdrTarget::new(buffer_allocator.clone(), hdr_render_pass.clone(), extent)?)));
+                    match targets {
+                        Ok((new_framebuffers, new_hdr_target)) => {
+                            framebuffers = new_framebuffers;
+                            hdr_target = new_hdr_target;
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to recreate framebuffers: {}", e);
+                            event_loop_window_target.exit();
+                            return;
+                        }
+                    }
+                    if let Err(e) = accumulation.resize(&hdr_target) {
+                        eprintln!("Failed to recreate the accumulation history: {}", e);
+                        event_loop_window_target.exit();
+                        return;
+                    }
+                    if let Err(e) = exposure_average.resize(&hdr_target) {
+                        eprintln!("Failed to recreate the luminance target: {}", e);
+                        event_loop_window_target.exit();
+                        return;
+                    }
+                    tonemap_set = hdr_target.descriptor_set(&descriptor_set_allocator, &tonemap_pipeline, sampler.clone(), &exposure_average);
+                    if let Some(Err(e)) = shadertoy.as_mut().map(|renderer| renderer.resize(extent)) {
+                        eprintln!("Failed to recreate the Shadertoy buffers: {}", e);
+                        event_loop_window_target.exit();
+                        return;
+                    }
                     recreate_swapchain = false;
                 }
 
@@ -278,51 +620,130 @@ pub fn run() {
                     recreate_swapchain = true;
                 }
 
-                let clear_values = vec![Some([0.0, 0.0, 0.0, 1.0].into())];
-                let mut builder = vulkano::command_buffer::AutoCommandBufferBuilder::primary(
-                    &command_buffer_allocator,
+                // Waits for the GPU to finish the frame that last used these resources, `frames_in_flight` frames ago.
+                let frame = frames.next();
+                let accumulating = accumulate && mode == ViewMode::Scene && timeline.paused;
+                // Once converged the HDR target keeps the average and the scene isn't drawn again until something changes.
+                let converged = accumulating && accumulation.converged();
+                constants.jitter = if accumulating { accumulation.jitter() } else { [0.0; 2] };
+                *frame.uniforms.write().unwrap() = constants.clone();
+                constants.frame_index += 1;
+                let frame_set = frame.frame_set.clone();
+                let mut builder = AutoCommandBufferBuilder::primary(
+                    &frame.command_buffer_allocator,
                     queue.queue_family_index(),
                     CommandBufferUsage::OneTimeSubmit
                 ).unwrap();
 
-                builder
-                    .begin_render_pass(
-                        RenderPassBeginInfo {
-                            clear_values,
-                            ..RenderPassBeginInfo::framebuffer(
-                                framebuffers[image_index as usize].clone(),
-                            )
-                        },
-                        SubpassBeginInfo {
-                            contents: SubpassContents::Inline,
-                            ..SubpassBeginInfo::default()
+                match mode {
+                    ViewMode::Scene if converged => {}
+                    ViewMode::Scene => render_core::pipeline::record_draw(
+                        &mut builder,
+                        hdr_target.framebuffer.clone(),
+                        &viewport,
+                        pipeline_layout.clone(),
+                        pipeline.clone(),
+                        None::<Constants>,
+                        scene_set.clone(),
+                        frame_set,
+                        vertex_buffer.clone(),
+                        index_buffer.clone(),
+                    ),
+                    ViewMode::Sdf2d => render_core::pipeline::record_draw(
+                        &mut builder,
+                        hdr_target.framebuffer.clone(),
+                        &viewport,
+                        pipeline_layout.clone(),
+                        pipeline.clone(),
+                        Some(push_constants_2d.clone()),
+                        scene_set.clone(),
+                        None,
+                        vertex_buffer.clone(),
+                        index_buffer.clone(),
+                    ),
+                    ViewMode::Shadertoy => shadertoy.as_mut().expect("Shadertoy mode needs a project").record(
+                        &mut builder,
+                        framebuffers[image_index as usize].clone(),
+                        &viewport,
+                        frame_set,
+                        vertex_buffer.clone(),
+                        index_buffer.clone(),
+                    ),
+                }
+                if accumulating && !converged {
+                    accumulation.record(&mut builder, &hdr_target, &viewport, vertex_buffer.clone(), index_buffer.clone());
+                    if accumulation.converged() {
+                        println!("Accumulated {} samples", accumulation.samples());
+                    }
+                }
+                // The Shadertoy Image pass draws to the swapchain itself, display encoded like on the website.
+                if mode != ViewMode::Shadertoy {
+                    // Debug views are drawn as they are, so the colors match their legend.
+                    let debug_view = mode == ViewMode::Scene && variant.debug_view != DebugView::Off;
+                    let tone_map_constants = if debug_view {
+                        ToneMapping::passthrough_constants()
+                    } else {
+                        tone_mapping.constants(&exposure_average)
+                    };
+                    if tone_mapping.auto_exposure && !debug_view {
+                        exposure_average.record(&mut builder, vertex_buffer.clone(), index_buffer.clone());
+                    }
+                    render_core::pipeline::record_draw(
+                        &mut builder,
+                        framebuffers[image_index as usize].clone(),
+                        &viewport,
+                        tonemap_layout.clone(),
+                        tonemap_pipeline.clone(),
+                        Some(tone_map_constants),
+                        Some(tonemap_set.clone()),
+                        None,
+                        vertex_buffer.clone(),
+                        index_buffer.clone(),
+                    );
+                }
+
+                // PNG screenshots show what is on screen, EXR screenshots the HDR frame before tone mapping.
+                let capture = capture_request.take().and_then(|file_format| {
+                    let image = match file_format {
+                        ImageFileFormat::Png if !swapchain.image_usage().intersects(ImageUsage::TRANSFER_SRC) => {
+                            eprintln!("PNG screenshots are not supported: swapchain images can't be copied on this device");
+                            return None;
+                        }
+                        ImageFileFormat::Png => images[image_index as usize].clone(),
+                        ImageFileFormat::Exr if mode == ViewMode::Shadertoy => {
+                            eprintln!("EXR screenshots are not supported in Shadertoy mode, there is no HDR frame");
+                            return None;
                         }
-                    ).unwrap()
-                    .set_viewport(0, vec![viewport.clone()].into()).unwrap()
-                    .push_constants(pipeline_layout.clone(), 0, push_constants.clone()).unwrap()
-                    .bind_pipeline_graphics(pipeline.clone()).unwrap()
-                    .bind_vertex_buffers(0, vec![vertex_buffer.clone()]).unwrap()
-                    .bind_index_buffer(index_buffer.clone()).unwrap()
-                    .draw_indexed(indices.len() as u32, 1, 0, 0, 0).unwrap()
-                    .end_render_pass(SubpassEndInfo::default()).unwrap();
+                        ImageFileFormat::Exr => hdr_target.image.clone(),
+                    };
+                    let buffer = capture::copy_to_host(&mut builder, buffer_allocator.clone(), image.clone());
+                    Some((file_format, image, buffer))
+                });
+
                 let command_buffer = builder.build().unwrap();
 
-                let future = previous_frame_end.take().unwrap().join(swapchain_future)
+                let future = frames.previous_future(device.clone()).join(swapchain_future)
                     .then_execute(queue.clone(), command_buffer).unwrap()
                     .then_swapchain_present(queue.clone(), SwapchainPresentInfo::swapchain_image_index(swapchain.clone(), image_index))
+                    .boxed_send_sync()
                     .then_signal_fence_and_flush();
 
                 match future {
                     Ok(future) => {
-                        previous_frame_end = Some(Box::new(future) as Box<_>);
+                        let fence = Arc::new(future);
+                        if let Some((file_format, image, buffer)) = capture {
+                            fence.wait(None).expect("Failed to wait for screenshot");
+                            save_screenshot(&screenshot_dir, file_format, &image, &buffer.read().unwrap());
+                        }
+                        frames.submitted(Some(fence));
                     }
                     Err(Validated::Error(vulkano::VulkanError::OutOfDate)) => {
                         recreate_swapchain = true;
-                        previous_frame_end = Some(Box::new(vulkano::sync::now(device.clone())) as Box<_>);
+                        frames.submitted(None);
                     }
                     Err(e) => {
                         eprintln!("Failed to flush future: {:?}", e);
-                        previous_frame_end = Some(Box::new(vulkano::sync::now(device.clone())) as Box<_>);
+                        frames.submitted(None);
                     }
                 }
             }
@@ -331,6 +752,323 @@ pub fn run() {
     }).expect("Event Loop failed");
 }
 
+/// Puts the legend of the debug view into the window title while the 3D scene shows one, otherwise the plain title.
+fn show_legend(window: &Window, mode: ViewMode, variant: Variant, quality: &Quality, materials: usize) {
+    match variant.debug_view.legend(quality, materials).filter(|_| mode == ViewMode::Scene) {
+        Some(legend) => window.set_title(&format!("{} - {} view: {}", window::TITLE, variant.debug_view, legend)),
+        None => window.set_title(window::TITLE),
+    }
+}
+
+/// `--list-devices`: prints what `--device` can choose from.
+pub fn list_devices() {
+    let (instance, _debug_messenger) = render_core::vulkano_core::init_headless(false).unwrap_or_else(init_failed);
+    let description = render_core::vulkano_core::describe_devices(&instance).unwrap_or_else(init_failed);
+    print!("{}", description);
+}
+
+/// Renders a single frame without a window and writes it to `path`, for batch renders and machines without a display.
+pub fn render_still(options: &Options, path: &Path) {
+    let extent = options.resolution.unwrap_or([1920, 1080]);
+    let scene = load_scene(options);
+    let renderer = OfflineRenderer::new(options, &scene, extent);
+
+    let pose = CameraPose {
+        position: Vector3::from(options.camera_position),
+        front: direction_from_pitch_yaw(options.pitch_yaw),
+    };
+
+    let texels = renderer.render(Constants {
+        time: options.start_time,
+        params: scene.packed_params(),
+        quality: options.quality.quality(),
+        fov: options.fov,
+        ..still_constants(pose, extent)
+    });
+    report_validation_errors();
+    match capture::save(path, extent, renderer.format(), &texels, Some(&options.tone_mapping)) {
+        Ok(()) => println!("Saved {}x{} frame to {}", extent[0], extent[1], path.display()),
+        Err(e) => {
+            eprintln!("Failed to save {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Renders `options.frames` frames on a fixed clock, independent of how long each frame takes to render.
+pub fn export_sequence(options: &Options, path: &Path) {
+    let extent = options.resolution.unwrap_or([1920, 1080]);
+    let mut writer = SequenceWriter::create(path, extent, options.fps).unwrap_or_else(|e| {
+        eprintln!("Failed to create {}: {}", path.display(), e);
+        std::process::exit(1);
+    });
+    let scene = load_scene(options);
+    let renderer = OfflineRenderer::new(options, &scene, extent);
+
+    let camera_path = camera_path(options);
+    let duration = options.frames as f32 / options.fps;
+
+    let mut clock = Clock::fixed(options.fps);
+    let mut time = 0.0;
+    let mut delta_time = 0.0;
+    for frame in 0..options.frames {
+        let pose = camera_path.pose(time / duration);
+        let texels = renderer.render(Constants {
+            time: options.start_time + time,
+            delta_time,
+            frame_index: frame,
+            params: scene.packed_params(),
+            quality: options.quality.quality(),
+            fov: options.fov,
+            ..still_constants(pose, extent)
+        });
+        if let Err(e) = writer.write_frame(frame, extent, renderer.format(), &texels, Some(&options.tone_mapping)) {
+            eprintln!("Failed to write frame {}: {}", frame, e);
+            std::process::exit(1);
+        }
+        print!("Exported frame {}/{}\r", frame + 1, options.frames);
+        delta_time = clock.tick();
+        time += delta_time;
+    }
+
+    report_validation_errors();
+    match writer.finish() {
+        Ok(()) => println!("\nSaved {} frames at {} fps to {}", options.frames, options.fps, path.display()),
+        Err(e) => {
+            eprintln!("\nFailed to finish {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// What `--render` and `--export` render into: linear HDR, tone mapped on the CPU for 8-bit output.
+const OFFLINE_FORMAT: Format = Format::R32G32B32A32_SFLOAT;
+
+/// Backend for `--render` and `--export`: the Vulkan pipeline, or the CPU reference renderer with `--cpu`.
+enum OfflineRenderer {
+    /// Every frame is the average of `samples` passes, more than one only with `--path-trace`.
+    Gpu { renderer: Box<HeadlessRenderer>, samples: u32 },
+    Cpu { extent: [u32; 2], scene: CompiledScene },
+}
+
+impl OfflineRenderer {
+    fn new(options: &Options, scene: &Scene, extent: [u32; 2]) -> Self {
+        if options.cpu {
+            let scene = scene.compile().expect("scene was validated when it was loaded");
+            OfflineRenderer::Cpu { extent, scene }
+        } else {
+            let shader_dir = shader_dir(options);
+            let mut renderer = HeadlessRenderer::new(extent, OFFLINE_FORMAT, scene, !options.interpret_scene, shader_dir.as_deref(), options.device.as_ref(), options.validation)
+                .unwrap_or_else(init_failed);
+            if let Err(e) = renderer.set_variant(options.variant) {
+                eprintln!("Failed to create the {} pipeline: {}", options.variant, e);
+                std::process::exit(1);
+            }
+            let samples = if options.variant.path_tracing { options.spp } else { 1 };
+            OfflineRenderer::Gpu { renderer: Box::new(renderer), samples }
+        }
+    }
+
+    fn format(&self) -> Format {
+        match self {
+            OfflineRenderer::Gpu { renderer, .. } => renderer.format(),
+            OfflineRenderer::Cpu { .. } => OFFLINE_FORMAT,
+        }
+    }
+
+    fn render(&self, constants: Constants) -> Vec<u8> {
+        match self {
+            OfflineRenderer::Gpu { renderer, samples: 1 } => renderer.render(constants),
+            OfflineRenderer::Gpu { renderer, samples } => render_samples(renderer, constants, *samples),
+            OfflineRenderer::Cpu { extent, scene } => reference::render(&constants, scene, *extent)
+                .iter()
+                .flatten()
+                .flat_map(|c| c.to_le_bytes())
+                .collect(),
+        }
+    }
+}
+
+/// Renders `samples` passes of a frame, each with its own jitter and random numbers, and averages them like the viewer's
+/// accumulation does.
+fn render_samples(renderer: &HeadlessRenderer, constants: Constants, samples: u32) -> Vec<u8> {
+    let mut sum: Vec<f32> = Vec::new();
+    for sample in 0..samples {
+        let texels = renderer.render(Constants {
+            jitter: accumulation::jitter(sample),
+            // The path tracer seeds its random numbers with the frame index, so every pass needs its own.
+            frame_index: constants.frame_index.wrapping_mul(samples).wrapping_add(sample),
+            ..constants.clone()
+        });
+        let values = texels.chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()));
+        if sum.is_empty() {
+            sum = values.collect();
+        } else {
+            sum.iter_mut().zip(values).for_each(|(sum, value)| *sum += value);
+        }
+        print!("Sample {}/{}\r", sample + 1, samples);
+        // Without a newline stdout isn't flushed on its own.
+        std::io::stdout().flush().unwrap();
+    }
+    println!();
+    sum.iter().flat_map(|sum| (sum / samples as f32).to_le_bytes()).collect()
+}
+
+/// The individual messages have been logged as they came; this makes them hard to miss after a long export.
+fn report_validation_errors() {
+    let errors = render_core::vulkano_core::validation_error_count();
+    if errors > 0 {
+        log::warn!("{} Vulkan validation errors while rendering", errors);
+    }
+}
+
+/// Vulkan is required outside of `--cpu`, so there is nothing to fall back to once `vulkano_core` gave up.
+fn init_failed<T>(e: InitError) -> T {
+    eprintln!("Failed to initialize Vulkan: {}", e);
+    std::process::exit(1);
+}
+
+/// Loads the `--scene` file, or the built-in default scene, applies the `--param` overrides and validates it;
+/// exits on an invalid scene.
+fn load_scene(options: &Options) -> Scene {
+    let scene = match &options.scene {
+        Some(path) => Scene::load(path),
+        None => Ok(Scene::default_scene()),
+    };
+    let scene = scene.map(|mut scene| {
+        for &(index, value) in &options.params {
+            if scene.params.len() <= index {
+                scene.params.resize(index + 1, 0.0);
+            }
+            scene.params[index] = value;
+        }
+        scene
+    });
+    match scene.and_then(|scene| scene.compile().map(|_| scene)) {
+        Ok(scene) => scene,
+        Err(e) => {
+            let name = options.scene.as_deref().unwrap_or(Path::new("default scene"));
+            eprintln!("Failed to load {}: {}", name.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A `--shadertoy` project with the `--channel` textures bound to its Image pass.
+fn load_project(path: &Path, channels: &[(usize, PathBuf)]) -> Result<Project, ShadertoyError> {
+    let mut project = Project::load(path)?;
+    for (channel, texture) in channels {
+        // Relative to where the viewer was started, not to the project.
+        let texture = std::env::current_dir().map(|dir| dir.join(texture)).unwrap_or_else(|_| texture.clone());
+        project.set_image_channel(*channel, Channel::Texture(texture));
+    }
+    Ok(project)
+}
+
+/// Watches the project file at `path` and the files `project` reads, for reloading it when one of them changes.
+fn watch_project(path: &Path, project: &Project) -> Option<ShaderWatcher> {
+    let mut files = project.files();
+    files.push(path.to_path_buf());
+    match ShaderWatcher::for_files(&files) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            eprintln!("Failed to watch the files of {}, it won't be reloaded: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// `--shader-dir`, or the source tree's `shaders/` if the binary runs next to it; `None` uses the built-in shaders.
+fn shader_dir(options: &Options) -> Option<PathBuf> {
+    options.shader_dir.clone().or_else(|| Some(shaders::source_dir()).filter(|dir| dir.is_dir()))
+}
+
+fn camera_path(options: &Options) -> CameraPath {
+    let start = CameraPose {
+        position: Vector3::from(options.camera_position),
+        front: direction_from_pitch_yaw(options.pitch_yaw),
+    };
+
+    if let Some(center) = options.turntable {
+        CameraPath::Turntable { center: Vector3::from(center), start: start.position }
+    } else if let Some(end) = options.flythrough {
+        CameraPath::Flythrough { start, end: Vector3::from(end) }
+    } else {
+        CameraPath::Static(start)
+    }
+}
+
+fn save_screenshot(screenshot_dir: &Path, file_format: ImageFileFormat, image: &Image, texels: &[u8]) {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let path = screenshot_dir.join(format!("screenshot-{}.{}", timestamp, file_format.extension()));
+    let extent = image.extent();
+
+    let result = std::fs::create_dir_all(screenshot_dir)
+        .map_err(CaptureError::from)
+        .and_then(|_| capture::save(&path, [extent[0], extent[1]], image.format(), texels, None));
+    match result {
+        Ok(()) => println!("Saved screenshot to {}", path.display()),
+        Err(e) => eprintln!("Failed to save screenshot {}: {}", path.display(), e),
+    }
+}
+
+/// What a frame of the 3D scene depends on besides the time, to tell when accumulated frames no longer match.
+#[derive(PartialEq)]
+struct SceneState {
+    view_matrix: [[f32; 4]; 4],
+    camera_position: [f32; 4],
+    resolution: [f32; 2],
+    params: [[f32; 4]; 4],
+    quality: Quality,
+    fov: f32,
+    variant: Variant,
+    mode: ViewMode,
+}
+
+impl SceneState {
+    fn new(constants: &Constants, variant: Variant, mode: ViewMode) -> Self {
+        Self {
+            view_matrix: constants.view_matrix,
+            camera_position: constants.camera_position,
+            resolution: constants.resolution,
+            params: constants.params,
+            quality: constants.quality,
+            fov: constants.fov,
+            variant,
+            mode,
+        }
+    }
+}
+
+/// Per-frame constants for a frame of `extent` pixels seen from `pose`, at time 0 without mouse input or params.
+pub(crate) fn still_constants(pose: CameraPose, extent: [u32; 2]) -> Constants {
+    let camera_up = Vector3::new(0.0, 1.0, 0.0);
+    Constants {
+        view_matrix: get_view_matrix(pose.position, pose.front, camera_up).into(),
+        camera_position: [pose.position.x, pose.position.y, pose.position.z, 0.0],
+        resolution: [extent[0] as f32, extent[1] as f32],
+        time: 0.0,
+        delta_time: 0.0,
+        mouse: [0.0; 4],
+        frame_index: 0,
+        mouse_buttons: 0,
+        padding: [0; 2],
+        params: [[0.0; 4]; 4],
+        quality: Quality::default(),
+        fov: DEFAULT_FOV,
+        padding2: 0,
+        jitter: [0.0; 2],
+    }
+}
+
+pub(crate) fn direction_from_pitch_yaw(pitch_yaw: [f32; 2]) -> Vector3<f32> {
+    Vector3::new(
+        pitch_yaw[1].to_radians().cos() * pitch_yaw[0].to_radians().cos(),
+        pitch_yaw[0].to_radians().sin(),
+        pitch_yaw[1].to_radians().sin() * pitch_yaw[0].to_radians().cos()
+    ).normalize()
+}
+
 fn update_camera_position(pressed_keys: &HashSet<KeyCode>, camera_position: &mut Vector3<f32>, camera_front: Vector3<f32>, mut delta_time: f32) -> Vector3<f32> {
     delta_time *= 10.0;
     let mut camera_position = camera_position.clone();
@@ -365,6 +1103,30 @@ fn update_camera_position(pressed_keys: &HashSet<KeyCode>, camera_position: &mut
     return camera_position;
 }
 
+/// Pans the 2D view with WASD at a constant speed on screen, whatever the zoom.
+fn update_pan(pressed_keys: &HashSet<KeyCode>, push_constants: &mut Constants2d, delta_time: f32) {
+    let distance = 500.0 * delta_time / push_constants.zoom;
+    for kc in pressed_keys {
+        match kc {
+            KeyCode::KeyW => push_constants.pan[1] -= distance,
+            KeyCode::KeyS => push_constants.pan[1] += distance,
+            KeyCode::KeyA => push_constants.pan[0] -= distance,
+            KeyCode::KeyD => push_constants.pan[0] += distance,
+            _ => {}
+        }
+    }
+}
+
+/// Zooms the 2D view by `factor`, keeping the point in the middle of the window in place.
+fn zoom_2d(push_constants: &mut Constants2d, factor: f32) {
+    let zoom = (push_constants.zoom * factor).clamp(0.01, 1000.0);
+    for axis in 0..2 {
+        let center = push_constants.pan[axis] + push_constants.resolution[axis] / 2.0 / push_constants.zoom;
+        push_constants.pan[axis] = center - push_constants.resolution[axis] / 2.0 / zoom;
+    }
+    push_constants.zoom = zoom;
+}
+
 fn get_view_matrix(camera_position: Vector3<f32>, camera_front: Vector3<f32>, up: Vector3<f32>) -> Matrix4<f32> {
     let up = Vector3::new(0.0, 1.0, 0.0);
     let right = camera_front.cross(&up).normalize();
@@ -379,18 +1141,3 @@ fn get_view_matrix(camera_position: Vector3<f32>, camera_front: Vector3<f32>, up
     // let translate = Matrix4::new_translation(&-camera_position);
     // return (look_at*translate).normalize();
 }
-
-#[repr(C)]
-#[derive(BufferContents, Vertex)]
-struct MyVertex {
-    #[format(R32G32_SFLOAT)]
-    position: [f32; 2],
-}
-
-#[repr(C)]
-#[derive(BufferContents, Clone)]
-struct Constants {
-    view_matrix: [[f32; 4]; 4],
-    camera_position: [f32; 4],
-    resolution: [f32; 2],
-}
\ No newline at end of file
@@ -0,0 +1,217 @@
+pub mod sequence;
+pub mod y4m;
+
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use exr::prelude::f16;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CopyImageToBufferInfo, PrimaryAutoCommandBuffer};
+use vulkano::format::Format;
+use vulkano::image::Image;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use crate::render_core::tonemap::ToneMapping;
+
+#[derive(Debug)]
+pub enum CaptureError {
+    UnknownExtension(PathBuf),
+    UnsupportedFormat(Format),
+    Io(std::io::Error),
+    Png(png::EncodingError),
+    Exr(exr::error::Error),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::UnknownExtension(path) => write!(f, "{}: expected a .png or .exr file", path.display()),
+            CaptureError::UnsupportedFormat(format) => write!(f, "can't convert {:?} texels to an image file", format),
+            CaptureError::Io(e) => write!(f, "{}", e),
+            CaptureError::Png(e) => write!(f, "PNG: {}", e),
+            CaptureError::Exr(e) => write!(f, "EXR: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(e: std::io::Error) -> Self {
+        CaptureError::Io(e)
+    }
+}
+
+impl From<png::EncodingError> for CaptureError {
+    fn from(e: png::EncodingError) -> Self {
+        CaptureError::Png(e)
+    }
+}
+
+impl From<exr::error::Error> for CaptureError {
+    fn from(e: exr::error::Error) -> Self {
+        CaptureError::Exr(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFileFormat {
+    /// 8-bit, sRGB encoded; tone mapped if rendered in HDR.
+    Png,
+    /// 32-bit float, linear HDR.
+    Exr,
+}
+
+impl ImageFileFormat {
+    pub fn from_path(path: &Path) -> Result<Self, CaptureError> {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("png") => Ok(ImageFileFormat::Png),
+            Some("exr") => Ok(ImageFileFormat::Exr),
+            _ => Err(CaptureError::UnknownExtension(path.to_path_buf())),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFileFormat::Png => "png",
+            ImageFileFormat::Exr => "exr",
+        }
+    }
+}
+
+/// Records a copy of `image` into a new host-visible buffer. The buffer can be read once the command buffer has finished.
+pub fn copy_to_host(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    image: Arc<Image>,
+) -> Subbuffer<[u8]> {
+    let extent = image.extent();
+    let buffer = Buffer::new_slice::<u8>(
+        buffer_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST,
+            ..BufferCreateInfo::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+            ..AllocationCreateInfo::default()
+        },
+        extent[0] as u64 * extent[1] as u64 * image.format().block_size()
+    ).expect("Failed to create capture buffer");
+
+    builder
+        .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(image, buffer.clone()))
+        .unwrap();
+
+    buffer
+}
+
+/// Writes tightly packed texels of a Vulkan image to a PNG or EXR file, chosen by the extension of `path`.
+/// `tone_mapping` maps HDR texels for PNG files, see `to_display_srgb8`; EXR files get them as they are.
+pub fn save(path: &Path, extent: [u32; 2], format: Format, texels: &[u8], tone_mapping: Option<&ToneMapping>) -> Result<(), CaptureError> {
+    match ImageFileFormat::from_path(path)? {
+        ImageFileFormat::Png => write_png(path, extent, &to_display_srgb8(format, texels, tone_mapping)?),
+        ImageFileFormat::Exr => write_exr(path, extent, &to_linear(format, texels)?),
+    }
+}
+
+pub fn write_png(path: &Path, extent: [u32; 2], rgba: &[u8]) -> Result<(), CaptureError> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), extent[0], extent[1]);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    encoder.write_header()?.write_image_data(rgba)?;
+    Ok(())
+}
+
+pub fn write_exr(path: &Path, extent: [u32; 2], rgba: &[[f32; 4]]) -> Result<(), CaptureError> {
+    let width = extent[0] as usize;
+    exr::prelude::write_rgba_file(path, width, extent[1] as usize, |x, y| {
+        let [r, g, b, a] = rgba[y * width + x];
+        (r, g, b, a)
+    })?;
+    Ok(())
+}
+
+/// 8-bit sRGB encoded RGBA, tone mapped with `tone_mapping` if given, otherwise clamped like `to_srgb8`.
+pub fn to_display_srgb8(format: Format, texels: &[u8], tone_mapping: Option<&ToneMapping>) -> Result<Vec<u8>, CaptureError> {
+    match tone_mapping {
+        Some(tone_mapping) => {
+            let mut pixels = to_linear(format, texels)?;
+            tone_mapping.apply(&mut pixels);
+            Ok(encode_srgb8(&pixels))
+        }
+        None => to_srgb8(format, texels),
+    }
+}
+
+/// 8-bit sRGB encoded RGBA. UNORM targets already hold sRGB encoded values, see `pipeline::encodes_srgb_in_shader`.
+pub fn to_srgb8(format: Format, texels: &[u8]) -> Result<Vec<u8>, CaptureError> {
+    match format {
+        Format::R8G8B8A8_SRGB | Format::R8G8B8A8_UNORM => Ok(texels.to_vec()),
+        Format::B8G8R8A8_SRGB | Format::B8G8R8A8_UNORM => Ok(texels
+            .chunks_exact(4)
+            .flat_map(|t| [t[2], t[1], t[0], t[3]])
+            .collect()),
+        Format::R16G16B16A16_SFLOAT | Format::R32G32B32A32_SFLOAT => Ok(encode_srgb8(&to_linear(format, texels)?)),
+        _ => Err(CaptureError::UnsupportedFormat(format)),
+    }
+}
+
+/// Linear float RGBA, what the shaders wrote before the target encoded it.
+pub fn to_linear(format: Format, texels: &[u8]) -> Result<Vec<[f32; 4]>, CaptureError> {
+    let unorm = |t: u8| t as f32 / 255.0;
+    let srgb = |t: u8| srgb_to_linear(t as f32 / 255.0);
+
+    match format {
+        Format::R8G8B8A8_SRGB | Format::R8G8B8A8_UNORM => Ok(texels.chunks_exact(4).map(|t| [srgb(t[0]), srgb(t[1]), srgb(t[2]), unorm(t[3])]).collect()),
+        Format::B8G8R8A8_SRGB | Format::B8G8R8A8_UNORM => Ok(texels.chunks_exact(4).map(|t| [srgb(t[2]), srgb(t[1]), srgb(t[0]), unorm(t[3])]).collect()),
+        Format::R32G32B32A32_SFLOAT => Ok(texels
+            .chunks_exact(16)
+            .map(|t| {
+                let channel = |i: usize| f32::from_le_bytes([t[i * 4], t[i * 4 + 1], t[i * 4 + 2], t[i * 4 + 3]]);
+                [channel(0), channel(1), channel(2), channel(3)]
+            })
+            .collect()),
+        Format::R16G16B16A16_SFLOAT => Ok(texels
+            .chunks_exact(8)
+            .map(|t| {
+                let channel = |i: usize| f16::from_bits(u16::from_le_bytes([t[i * 2], t[i * 2 + 1]])).to_f32();
+                [channel(0), channel(1), channel(2), channel(3)]
+            })
+            .collect()),
+        _ => Err(CaptureError::UnsupportedFormat(format)),
+    }
+}
+
+fn encode_srgb8(pixels: &[[f32; 4]]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|&[r, g, b, a]| [
+            encode_unorm8(linear_to_srgb(r)),
+            encode_unorm8(linear_to_srgb(g)),
+            encode_unorm8(linear_to_srgb(b)),
+            encode_unorm8(a),
+        ])
+        .collect()
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn encode_unorm8(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+use vulkano::format::Format;
+use crate::capture;
+use crate::capture::{CaptureError, ImageFileFormat};
+use crate::capture::y4m::Y4mWriter;
+use crate::render_core::tonemap::ToneMapping;
+
+/// Destination of an exported animation: numbered image files or a single Y4M stream.
+pub enum SequenceWriter {
+    Images { path: PathBuf },
+    Y4m(Y4mWriter),
+}
+
+impl SequenceWriter {
+    /// A `.y4m` path becomes a video stream, a `.png`/`.exr` path a numbered sequence (`shot.png` -> `shot_00000.png`, ...).
+    pub fn create(path: &Path, extent: [u32; 2], fps: f32) -> Result<Self, CaptureError> {
+        if is_y4m(path) {
+            return Ok(SequenceWriter::Y4m(Y4mWriter::create(path, extent, fps)?));
+        }
+
+        ImageFileFormat::from_path(path)?;
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        Ok(SequenceWriter::Images { path: path.to_path_buf() })
+    }
+
+    /// `tone_mapping` applies to 8-bit output like in `capture::save`.
+    pub fn write_frame(
+        &mut self,
+        index: u32,
+        extent: [u32; 2],
+        format: Format,
+        texels: &[u8],
+        tone_mapping: Option<&ToneMapping>,
+    ) -> Result<(), CaptureError> {
+        match self {
+            SequenceWriter::Images { path, .. } => capture::save(&numbered_path(path, index), extent, format, texels, tone_mapping),
+            SequenceWriter::Y4m(writer) => Ok(writer.write_frame(&capture::to_display_srgb8(format, texels, tone_mapping)?)?),
+        }
+    }
+
+    pub fn finish(self) -> Result<(), CaptureError> {
+        match self {
+            SequenceWriter::Images { .. } => Ok(()),
+            SequenceWriter::Y4m(writer) => Ok(writer.finish()?),
+        }
+    }
+}
+
+pub fn is_y4m(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e.eq_ignore_ascii_case("y4m"))
+}
+
+fn numbered_path(path: &Path, index: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_{:05}.{}", stem, index, extension))
+}
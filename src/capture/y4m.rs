@@ -0,0 +1,48 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// Uncompressed YUV4MPEG2 stream, 4:4:4 with BT.709 limited range, readable by ffmpeg and most players.
+pub struct Y4mWriter {
+    out: BufWriter<File>,
+    extent: [u32; 2],
+}
+
+impl Y4mWriter {
+    pub fn create(path: &Path, extent: [u32; 2], fps: f32) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(
+            out,
+            "YUV4MPEG2 W{} H{} F{}:1000 Ip A1:1 C444 XCOLORRANGE=LIMITED",
+            extent[0],
+            extent[1],
+            (fps * 1000.0).round() as u32
+        )?;
+        Ok(Self { out, extent })
+    }
+
+    /// Appends one frame of 8-bit sRGB encoded RGBA pixels.
+    pub fn write_frame(&mut self, rgba: &[u8]) -> std::io::Result<()> {
+        let pixel_count = (self.extent[0] * self.extent[1]) as usize;
+        let mut planes = vec![0u8; pixel_count * 3];
+        let (y_plane, uv_planes) = planes.split_at_mut(pixel_count);
+        let (u_plane, v_plane) = uv_planes.split_at_mut(pixel_count);
+
+        for (i, pixel) in rgba.chunks_exact(4).enumerate() {
+            let [r, g, b] = [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+            let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            let u = (b - y) / 1.8556;
+            let v = (r - y) / 1.5748;
+            y_plane[i] = (16.0 + y * 219.0 / 255.0).round() as u8;
+            u_plane[i] = (128.0 + u * 224.0 / 255.0).round() as u8;
+            v_plane[i] = (128.0 + v * 224.0 / 255.0).round() as u8;
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&planes)
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,368 @@
+use std::path::PathBuf;
+use std::process::exit;
+use std::str::FromStr;
+use crate::capture::ImageFileFormat;
+use crate::capture::sequence;
+use vulkano::swapchain::PresentMode;
+use crate::render_core::accumulation::DEFAULT_SAMPLES;
+use crate::render_core::frames::MAX_FRAMES_IN_FLIGHT;
+use crate::render_core::pipeline::{DEFAULT_FOV, MAX_FOV, MIN_FOV};
+use crate::render_core::pipeline_cache;
+use crate::render_core::quality::QualitySettings;
+use crate::render_core::variants;
+use crate::render_core::variants::Variant;
+use crate::render_core::tonemap::ToneMapping;
+use crate::render_core::vulkano_core::{DeviceSelector, SwapchainSettings};
+use crate::scene::MAX_PARAMS;
+use crate::shadertoy::MAX_CHANNELS;
+
+/// Samples per pixel of path-traced `--render` and `--export` unless `--spp` asks for another number.
+const DEFAULT_SPP: u32 = 1024;
+
+const USAGE: &str = "\
+Usage: vulkan-raymarching [OPTIONS]
+
+Without --render or --export the interactive viewer is started.
+Viewer keys: F12 saves a PNG screenshot, F11 saves an EXR screenshot, Tab switches between the 3D scene, the 2D SDF mode
+(pan with WASD, zoom with the mouse wheel) and, with --shadertoy, the Shadertoy project, V toggles vsync, T cycles the tone mapping operator, E toggles auto exposure,
+- and + change the exposure. P pauses the scene time, [ and ] halve and double its speed, holding Left or Right scrubs it.
+Q cycles the quality presets; in the 3D scene the mouse wheel zooms by changing the field of view, 1 to 4 toggle shadows,
+ambient occlusion, fog and terrain, 5 cycles the debug views (legend in the window title) and 6 toggles path tracing.
+While the camera rests and the time is paused the 3D scene accumulates jittered frames into a clean image; R toggles that.
+
+Options:
+  --scene <FILE>              Scene to render, .ron or .json [default: the built-in scenes/default.ron]
+  --interpret-scene           Evaluate the scene with the built-in interpreting shader instead of generating and compiling one
+  --shader-dir <DIR>          Compile the shaders from this directory at runtime; the viewer reloads them when they change
+                              [default: shaders/ of the source tree if it exists, otherwise the built-in shaders]
+  --2d                        Start the viewer in the 2D SDF mode (shaders/raymarching-2d.frag)
+  --shadertoy <FILE>          Start the viewer with a Shadertoy project (.ron or .json) or a single mainImage() GLSL file
+  --channel <N>=<FILE>        Bind a .png or .exr file to iChannelN (0 to 3) of the Shadertoy Image pass; repeatable
+  --render <FILE>             Render a single frame without a window and save it as .png or .exr
+  --export <FILE>             Render an animation with a fixed clock; .y4m writes a video stream,
+                              .png/.exr a numbered image sequence (frame.png -> frame_00000.png, ...)
+  --frames <N>                Number of frames to export [default: 120]
+  --fps <FPS>                 Frame rate of the export clock [default: 30]
+  --turntable <X,Y,Z>         Export: orbit once around this point, looking at it
+  --flythrough <X,Y,Z>        Export: fly in a straight line from --camera to this point
+  --cpu                       Use the CPU reference renderer for --render/--export instead of Vulkan
+  --resolution <WxH>          Output resolution for --render/--export, window size for the viewer [default render: 1920x1080]
+  --camera <X,Y,Z>            Camera position [default: 0,1.6,-5]
+  --look <PITCH,YAW>          Camera orientation in degrees [default: 0,90]
+  --fov <DEGREES>             Vertical field of view, 10 to 150 [default: 90]
+  --quality <PRESET>[,<NAME>=<VALUE>...]
+                              Raymarching quality: low, medium, high or ultra, optionally changing steps, far-plane,
+                              hit-epsilon, normal-epsilon, shadow-steps, shadow-softness, ao-taps or bounces, e.g.
+                              medium,shadow-steps=40; the viewer keeps the changes when Q switches presets [default: high]
+  --features <LIST>           Features compiled into the viewer's 3D shader, comma separated: shadows, ao, fog and terrain,
+                              or none [default: shadows,ao,terrain]
+  --path-trace                Path trace the 3D scene with bounces (see --quality), sky and sun light instead of shading it;
+                              converges while the viewer accumulates, --render/--export average --spp passes
+  --debug-view <VIEW>         Show a debug view in the 3D viewer instead of the shaded scene, with its legend in the window
+                              title: off, steps, distance, normals, shadow, ao, depth, material or misses [default: off]
+  --pipeline-cache <FILE>     Where the viewer keeps the compiled pipelines between runs [default:
+                              vulkan-raymarching/pipeline-cache.bin in $XDG_CACHE_HOME, ~/.cache or %LOCALAPPDATA%]
+  --no-pipeline-cache         Don't load or save the pipeline cache
+  --no-accumulation           Start the viewer without accumulating frames while the camera rests and the time is paused
+  --accumulate-samples <N>    Frames the viewer accumulates before it stops rendering a resting camera [default: 1024]
+  --spp <N>                   Samples per pixel of --render/--export with --path-trace [default: 1024]
+  --time <SECONDS>            Scene time of the first frame, the shaders' frame.time [default: 0]
+  --param <INDEX>=<VALUE>     Set the scene's user parameter INDEX (0 to 15), read with param(INDEX); repeatable
+  --tonemap <OPERATOR>        Tone mapping of the HDR frame for the viewer, PNG and Y4M: reinhard, aces or agx [default: aces]
+  --exposure <EV>             Exposure in stops, a compensation with --auto-exposure [default: 0]
+  --auto-exposure             Expose each frame so its average luminance becomes middle grey
+  --screenshot-dir <DIR>      Where the viewer saves screenshots [default: screenshots]
+  --present-mode <MODE>       Viewer present mode: fifo (vsync), fifo-relaxed, mailbox or immediate; falls back to a
+                              supported one [default: fifo]
+  --frames-in-flight <N>      Frames the viewer records ahead of the GPU, 1 to 3; RUST_LOG=info logs frame and wait times
+                              [default: 2]
+  --image-count <N>           Number of swapchain images, clamped to what the surface allows [default: its minimum]
+  --device <DEVICE>           Vulkan device to use: an index from --list-devices, cpu, discrete, integrated, virtual,
+                              or part of the device name [default: $VULKAN_RAYMARCHING_DEVICE, otherwise the best GPU]
+  --list-devices              Print the Vulkan devices with their drivers and queue families, then exit
+  --validation                Enable the Vulkan validation layer and log its messages (set RUST_LOG=info for more)
+                              [default: on in debug builds]
+  --no-validation             Disable the validation layer in debug builds
+  -h, --help                  Print this help
+";
+
+pub struct Options {
+    pub scene: Option<PathBuf>,
+    pub interpret_scene: bool,
+    pub shader_dir: Option<PathBuf>,
+    pub sdf_2d: bool,
+    pub shadertoy: Option<PathBuf>,
+    /// `--channel` textures of the Shadertoy Image pass, by channel.
+    pub channels: Vec<(usize, PathBuf)>,
+    pub render: Option<PathBuf>,
+    pub export: Option<PathBuf>,
+    pub frames: u32,
+    pub fps: f32,
+    pub turntable: Option<[f32; 3]>,
+    pub flythrough: Option<[f32; 3]>,
+    pub cpu: bool,
+    pub resolution: Option<[u32; 2]>,
+    pub camera_position: [f32; 3],
+    pub pitch_yaw: [f32; 2],
+    pub fov: f32,
+    pub quality: QualitySettings,
+    /// `--features` and `--debug-view`.
+    pub variant: Variant,
+    /// `None` with `--no-pipeline-cache` or without a cache directory.
+    pub pipeline_cache: Option<PathBuf>,
+    /// Whether the viewer accumulates frames while the camera rests and the time is paused; `--no-accumulation` turns it off.
+    pub accumulation: bool,
+    /// Samples the viewer accumulates before it stops.
+    pub accumulate_samples: u32,
+    /// Samples per pixel of path-traced `--render` and `--export`.
+    pub spp: u32,
+    pub start_time: f32,
+    /// `--param` overrides of the scene's `params`, by index.
+    pub params: Vec<(usize, f32)>,
+    pub screenshot_dir: PathBuf,
+    pub tone_mapping: ToneMapping,
+    pub swapchain: SwapchainSettings,
+    pub frames_in_flight: usize,
+    pub device: Option<DeviceSelector>,
+    pub list_devices: bool,
+    pub validation: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scene: None,
+            interpret_scene: false,
+            shader_dir: None,
+            sdf_2d: false,
+            shadertoy: None,
+            channels: Vec::new(),
+            render: None,
+            export: None,
+            frames: 120,
+            fps: 30.0,
+            turntable: None,
+            flythrough: None,
+            cpu: false,
+            resolution: None,
+            camera_position: [0.0, 1.6, -5.0],
+            pitch_yaw: [0.0, 90.0],
+            fov: DEFAULT_FOV,
+            quality: QualitySettings::default(),
+            variant: Variant::default(),
+            pipeline_cache: pipeline_cache::default_path(),
+            accumulation: true,
+            accumulate_samples: DEFAULT_SAMPLES,
+            spp: DEFAULT_SPP,
+            start_time: 0.0,
+            params: Vec::new(),
+            screenshot_dir: PathBuf::from("screenshots"),
+            tone_mapping: ToneMapping::default(),
+            swapchain: SwapchainSettings::default(),
+            frames_in_flight: 2,
+            device: None,
+            list_devices: false,
+            validation: cfg!(debug_assertions),
+        }
+    }
+}
+
+pub fn parse() -> Options {
+    match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            exit(2);
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut spp_given = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} expects a value", arg));
+        match arg.as_str() {
+            "--scene" => options.scene = Some(PathBuf::from(value()?)),
+            "--interpret-scene" => options.interpret_scene = true,
+            "--shader-dir" => options.shader_dir = Some(PathBuf::from(value()?)),
+            "--2d" => options.sdf_2d = true,
+            "--shadertoy" => options.shadertoy = Some(PathBuf::from(value()?)),
+            "--channel" => options.channels.push(parse_channel(&value()?)?),
+            "--render" => {
+                let path = PathBuf::from(value()?);
+                ImageFileFormat::from_path(&path).map_err(|e| e.to_string())?;
+                options.render = Some(path);
+            }
+            "--export" => {
+                let path = PathBuf::from(value()?);
+                if !sequence::is_y4m(&path) {
+                    ImageFileFormat::from_path(&path).map_err(|e| e.to_string())?;
+                }
+                options.export = Some(path);
+            }
+            "--frames" => options.frames = parse_value(&value()?)?,
+            "--fps" => {
+                options.fps = parse_value(&value()?)?;
+                if options.fps <= 0.0 {
+                    return Err("--fps must be positive".to_string());
+                }
+            }
+            "--turntable" => options.turntable = Some(parse_list(&value()?, ',')?),
+            "--flythrough" => options.flythrough = Some(parse_list(&value()?, ',')?),
+            "--cpu" => options.cpu = true,
+            "--resolution" => {
+                let [width, height] = parse_list::<u32, 2>(&value()?, 'x')?;
+                if width == 0 || height == 0 {
+                    return Err("--resolution must not be zero".to_string());
+                }
+                options.resolution = Some([width, height]);
+            }
+            "--camera" => options.camera_position = parse_list(&value()?, ',')?,
+            "--look" => options.pitch_yaw = parse_list(&value()?, ',')?,
+            "--fov" => {
+                options.fov = parse_value(&value()?)?;
+                if !(MIN_FOV..=MAX_FOV).contains(&options.fov) {
+                    return Err(format!("--fov must be between {} and {}", MIN_FOV, MAX_FOV));
+                }
+            }
+            "--quality" => options.quality = value()?.parse()?,
+            "--features" => {
+                let features = variants::parse_features(&value()?)?;
+                options.variant = options.variant.with_features(&features);
+            }
+            "--debug-view" => options.variant.debug_view = value()?.parse()?,
+            "--path-trace" => options.variant.path_tracing = true,
+            "--accumulate-samples" => {
+                options.accumulate_samples = parse_value(&value()?)?;
+                if options.accumulate_samples == 0 {
+                    return Err("--accumulate-samples must not be zero".to_string());
+                }
+            }
+            "--spp" => {
+                options.spp = parse_value(&value()?)?;
+                if options.spp == 0 {
+                    return Err("--spp must not be zero".to_string());
+                }
+                spp_given = true;
+            }
+            "--pipeline-cache" => options.pipeline_cache = Some(PathBuf::from(value()?)),
+            "--no-pipeline-cache" => options.pipeline_cache = None,
+            "--no-accumulation" => options.accumulation = false,
+            "--time" => {
+                options.start_time = parse_value(&value()?)?;
+                if options.start_time < 0.0 {
+                    return Err("--time must not be negative".to_string());
+                }
+            }
+            "--param" => options.params.push(parse_param(&value()?)?),
+            "--screenshot-dir" => options.screenshot_dir = PathBuf::from(value()?),
+            "--tonemap" => options.tone_mapping.operator = value()?.parse()?,
+            "--exposure" => options.tone_mapping.exposure = parse_value(&value()?)?,
+            "--auto-exposure" => options.tone_mapping.auto_exposure = true,
+            "--present-mode" => options.swapchain.present_mode = parse_present_mode(&value()?)?,
+            "--frames-in-flight" => {
+                options.frames_in_flight = parse_value(&value()?)?;
+                if !(1..=MAX_FRAMES_IN_FLIGHT).contains(&options.frames_in_flight) {
+                    return Err(format!("--frames-in-flight must be between 1 and {}", MAX_FRAMES_IN_FLIGHT));
+                }
+            }
+            "--image-count" => {
+                let count = parse_value(&value()?)?;
+                if count == 0 {
+                    return Err("--image-count must not be zero".to_string());
+                }
+                options.swapchain.image_count = Some(count);
+            }
+            "--device" => options.device = Some(value()?.parse()?),
+            "--list-devices" => options.list_devices = true,
+            "--validation" => options.validation = true,
+            "--no-validation" => options.validation = false,
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                exit(0);
+            }
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+
+    if options.device.is_none() {
+        options.device = DeviceSelector::from_env();
+    }
+
+    if options.render.is_some() && options.export.is_some() {
+        return Err("--render and --export can't be combined".to_string());
+    }
+    if options.sdf_2d && (options.render.is_some() || options.export.is_some()) {
+        return Err("--2d is only supported by the viewer".to_string());
+    }
+    let offline = options.render.is_some() || options.export.is_some();
+    // Offline renders only path trace, the other parts of a variant are for the viewer.
+    let rasterized = Variant { path_tracing: false, ..options.variant };
+    if rasterized != Variant::default() && offline {
+        return Err("--features and --debug-view are only supported by the viewer".to_string());
+    }
+    if options.variant.path_tracing && options.cpu && offline {
+        return Err("--path-trace needs Vulkan, the CPU reference renderer doesn't path trace".to_string());
+    }
+    if spp_given && !(options.variant.path_tracing && offline) {
+        return Err("--spp is only supported by --render and --export with --path-trace".to_string());
+    }
+    if options.shadertoy.is_some() && (options.render.is_some() || options.export.is_some() || options.sdf_2d) {
+        return Err("--shadertoy is only supported by the viewer and can't be combined with --2d".to_string());
+    }
+    if !options.channels.is_empty() && options.shadertoy.is_none() {
+        return Err("--channel needs --shadertoy".to_string());
+    }
+    if options.turntable.is_some() && options.flythrough.is_some() {
+        return Err("--turntable and --flythrough can't be combined".to_string());
+    }
+
+    Ok(options)
+}
+
+fn parse_list<T: FromStr + Copy + Default, const N: usize>(value: &str, separator: char) -> Result<[T; N], String> {
+    let parts: Vec<&str> = value.split(separator).collect();
+    if parts.len() != N {
+        return Err(format!("'{}' should have {} values separated by '{}'", value, N, separator));
+    }
+
+    let mut result = [T::default(); N];
+    for (slot, part) in result.iter_mut().zip(parts) {
+        *slot = parse_value(part)?;
+    }
+    Ok(result)
+}
+
+fn parse_param(value: &str) -> Result<(usize, f32), String> {
+    let (index, param) = value.split_once('=').ok_or_else(|| format!("'{}' should be <INDEX>=<VALUE>", value))?;
+    let index: usize = parse_value(index)?;
+    if index >= MAX_PARAMS {
+        return Err(format!("--param index must be below {}, got {}", MAX_PARAMS, index));
+    }
+    Ok((index, parse_value(param)?))
+}
+
+fn parse_channel(value: &str) -> Result<(usize, PathBuf), String> {
+    let (index, path) = value.split_once('=').ok_or_else(|| format!("'{}' should be <N>=<FILE>", value))?;
+    let index: usize = parse_value(index)?;
+    if index >= MAX_CHANNELS {
+        return Err(format!("--channel must be below {}, got {}", MAX_CHANNELS, index));
+    }
+    Ok((index, PathBuf::from(path)))
+}
+
+fn parse_present_mode(value: &str) -> Result<PresentMode, String> {
+    match value.trim().to_lowercase().as_str() {
+        "fifo" => Ok(PresentMode::Fifo),
+        "fifo-relaxed" => Ok(PresentMode::FifoRelaxed),
+        "mailbox" => Ok(PresentMode::Mailbox),
+        "immediate" => Ok(PresentMode::Immediate),
+        _ => Err(format!("'{}' is not a present mode, expected fifo, fifo-relaxed, mailbox or immediate", value)),
+    }
+}
+
+fn parse_value<T: FromStr>(value: &str) -> Result<T, String> {
+    value.trim().parse().map_err(|_| format!("'{}' is not a valid number", value))
+}
@@ -0,0 +1,152 @@
+//! Golden-image regression tests for `shaders/raymarching-3d.frag`.
+//!
+//! Fixed views of the default scene are rendered and compared against `tests/golden/<view>.png`.
+//! On a mismatch the actual image and an amplified difference image are written to `target/golden-diff/`.
+//! After an intended visual change, regenerate the references with `UPDATE_GOLDEN=1 cargo test golden`.
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use nalgebra::Vector3;
+use vulkano::format::Format;
+use crate::animation::CameraPose;
+use crate::application::{direction_from_pitch_yaw, still_constants};
+use crate::capture;
+use crate::logging;
+use crate::reference;
+use crate::render_core::headless::HeadlessRenderer;
+use crate::render_core::vulkano_core;
+use crate::render_core::vulkano_core::DeviceSelector;
+use crate::scene::Scene;
+
+const EXTENT: [u32; 2] = [96, 54];
+
+struct View {
+    name: &'static str,
+    position: [f32; 3],
+    pitch_yaw: [f32; 2],
+}
+
+const VIEWS: [View; 4] = [
+    View { name: "default", position: [0.0, 1.6, -5.0], pitch_yaw: [0.0, 90.0] },
+    View { name: "dong", position: [0.0, 1.6, -5.0], pitch_yaw: [10.0, 270.0] },
+    View { name: "close_up", position: [1.5, 1.5, -2.0], pitch_yaw: [7.0, 307.0] },
+    View { name: "terrain_far", position: [0.0, 25.0, -60.0], pitch_yaw: [25.0, 270.0] },
+];
+
+struct Tolerance {
+    /// Largest per-channel difference, in 8-bit steps, a pixel may have and still match.
+    max_channel_diff: u8,
+    /// Fraction of pixels allowed to exceed `max_channel_diff`, for noise-driven terrain edges.
+    max_mismatch_ratio: f32,
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("golden-diff")
+}
+
+fn pose(view: &View) -> CameraPose {
+    CameraPose {
+        position: Vector3::from(view.position),
+        front: direction_from_pitch_yaw(view.pitch_yaw),
+    }
+}
+
+fn read_png(path: &Path) -> Vec<u8> {
+    let decoder = png::Decoder::new(File::open(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e)));
+    let mut reader = decoder.read_info().unwrap();
+    let mut rgba = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgba).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{}: expected an RGBA reference", path.display());
+    assert_eq!([info.width, info.height], EXTENT, "{}: reference has the wrong size", path.display());
+    rgba.truncate(info.buffer_size());
+    rgba
+}
+
+/// Compares `actual` (8-bit sRGB RGBA) with the stored reference of `view`, or replaces the reference with `UPDATE_GOLDEN` set.
+/// Returns a description of the mismatch instead of panicking, so every view gets checked.
+fn compare(view: &View, suffix: &str, actual: &[u8], tolerance: &Tolerance) -> Result<(), String> {
+    let reference_path = golden_dir().join(format!("{}.png", view.name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        capture::write_png(&reference_path, EXTENT, actual).unwrap();
+        return Ok(());
+    }
+
+    let expected = read_png(&reference_path);
+    let mut diff = Vec::with_capacity(actual.len());
+    let mut mismatches = 0;
+    let mut worst = 0;
+    for (a, e) in actual.chunks_exact(4).zip(expected.chunks_exact(4)) {
+        let channel_diff = [0, 1, 2].map(|i| a[i].abs_diff(e[i]));
+        let pixel_diff = channel_diff.into_iter().max().unwrap();
+        worst = worst.max(pixel_diff);
+        if pixel_diff > tolerance.max_channel_diff {
+            mismatches += 1;
+        }
+        diff.extend(channel_diff.map(|d| d.saturating_mul(8)));
+        diff.push(255);
+    }
+
+    let ratio = mismatches as f32 / (EXTENT[0] * EXTENT[1]) as f32;
+    if ratio <= tolerance.max_mismatch_ratio {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(diff_dir()).unwrap();
+    let actual_path = diff_dir().join(format!("{}-{}-actual.png", view.name, suffix));
+    let diff_path = diff_dir().join(format!("{}-{}-diff.png", view.name, suffix));
+    capture::write_png(&actual_path, EXTENT, actual).unwrap();
+    capture::write_png(&diff_path, EXTENT, &diff).unwrap();
+    Err(format!(
+        "{}: {:.2}% of pixels differ by more than {} (worst {}), see {} and {}",
+        view.name,
+        ratio * 100.0,
+        tolerance.max_channel_diff,
+        worst,
+        actual_path.display(),
+        diff_path.display()
+    ))
+}
+
+fn check_all(suffix: &str, tolerance: &Tolerance, mut render: impl FnMut(&View) -> Vec<u8>) {
+    let failures: Vec<String> = VIEWS
+        .iter()
+        .filter_map(|view| compare(view, suffix, &render(view), tolerance).err())
+        .collect();
+    assert!(failures.is_empty(), "golden images differ:\n{}", failures.join("\n"));
+}
+
+#[test]
+fn golden_cpu_reference() {
+    let scene = Scene::default_scene().compile().unwrap();
+    let tolerance = Tolerance { max_channel_diff: 2, max_mismatch_ratio: 0.002 };
+    check_all("cpu", &tolerance, |view| {
+        let color = reference::render(&still_constants(pose(view), EXTENT), &scene, EXTENT);
+        let texels: Vec<u8> = color.iter().flatten().flat_map(|c| c.to_le_bytes()).collect();
+        capture::to_srgb8(Format::R32G32B32A32_SFLOAT, &texels).unwrap()
+    });
+}
+
+/// Needs a Vulkan implementation, e.g. lavapipe on CI: `cargo test golden -- --ignored`.
+/// `VULKAN_RAYMARCHING_DEVICE` picks the device, like `--device`. Runs with the validation layer if it is installed
+/// and fails on validation errors, which are logged with `RUST_LOG=error` or more verbose.
+#[test]
+#[ignore]
+fn golden_vulkan_headless() {
+    logging::init();
+    let device = DeviceSelector::from_env();
+    let renderer = HeadlessRenderer::new(EXTENT, Format::R8G8B8A8_SRGB, &Scene::default_scene(), true, None, device.as_ref(), true)
+        .unwrap_or_else(|e| panic!("{}", e));
+    let tolerance = Tolerance { max_channel_diff: 8, max_mismatch_ratio: 0.02 };
+    check_all("vulkan", &tolerance, |view| {
+        let texels = renderer.render(still_constants(pose(view), EXTENT));
+        capture::to_srgb8(renderer.format(), &texels).unwrap()
+    });
+    drop(renderer);
+    let errors = vulkano_core::validation_error_count();
+    assert_eq!(errors, 0, "{} Vulkan validation errors, see the log", errors);
+}
@@ -0,0 +1,32 @@
+//! Minimal `log` backend that writes to stderr. `RUST_LOG` sets the most verbose level shown:
+//! `off`, `error`, `warn` (the default), `info`, `debug` or `trace`.
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+pub const LEVEL_ENV: &str = "RUST_LOG";
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Installs the logger; later calls, e.g. from several tests, keep the first one.
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        let level = std::env::var(LEVEL_ENV).ok().and_then(|value| value.trim().parse().ok());
+        log::set_max_level(level.unwrap_or(LevelFilter::Warn));
+    }
+}
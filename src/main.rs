@@ -1,9 +1,26 @@
 mod window;
+mod animation;
 mod render_core;
 mod application;
+mod capture;
+mod cli;
+mod reference;
+mod scene;
+mod shadertoy;
+mod logging;
+#[cfg(test)]
+mod golden;
 
 fn main() {
-    application::run();
+    logging::init();
+    let options = cli::parse();
+    if options.list_devices {
+        application::list_devices();
+    } else if let Some(path) = &options.render {
+        application::render_still(&options, path);
+    } else if let Some(path) = &options.export {
+        application::export_sequence(&options, path);
+    } else {
+        application::run(&options);
+    }
 }
-
-
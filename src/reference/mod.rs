@@ -0,0 +1,323 @@
+//! CPU port of `shaders/raymarching-3d.frag`. Keep it in sync with the shader: it is the ground truth GPU output is compared against.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+use crate::render_core::pipeline::Constants;
+use crate::render_core::quality::Quality;
+use crate::scene;
+use crate::scene::{CompiledScene, Instruction, MaterialData, MAX_DOMAINS, MAX_STACK};
+
+const TILE_SIZE: u32 = 32;
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+#[allow(clippy::excessive_precision)]
+pub fn hash3(p: Vector3<f32>) -> Vector3<f32> {
+    let p = Vector3::new(
+        p.dot(&Vector3::new(127.1, 311.7, 74.7)),
+        p.dot(&Vector3::new(269.5, 183.3, 246.1)),
+        p.dot(&Vector3::new(113.5, 271.9, 124.6)),
+    );
+
+    p.map(|c| -1.0 + 2.0 * fract(c.sin() * 43758.5453123))
+}
+
+fn hash2(p: Vector2<f32>) -> Vector2<f32> {
+    hash3(Vector3::new(p.x, p.y, 0.0)).xy()
+}
+
+/// Gradient noise, value only.
+pub fn noised(x: Vector2<f32>) -> f32 {
+    let i = x.map(f32::floor);
+    let f = x.map(fract);
+
+    let u = f.map(|f| f * f * f * (f * (f * 6.0 - 15.0) + 10.0));
+
+    let ga = hash2(i + Vector2::new(0.0, 0.0));
+    let gb = hash2(i + Vector2::new(1.0, 0.0));
+    let gc = hash2(i + Vector2::new(0.0, 1.0));
+    let gd = hash2(i + Vector2::new(1.0, 1.0));
+
+    let va = ga.dot(&(f - Vector2::new(0.0, 0.0)));
+    let vb = gb.dot(&(f - Vector2::new(1.0, 0.0)));
+    let vc = gc.dot(&(f - Vector2::new(0.0, 1.0)));
+    let vd = gd.dot(&(f - Vector2::new(1.0, 1.0)));
+
+    va + u.x * (vb - va) + u.y * (vc - va) + u.x * u.y * (va - vb - vc + vd)
+}
+
+fn sd_vertical_capsule(mut p: Vector3<f32>, h: f32, r: f32) -> f32 {
+    p.y -= p.y.clamp(0.0, h);
+    p.norm() - r
+}
+
+fn sd_box(p: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    let q = p.abs() - b;
+    q.sup(&Vector3::zeros()).norm() + q.max().min(0.0)
+}
+
+fn sd_torus(p: Vector3<f32>, t: Vector2<f32>) -> f32 {
+    Vector2::new(p.xz().norm() - t.x, p.y).norm() - t.y
+}
+
+pub fn smin(a: f32, b: f32, k: f32) -> f32 {
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k * (1.0 / 4.0)
+}
+
+pub fn op_u(d1: Vector4<f32>, d2: Vector4<f32>) -> Vector4<f32> {
+    if d1.x < d2.x { d1 } else { d2 }
+}
+
+/// `params` holds max height, offset and frequency.
+pub fn sd_terrain(p: Vector3<f32>, params: Vector4<f32>, snow_color: Vector3<f32>, grass_color: Vector3<f32>) -> Vector4<f32> {
+    let noise_val = noised(p.xz() * params.z);
+    let mut height_factor = (noise_val + 1.0) / 2.0;
+    let d = p.y - (noise_val * params.x - params.y);
+    let color = if height_factor < 0.5 {
+        grass_color
+    } else {
+        height_factor = (height_factor - 0.5) * 2.0;
+        // Same operator precedence as the shader: the height factor is subtracted from every channel.
+        snow_color * height_factor + grass_color.add_scalar(-height_factor)
+    };
+    Vector4::new(d, color.x, color.y, color.z)
+}
+
+fn sd_primitive(ins: &Instruction, materials: &[MaterialData], p: Vector3<f32>) -> Vector4<f32> {
+    let q = (Matrix4::from(ins.inverse_transform) * p.push(1.0)).xyz();
+    let params = Vector4::from(ins.params);
+    let color = Vector4::from(materials[ins.material as usize].color).xyz();
+
+    let mut result = match ins.shape {
+        scene::SHAPE_SPHERE => Vector4::new(q.norm() - params.x, color.x, color.y, color.z),
+        scene::SHAPE_CAPSULE => Vector4::new(sd_vertical_capsule(q, params.x, params.y), color.x, color.y, color.z),
+        scene::SHAPE_BOX => Vector4::new(sd_box(q, params.xyz()), color.x, color.y, color.z),
+        scene::SHAPE_TORUS => Vector4::new(sd_torus(q, params.xy()), color.x, color.y, color.z),
+        scene::SHAPE_PLANE => Vector4::new(q.y, color.x, color.y, color.z),
+        scene::SHAPE_TERRAIN => sd_terrain(q, params, Vector4::from(ins.extra).xyz(), color),
+        _ => Vector4::new(1000.0, color.x, color.y, color.z),
+    };
+    result.x *= ins.scale;
+    result
+}
+
+fn op_repeat(p: Vector3<f32>, period: Vector3<f32>) -> Vector3<f32> {
+    p - period.component_mul(&p.component_div(&period.map(|c| c.max(0.000001))).map(|c| (c + 0.5).floor()))
+}
+
+/// The color part (`.yzw`) of a `map` result.
+fn rgb(result: Vector4<f32>) -> Vector3<f32> {
+    Vector3::new(result.y, result.z, result.w)
+}
+
+fn op_combine(opcode: u32, a: Vector4<f32>, b: Vector4<f32>, k: f32) -> Vector4<f32> {
+    match opcode {
+        scene::OP_SMOOTH_UNION => {
+            let blend = (0.5 + 0.5 * (a.x - b.x) / k).clamp(0.0, 1.0);
+            let color = rgb(a).lerp(&rgb(b), blend);
+            Vector4::new(smin(a.x, b.x, k), color.x, color.y, color.z)
+        }
+        scene::OP_SUBTRACTION => Vector4::new(a.x.max(-b.x), a.y, a.z, a.w),
+        scene::OP_INTERSECTION => if a.x > b.x { a } else { b },
+        _ => op_u(a, b),
+    }
+}
+
+/// Evaluates the scene program: distance to the scene in `.x`, surface color in `.yzw`.
+pub fn map(scene: &CompiledScene, p: Vector3<f32>) -> Vector4<f32> {
+    let mut stack = [Vector4::zeros(); MAX_STACK];
+    let mut top = 0;
+    // Point in the current frame and the accumulated scale.
+    let mut domains = [(p, 1.0); MAX_DOMAINS + 1];
+    let mut domain = 0;
+
+    for ins in &scene.program {
+        let (point, scale) = domains[domain];
+        match ins.opcode {
+            scene::OP_PRIMITIVE => {
+                stack[top] = sd_primitive(ins, &scene.materials, point);
+                stack[top].x *= scale;
+                top += 1;
+            }
+            scene::OP_PUSH_TRANSFORM => {
+                let q = (Matrix4::from(ins.inverse_transform) * point.push(1.0)).xyz();
+                domains[domain + 1] = (q, scale * ins.scale);
+                domain += 1;
+            }
+            scene::OP_PUSH_REPEAT => {
+                domains[domain + 1] = (op_repeat(point, Vector4::from(ins.params).xyz()), scale);
+                domain += 1;
+            }
+            scene::OP_POP_DOMAIN => domain -= 1,
+            _ => {
+                top -= 1;
+                stack[top - 1] = op_combine(ins.opcode, stack[top - 1], stack[top], ins.k * scale);
+            }
+        }
+    }
+
+    stack[0]
+}
+
+/// Signed distance to the scene (`f` in the shader).
+pub fn distance(scene: &CompiledScene, p: Vector3<f32>) -> f32 {
+    map(scene, p).x
+}
+
+pub fn calc_normal(scene: &CompiledScene, p: Vector3<f32>, h: f32) -> Vector3<f32> {
+    let xyy = Vector3::new(1.0, -1.0, -1.0);
+    let yyx = Vector3::new(-1.0, -1.0, 1.0);
+    let yxy = Vector3::new(-1.0, 1.0, -1.0);
+    let xxx = Vector3::new(1.0, 1.0, 1.0);
+
+    (xyy * distance(scene, p + xyy * h)
+        + yyx * distance(scene, p + yyx * h)
+        + yxy * distance(scene, p + yxy * h)
+        + xxx * distance(scene, p + xxx * h)).normalize()
+}
+
+pub fn calculate_shadow(scene: &CompiledScene, point: Vector3<f32>, light: Vector3<f32>, max_distance: f32, steps: u32, softness: f32) -> f32 {
+    let mut t = 0.02;
+    let mut result: f32 = 1.0;
+    for _ in 0..steps {
+        if t >= max_distance {
+            break;
+        }
+        let d = distance(scene, point + light * t);
+        result = result.min(softness * d / t);
+        if result < 0.004 {
+            break;
+        }
+        t += d;
+    }
+    result
+}
+
+pub fn calculate_ao(scene: &CompiledScene, point: Vector3<f32>, normal: Vector3<f32>, taps: u32) -> f32 {
+    let start = 0.01;
+    let step = 0.03;
+    let mut ao = 0.0;
+    for i in 0..taps {
+        let i = i as f32;
+        ao += 1.0 / i.exp2() * i * step - distance(scene, point + normal * i * (start + step));
+    }
+    1.0 - 5.0 * ao
+}
+
+/// Returns the hit position and surface color.
+pub fn march(scene: &CompiledScene, quality: &Quality, ray: Vector3<f32>, start: Vector3<f32>) -> Option<(Vector3<f32>, Vector3<f32>)> {
+    let mut dist = 0.0;
+    for _ in 0..quality.max_steps {
+        if dist >= quality.far_plane {
+            break;
+        }
+        let pos = start + ray * dist;
+        let result = map(scene, pos);
+        let d = result.x;
+        if d < quality.hit_epsilon * dist {
+            return Some((pos, rgb(result)));
+        }
+        dist += d;
+    }
+    None
+}
+
+/// `main()` of the shader for one fragment; `frag_coord` is `gl_FragCoord.xy`, i.e. pixel centers.
+pub fn shade(constants: &Constants, scene: &CompiledScene, frag_coord: Vector2<f32>) -> Vector4<f32> {
+    let resolution = Vector2::from(constants.resolution);
+    let view = Matrix4::from(constants.view_matrix);
+
+    let quality = &constants.quality;
+    let camera_fov = constants.fov;
+    let aspect_ratio = Vector2::new(resolution.x / resolution.y, 1.0);
+    let mut uv = (frag_coord + Vector2::from(constants.jitter)).component_div(&resolution) * 2.0 - Vector2::repeat(1.0);
+    uv = uv.component_mul(&aspect_ratio);
+    uv.y = -uv.y;
+
+    let origin = Vector4::from(constants.camera_position).xyz();
+    let ray = (view * Vector4::new(uv.x, uv.y, 1.0 / (camera_fov.to_radians() / 2.0).tan(), 1.0).normalize()).xyz();
+
+    match march(scene, quality, ray, origin) {
+        Some((hit, color)) => {
+            let normal = calc_normal(scene, hit, quality.normal_epsilon);
+            let ao = calculate_ao(scene, hit, normal, quality.ao_taps).clamp(0.1, 1.0);
+
+            let mut lit = Vector3::zeros();
+            for light in &scene.lights {
+                let position = Vector4::from(light.position);
+                let mut to_light = position.xyz();
+                let mut attenuation = 1.0;
+                let mut shadow_distance: f32 = 10.0;
+                if position.w != 0.0 {
+                    to_light = position.xyz() - hit;
+                    let light_distance = to_light.norm();
+                    to_light /= light_distance;
+                    attenuation = 1.0 / (light_distance * light_distance);
+                    shadow_distance = shadow_distance.min(light_distance);
+                }
+
+                let diffuse = normal.dot(&to_light).clamp(0.0, 1.0);
+                let specular = normal.dot(&(to_light - ray)).clamp(0.0, 1.0).powf(16.0);
+                let shadow = calculate_shadow(scene, hit, to_light, shadow_distance, quality.shadow_steps, quality.shadow_softness).clamp(0.2, 1.0);
+                let radiance = Vector4::from(light.color).xyz() * attenuation;
+                lit += (0.7 * color).component_mul(&radiance) * diffuse * shadow * ao;
+                lit += 0.04 * radiance * specular;
+            }
+
+            let ambient_light: f32 = 0.5;
+            let rgb: Vector3<f32> = lit + 0.2 * color * ambient_light;
+            Vector4::new(rgb.x, rgb.y, rgb.z, 1.0)
+        }
+        None => Vector4::new(0.5, 0.5, 0.5, 1.0),
+    }
+}
+
+/// Renders a full frame on all cores, tile by tile. Returns `f_color` per pixel, row-major from the top-left.
+pub fn render(constants: &Constants, scene: &CompiledScene, extent: [u32; 2]) -> Vec<[f32; 4]> {
+    let [width, height] = extent;
+    let tiles_x = width.div_ceil(TILE_SIZE);
+    let tile_count = tiles_x * height.div_ceil(TILE_SIZE);
+
+    let next_tile = AtomicU32::new(0);
+    let image = Mutex::new(vec![[0.0; 4]; (width * height) as usize]);
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let tile = next_tile.fetch_add(1, Ordering::Relaxed);
+                if tile >= tile_count {
+                    break;
+                }
+
+                let x0 = (tile % tiles_x) * TILE_SIZE;
+                let y0 = (tile / tiles_x) * TILE_SIZE;
+                let x1 = (x0 + TILE_SIZE).min(width);
+                let y1 = (y0 + TILE_SIZE).min(height);
+
+                let mut pixels = Vec::with_capacity(((x1 - x0) * (y1 - y0)) as usize);
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        let color = shade(constants, scene, Vector2::new(x as f32 + 0.5, y as f32 + 0.5));
+                        pixels.push(color.into());
+                    }
+                }
+
+                let mut image = image.lock().unwrap();
+                let mut pixels = pixels.into_iter();
+                for y in y0..y1 {
+                    let row = (y * width) as usize;
+                    for slot in &mut image[row + x0 as usize..row + x1 as usize] {
+                        *slot = pixels.next().unwrap();
+                    }
+                }
+            });
+        }
+    });
+
+    image.into_inner().unwrap()
+}
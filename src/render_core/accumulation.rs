@@ -0,0 +1,259 @@
+//! Temporal accumulation: while the camera rests and the scene time is paused, every frame is rendered with its rays
+//! jittered inside the pixel and averaged into a float history, so edges and soft shadows converge into a clean image.
+//! The history is two images the passes alternate between, and the average is copied back into the HDR target so tone
+//! mapping and screenshots use it.
+
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{BufferContents, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, BlitImageInfo, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::format::Format;
+use vulkano::image::sampler::{Sampler, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineLayout};
+use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass};
+use crate::render_core::pipeline;
+use crate::render_core::pipeline::MyVertex;
+use crate::render_core::shaders;
+use crate::render_core::shaders::ShaderError;
+use crate::render_core::tonemap::HdrTarget;
+use crate::render_core::vulkano_core::{set_name, InitError};
+
+/// Full floats, so a thousand samples add up without banding.
+pub const HISTORY_FORMAT: Format = Format::R32G32B32A32_SFLOAT;
+/// Samples after which the image counts as converged and the viewer stops rendering until something changes, unless
+/// `--accumulate-samples` asks for another number.
+pub const DEFAULT_SAMPLES: u32 = 1024;
+
+/// Push constants of `shaders/accumulate.frag`.
+#[repr(C)]
+#[derive(BufferContents, Clone)]
+pub struct AccumulateConstants {
+    /// Weight of the new frame, `1 / samples`.
+    pub weight: f32,
+}
+
+/// The two history images.
+struct History {
+    images: [Arc<Image>; 2],
+    framebuffers: [Arc<Framebuffer>; 2],
+    /// Set 0 reading the HDR target and either image, see `Accumulation::current`.
+    sets: [Arc<PersistentDescriptorSet>; 2],
+}
+
+pub struct Accumulation {
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: StandardDescriptorSetAllocator,
+    render_pass: Arc<RenderPass>,
+    layout: Arc<PipelineLayout>,
+    pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    history: History,
+    /// Which history image holds the average so far; the next pass reads it and writes the other one.
+    current: usize,
+    /// Frames averaged into the history since the last reset.
+    samples: u32,
+    max_samples: u32,
+}
+
+impl Accumulation {
+    /// A history the size of `hdr_target` that converges after `max_samples`; with `shader_dir` the shader comes from
+    /// there, falling back to the built-in one.
+    pub fn new(
+        device: Arc<Device>,
+        buffer_allocator: Arc<StandardMemoryAllocator>,
+        hdr_target: &HdrTarget,
+        max_samples: u32,
+        shader_dir: Option<&Path>,
+    ) -> Result<Self, InitError> {
+        let render_pass = crate::render_core::vulkano_core::init_render_pass(device.clone(), HISTORY_FORMAT)?;
+        let (layout, pipeline) = build_pipeline(device.clone(), render_pass.clone(), shader_dir).unwrap_or_else(|e| {
+            eprintln!("{}\nFalling back to the built-in accumulation shader", e);
+            build_pipeline(device.clone(), render_pass.clone(), None).expect("Failed to create accumulation pipeline")
+        });
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone(), Default::default());
+        let sampler = Sampler::new(device, SamplerCreateInfo::default()).expect("Failed to create accumulation sampler");
+        let history = init_history(&buffer_allocator, &descriptor_set_allocator, &render_pass, &pipeline, &sampler, hdr_target)?;
+        Ok(Self {
+            buffer_allocator,
+            descriptor_set_allocator,
+            render_pass,
+            layout,
+            pipeline,
+            sampler,
+            history,
+            current: 0,
+            samples: 0,
+            max_samples,
+        })
+    }
+
+    /// Recreates the history at the size of the new `hdr_target` and starts over.
+    pub fn resize(&mut self, hdr_target: &HdrTarget) -> Result<(), InitError> {
+        self.history = init_history(
+            &self.buffer_allocator,
+            &self.descriptor_set_allocator,
+            &self.render_pass,
+            &self.pipeline,
+            &self.sampler,
+            hdr_target,
+        )?;
+        self.reset();
+        Ok(())
+    }
+
+    /// Rebuilds the pipeline from `shader_dir` and starts over; on failure the previous one stays in use.
+    pub fn reload(&mut self, hdr_target: &HdrTarget, shader_dir: Option<&Path>) -> Result<(), ShaderError> {
+        let (layout, pipeline) = build_pipeline(self.layout.device().clone(), self.render_pass.clone(), shader_dir)?;
+        self.history.sets = bind(&self.descriptor_set_allocator, &pipeline, &self.sampler, hdr_target, &self.history.images);
+        self.layout = layout;
+        self.pipeline = pipeline;
+        self.reset();
+        Ok(())
+    }
+
+    /// Starts over; the next frame replaces the history instead of being averaged into it.
+    pub fn reset(&mut self) {
+        self.samples = 0;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn converged(&self) -> bool {
+        self.samples >= self.max_samples
+    }
+
+    /// Sub-pixel offset for the next frame in pixels, see `jitter`.
+    pub fn jitter(&self) -> [f32; 2] {
+        jitter(self.samples)
+    }
+
+    /// Averages the frame in `hdr_target` into the history and copies the average back over it. Call after drawing the
+    /// frame with `jitter()` and before tone mapping.
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        hdr_target: &HdrTarget,
+        viewport: &Viewport,
+        vertex_buffer: Subbuffer<[MyVertex]>,
+        index_buffer: Subbuffer<[u32]>,
+    ) {
+        self.samples += 1;
+        let next = 1 - self.current;
+        pipeline::record_draw(
+            builder,
+            self.history.framebuffers[next].clone(),
+            viewport,
+            self.layout.clone(),
+            self.pipeline.clone(),
+            Some(AccumulateConstants { weight: 1.0 / self.samples as f32 }),
+            Some(self.history.sets[self.current].clone()),
+            None,
+            vertex_buffer,
+            index_buffer,
+        );
+        builder.blit_image(BlitImageInfo::images(self.history.images[next].clone(), hdr_target.image.clone())).unwrap();
+        self.current = next;
+    }
+}
+
+/// Sub-pixel offset of `sample` in pixels: none for the first, so a single frame looks as without accumulation, then the
+/// Halton (2, 3) sequence, which covers the pixel evenly at any sample count.
+pub fn jitter(sample: u32) -> [f32; 2] {
+    if sample == 0 {
+        return [0.0; 2];
+    }
+    [halton(sample, 2) - 0.5, halton(sample, 3) - 0.5]
+}
+
+/// Builds the accumulation pipeline, which draws into `render_pass`, made for `HISTORY_FORMAT`.
+fn build_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    shader_dir: Option<&Path>,
+) -> Result<(Arc<PipelineLayout>, Arc<GraphicsPipeline>), ShaderError> {
+    let shaders = shaders::load_accumulate(device.clone(), shader_dir)?;
+    pipeline::init_pipeline_with_blend(device, render_pass, shaders, None)
+}
+
+fn init_history(
+    buffer_allocator: &Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    render_pass: &Arc<RenderPass>,
+    pipeline: &GraphicsPipeline,
+    sampler: &Arc<Sampler>,
+    hdr_target: &HdrTarget,
+) -> Result<History, InitError> {
+    let extent = hdr_target.image.extent();
+    let image = |index| -> Result<_, InitError> {
+        let image = Image::new(
+            buffer_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: HISTORY_FORMAT,
+                extent,
+                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC,
+                ..ImageCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..AllocationCreateInfo::default()
+            }
+        ).map_err(InitError::Image)?;
+        set_name(image.as_ref(), &format!("accumulation history {}", index));
+        let framebuffer = Framebuffer::new(
+            render_pass.clone(),
+            FramebufferCreateInfo {
+                attachments: vec![ImageView::new_default(image.clone()).map_err(InitError::Framebuffer)?],
+                ..FramebufferCreateInfo::default()
+            }
+        ).map_err(InitError::Framebuffer)?;
+        Ok((image, framebuffer))
+    };
+    let (first, second) = (image(0)?, image(1)?);
+    let images = [first.0, second.0];
+    let sets = bind(descriptor_set_allocator, pipeline, sampler, hdr_target, &images);
+    Ok(History { images, framebuffers: [first.1, second.1], sets })
+}
+
+/// Set 0 for reading either history image together with the new frame.
+fn bind(
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    pipeline: &GraphicsPipeline,
+    sampler: &Arc<Sampler>,
+    hdr_target: &HdrTarget,
+    images: &[Arc<Image>; 2],
+) -> [Arc<PersistentDescriptorSet>; 2] {
+    images.clone().map(|image| {
+        let history = ImageView::new_default(image).expect("Failed to create history view");
+        PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(0, hdr_target.view(), sampler.clone()),
+                WriteDescriptorSet::image_view_sampler(1, history, sampler.clone()),
+            ],
+            []
+        ).expect("Failed to create accumulation descriptor set")
+    })
+}
+
+/// The `index`th element of the Halton sequence with `base`, in 0..1.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
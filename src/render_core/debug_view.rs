@@ -0,0 +1,122 @@
+//! Debug views of the 3D shader: instead of the shaded scene it shows one of the values that go into it, with a legend
+//! bar at the bottom of the frame. The view is the `DEBUG_VIEW` specialization constant of a `Variant`, so every scene,
+//! generated or interpreted, has them; `legend` describes the bar for the window title.
+
+use std::fmt;
+use std::str::FromStr;
+use crate::render_core::quality::Quality;
+
+/// The discriminants are the `DEBUG_VIEW_*` values of `shaders/raymarching-3d.frag`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DebugView {
+    Off = 0,
+    /// Steps `march` took, as a heatmap up to the step limit.
+    Steps = 1,
+    /// `map()` at the hit point relative to the hit threshold.
+    Distance = 2,
+    /// Surface normals from `calcNormal`, mapped from -1..1 to 0..1.
+    Normals = 3,
+    /// The shadow term averaged over the lights.
+    Shadow = 4,
+    /// The ambient occlusion term.
+    Ao = 5,
+    /// Distance along the ray, logarithmic up to the far plane.
+    Depth = 6,
+    /// The scene material closest to the surface color.
+    Material = 7,
+    /// Whether a ray hit, or missed at the step limit or at the far plane.
+    Misses = 8,
+}
+
+impl DebugView {
+    pub fn value(self) -> u32 {
+        self as u32
+    }
+
+    /// The next view, for cycling through them in the viewer.
+    pub fn next(self) -> Self {
+        match self {
+            DebugView::Off => DebugView::Steps,
+            DebugView::Steps => DebugView::Distance,
+            DebugView::Distance => DebugView::Normals,
+            DebugView::Normals => DebugView::Shadow,
+            DebugView::Shadow => DebugView::Ao,
+            DebugView::Ao => DebugView::Depth,
+            DebugView::Depth => DebugView::Material,
+            DebugView::Material => DebugView::Misses,
+            DebugView::Misses => DebugView::Off,
+        }
+    }
+
+    /// What the colors and the legend bar mean, with the values at its ticks for `quality`; `None` without a debug view.
+    /// `materials` is the size of the scene's material table, one bar segment each.
+    pub fn legend(self, quality: &Quality, materials: usize) -> Option<String> {
+        let quarters = |value: &dyn Fn(f32) -> f32| {
+            [0.25, 0.5, 0.75].map(|x| format!("{:.0}", value(x))).join(", ")
+        };
+        let legend = match self {
+            DebugView::Off => return None,
+            DebugView::Steps => format!(
+                "steps per ray from 0 (blue) to the limit of {} (red), ticks at {}",
+                quality.max_steps,
+                quarters(&|x| x * quality.max_steps as f32)
+            ),
+            DebugView::Distance => {
+                "distance to the surface at the hit in hit thresholds, -1 (blue, overshot into the surface) over 0 (green) to 1 (red)"
+                    .to_string()
+            }
+            DebugView::Normals => "normals, x red, y green, z blue; bar: +X, -X, +Y, -Y, +Z, -Z".to_string(),
+            DebugView::Shadow => "shadow term averaged over the lights, black in shadow to white lit".to_string(),
+            DebugView::Ao => "ambient occlusion, black occluded to white open".to_string(),
+            DebugView::Depth => format!(
+                "distance along the ray, logarithmic from 0 (blue) to the far plane at {} (red), ticks at {}",
+                quality.far_plane,
+                quarters(&|x| (1.0 + quality.far_plane).powf(x) - 1.0)
+            ),
+            DebugView::Material => format!("material IDs 0 to {} from left to right", materials.max(1) - 1),
+            DebugView::Misses => format!(
+                "grey hit, red missed at the step limit of {}, blue missed at the far plane at {}",
+                quality.max_steps, quality.far_plane
+            ),
+        };
+        Some(legend)
+    }
+}
+
+impl FromStr for DebugView {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Ok(DebugView::Off),
+            "steps" => Ok(DebugView::Steps),
+            "distance" => Ok(DebugView::Distance),
+            "normals" => Ok(DebugView::Normals),
+            "shadow" => Ok(DebugView::Shadow),
+            "ao" => Ok(DebugView::Ao),
+            "depth" => Ok(DebugView::Depth),
+            "material" => Ok(DebugView::Material),
+            "misses" => Ok(DebugView::Misses),
+            _ => Err(format!(
+                "'{}' is not a debug view, expected off, steps, distance, normals, shadow, ao, depth, material or misses",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DebugView {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugView::Off => write!(f, "off"),
+            DebugView::Steps => write!(f, "steps"),
+            DebugView::Distance => write!(f, "distance"),
+            DebugView::Normals => write!(f, "normals"),
+            DebugView::Shadow => write!(f, "shadow"),
+            DebugView::Ao => write!(f, "ao"),
+            DebugView::Depth => write!(f, "depth"),
+            DebugView::Material => write!(f, "material"),
+            DebugView::Misses => write!(f, "misses"),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+//! The average for auto exposure: `shaders/luminance.frag` writes the log luminance of the HDR frame into a square float
+//! image a power of two large, whose mip chain is then built by blitting. Halving a power of two is an exact 2x2 box
+//! filter, so the last level is the mean over all pixels, and averaging logarithms keeps a few bright highlights from
+//! darkening the whole frame the way an arithmetic mean would.
+
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{BufferContents, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, BlitImageInfo, ImageBlit, PrimaryAutoCommandBuffer};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::format::Format;
+use vulkano::image::sampler::{Filter, Sampler, SamplerCreateInfo};
+use vulkano::image::view::{ImageView, ImageViewCreateInfo};
+use vulkano::image::{max_mip_levels, mip_level_extent, Image, ImageCreateInfo, ImageSubresourceLayers, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineLayout};
+use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass};
+use crate::render_core::pipeline;
+use crate::render_core::pipeline::MyVertex;
+use crate::render_core::shaders;
+use crate::render_core::shaders::ShaderError;
+use crate::render_core::tonemap::HdrTarget;
+use crate::render_core::vulkano_core::{set_name, InitError};
+
+/// Log luminance and the share of texels inside the frame; both channels can be blitted with linear filtering everywhere.
+pub const LUMINANCE_FORMAT: Format = Format::R16G16_SFLOAT;
+/// Largest side of the log luminance image; larger frames are summed in blocks of pixels by the first pass.
+const MAX_SIZE: u32 = 1024;
+
+/// Push constants of `shaders/luminance.frag`.
+#[repr(C)]
+#[derive(BufferContents, Clone)]
+pub struct LuminanceConstants {
+    /// Side of the block of HDR pixels each texel covers.
+    pub block: u32,
+}
+
+/// The log luminance image and what draws into it.
+struct Target {
+    image: Arc<Image>,
+    /// Renders into the first mip level.
+    framebuffer: Arc<Framebuffer>,
+    /// All mip levels, for the tone mapping pass.
+    view: Arc<ImageView>,
+    /// Set 0 reading the HDR target.
+    set: Arc<PersistentDescriptorSet>,
+    block: u32,
+}
+
+pub struct ExposureAverage {
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: StandardDescriptorSetAllocator,
+    render_pass: Arc<RenderPass>,
+    layout: Arc<PipelineLayout>,
+    pipeline: Arc<GraphicsPipeline>,
+    sampler: Arc<Sampler>,
+    target: Target,
+}
+
+impl ExposureAverage {
+    /// An average of `hdr_target`; with `shader_dir` the shader comes from there, falling back to the built-in one.
+    pub fn new(
+        device: Arc<Device>,
+        buffer_allocator: Arc<StandardMemoryAllocator>,
+        hdr_target: &HdrTarget,
+        shader_dir: Option<&Path>,
+    ) -> Result<Self, InitError> {
+        let render_pass = crate::render_core::vulkano_core::init_render_pass(device.clone(), LUMINANCE_FORMAT)?;
+        let (layout, pipeline) = build_pipeline(device.clone(), render_pass.clone(), shader_dir).unwrap_or_else(|e| {
+            eprintln!("{}\nFalling back to the built-in luminance shader", e);
+            build_pipeline(device.clone(), render_pass.clone(), None).expect("Failed to create luminance pipeline")
+        });
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone(), Default::default());
+        let sampler = Sampler::new(device, SamplerCreateInfo::default()).expect("Failed to create luminance sampler");
+        let target = init_target(&buffer_allocator, &descriptor_set_allocator, &render_pass, &pipeline, &sampler, hdr_target)?;
+        Ok(Self { buffer_allocator, descriptor_set_allocator, render_pass, layout, pipeline, sampler, target })
+    }
+
+    /// Recreates the log luminance image for the new `hdr_target`.
+    pub fn resize(&mut self, hdr_target: &HdrTarget) -> Result<(), InitError> {
+        self.target = init_target(
+            &self.buffer_allocator,
+            &self.descriptor_set_allocator,
+            &self.render_pass,
+            &self.pipeline,
+            &self.sampler,
+            hdr_target,
+        )?;
+        Ok(())
+    }
+
+    /// Rebuilds the pipeline from `shader_dir`; on failure the previous one stays in use.
+    pub fn reload(&mut self, hdr_target: &HdrTarget, shader_dir: Option<&Path>) -> Result<(), ShaderError> {
+        let (layout, pipeline) = build_pipeline(self.layout.device().clone(), self.render_pass.clone(), shader_dir)?;
+        self.target.set = bind(&self.descriptor_set_allocator, &pipeline, &self.sampler, hdr_target);
+        self.layout = layout;
+        self.pipeline = pipeline;
+        Ok(())
+    }
+
+    /// All mip levels of the log luminance image, for the tone mapping pass.
+    pub fn view(&self) -> Arc<ImageView> {
+        self.target.view.clone()
+    }
+
+    /// Mip level that holds the 1x1 average.
+    pub fn average_lod(&self) -> f32 {
+        (self.target.image.mip_levels() - 1) as f32
+    }
+
+    /// Averages the frame in the HDR target into the last mip level. Call after drawing the frame and before tone mapping.
+    pub fn record(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        vertex_buffer: Subbuffer<[MyVertex]>,
+        index_buffer: Subbuffer<[u32]>,
+    ) {
+        let extent = self.target.image.extent();
+        let viewport = Viewport { extent: [extent[0] as f32, extent[1] as f32], ..Viewport::default() };
+        pipeline::record_draw(
+            builder,
+            self.target.framebuffer.clone(),
+            &viewport,
+            self.layout.clone(),
+            self.pipeline.clone(),
+            Some(LuminanceConstants { block: self.target.block }),
+            Some(self.target.set.clone()),
+            None,
+            vertex_buffer,
+            index_buffer,
+        );
+        let image = &self.target.image;
+        for level in 1..image.mip_levels() {
+            builder.blit_image(BlitImageInfo {
+                regions: [ImageBlit {
+                    src_subresource: ImageSubresourceLayers { mip_level: level - 1, ..image.subresource_layers() },
+                    src_offsets: [[0, 0, 0], mip_level_extent(extent, level - 1).unwrap()],
+                    dst_subresource: ImageSubresourceLayers { mip_level: level, ..image.subresource_layers() },
+                    dst_offsets: [[0, 0, 0], mip_level_extent(extent, level).unwrap()],
+                    ..ImageBlit::default()
+                }].into(),
+                filter: Filter::Linear,
+                ..BlitImageInfo::images(image.clone(), image.clone())
+            }).unwrap();
+        }
+    }
+}
+
+/// Side of the log luminance image for a frame of `extent` and the block of pixels each of its texels covers; together
+/// they cover the frame.
+fn layout_for(extent: [u32; 3]) -> (u32, u32) {
+    let mut side = extent[0].max(extent[1]).next_power_of_two();
+    let mut block = 1;
+    while side > MAX_SIZE {
+        side /= 2;
+        block *= 2;
+    }
+    (side, block)
+}
+
+/// Builds the luminance pipeline, which draws into `render_pass`, made for `LUMINANCE_FORMAT`.
+fn build_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    shader_dir: Option<&Path>,
+) -> Result<(Arc<PipelineLayout>, Arc<GraphicsPipeline>), ShaderError> {
+    let shaders = shaders::load_luminance(device.clone(), shader_dir)?;
+    pipeline::init_pipeline_with_blend(device, render_pass, shaders, None)
+}
+
+fn init_target(
+    buffer_allocator: &Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    render_pass: &Arc<RenderPass>,
+    pipeline: &GraphicsPipeline,
+    sampler: &Arc<Sampler>,
+    hdr_target: &HdrTarget,
+) -> Result<Target, InitError> {
+    let (side, block) = layout_for(hdr_target.image.extent());
+    let extent = [side, side, 1];
+    let image = Image::new(
+        buffer_allocator.clone(),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format: LUMINANCE_FORMAT,
+            extent,
+            mip_levels: max_mip_levels(extent),
+            usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
+            ..ImageCreateInfo::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..AllocationCreateInfo::default()
+        }
+    ).map_err(InitError::Image)?;
+    set_name(image.as_ref(), "log luminance");
+
+    let mut attachment_info = ImageViewCreateInfo::from_image(&image);
+    attachment_info.subresource_range.mip_levels = 0..1;
+    let attachment = ImageView::new(image.clone(), attachment_info).map_err(InitError::Framebuffer)?;
+    let framebuffer = Framebuffer::new(
+        render_pass.clone(),
+        FramebufferCreateInfo {
+            attachments: vec![attachment],
+            ..FramebufferCreateInfo::default()
+        }
+    ).map_err(InitError::Framebuffer)?;
+    let view = ImageView::new_default(image.clone()).map_err(InitError::Framebuffer)?;
+    let set = bind(descriptor_set_allocator, pipeline, sampler, hdr_target);
+    Ok(Target { image, framebuffer, view, set, block })
+}
+
+/// Set 0 for reading the HDR frame.
+fn bind(
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    pipeline: &GraphicsPipeline,
+    sampler: &Arc<Sampler>,
+    hdr_target: &HdrTarget,
+) -> Arc<PersistentDescriptorSet> {
+    PersistentDescriptorSet::new(
+        descriptor_set_allocator,
+        pipeline.layout().set_layouts()[0].clone(),
+        [WriteDescriptorSet::image_view_sampler(0, hdr_target.view(), sampler.clone())],
+        []
+    ).expect("Failed to create luminance descriptor set")
+}
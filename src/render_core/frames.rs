@@ -0,0 +1,123 @@
+//! Frames in flight: the viewer records frame N+1 while the GPU still works on frame N. Each frame in flight has its own
+//! uniform buffer and command buffer allocator, which are only touched again once the frame's fence has signalled.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vulkano::buffer::Subbuffer;
+use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::Device;
+use vulkano::memory::allocator::StandardMemoryAllocator;
+use vulkano::pipeline::GraphicsPipeline;
+use vulkano::sync::future::FenceSignalFuture;
+use vulkano::sync::GpuFuture;
+use crate::render_core::pipeline;
+use crate::render_core::pipeline::Constants;
+
+pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+/// Signalled when the GPU has finished a frame; shared because the next frame waits for it too.
+pub type FrameFence = Arc<FenceSignalFuture<Box<dyn GpuFuture + Send + Sync>>>;
+
+/// What one frame in flight records into and reads from.
+pub struct Frame {
+    pub command_buffer_allocator: StandardCommandBufferAllocator,
+    pub uniforms: Subbuffer<Constants>,
+    /// `uniforms` bound to the current scene pipeline, `None` if it has no per-frame uniforms.
+    pub frame_set: Option<Arc<PersistentDescriptorSet>>,
+    fence: Option<FrameFence>,
+}
+
+/// The frames in flight, used round-robin.
+pub struct FrameRing {
+    frames: Vec<Frame>,
+    current: usize,
+    /// Fence of the frame submitted last, which the next one has to start after.
+    previous_fence: Option<FrameFence>,
+    stats: FrameStats,
+}
+
+impl FrameRing {
+    /// `count` is clamped to 1..=`MAX_FRAMES_IN_FLIGHT`; with 1 the CPU waits for every frame before recording the next.
+    pub fn new(device: Arc<Device>, buffer_allocator: Arc<StandardMemoryAllocator>, count: usize) -> Self {
+        let frames = (0..count.clamp(1, MAX_FRAMES_IN_FLIGHT))
+            .map(|i| Frame {
+                command_buffer_allocator: StandardCommandBufferAllocator::new(
+                    device.clone(),
+                    StandardCommandBufferAllocatorCreateInfo::default()
+                ),
+                uniforms: pipeline::init_frame_uniforms(buffer_allocator.clone(), &format!("frame {} uniforms", i)),
+                frame_set: None,
+                fence: None,
+            })
+            .collect::<Vec<_>>();
+        let stats = FrameStats::new(frames.len());
+
+        Self { frames, current: 0, previous_fence: None, stats }
+    }
+
+    /// Rebinds the uniform buffers after the scene pipeline changed.
+    pub fn bind(&mut self, descriptor_set_allocator: &StandardDescriptorSetAllocator, pipeline: &GraphicsPipeline) {
+        for frame in &mut self.frames {
+            frame.frame_set = pipeline::bind_frame_uniforms(descriptor_set_allocator, pipeline, frame.uniforms.clone());
+        }
+    }
+
+    /// Moves on to the next frame and waits until the GPU is done with what it used the last time round.
+    pub fn next(&mut self) -> &mut Frame {
+        self.current = (self.current + 1) % self.frames.len();
+        let wait_start = Instant::now();
+        if let Some(fence) = self.frames[self.current].fence.take() {
+            fence.wait(None).expect("Failed to wait for frame in flight");
+        }
+        self.stats.frame_started(wait_start.elapsed());
+        &mut self.frames[self.current]
+    }
+
+    /// What the current frame's work has to start after: the previous frame, so frames still execute in order.
+    pub fn previous_future(&self, device: Arc<Device>) -> Box<dyn GpuFuture + Send + Sync> {
+        match self.previous_fence.clone() {
+            Some(fence) => Box::new(fence),
+            None => Box::new(vulkano::sync::now(device)),
+        }
+    }
+
+    /// Records the fence of the current frame once it's submitted; `None` if submitting failed.
+    pub fn submitted(&mut self, fence: Option<FrameFence>) {
+        self.frames[self.current].fence = fence.clone();
+        self.previous_fence = fence;
+    }
+}
+
+/// Logs frame times once a second, with how long the CPU waited for frames in flight to finish, to compare ring sizes.
+struct FrameStats {
+    frames_in_flight: usize,
+    since: Instant,
+    frames: u32,
+    waiting: Duration,
+}
+
+impl FrameStats {
+    fn new(frames_in_flight: usize) -> Self {
+        Self { frames_in_flight, since: Instant::now(), frames: 0, waiting: Duration::ZERO }
+    }
+
+    fn frame_started(&mut self, waited: Duration) {
+        self.frames += 1;
+        self.waiting += waited;
+
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let frames = self.frames as f32;
+            log::info!(
+                "{:.1} fps, {:.2} ms per frame, {:.2} ms waiting for the GPU, {} frames in flight",
+                frames / elapsed.as_secs_f32(),
+                elapsed.as_secs_f32() * 1000.0 / frames,
+                self.waiting.as_secs_f32() * 1000.0 / frames,
+                self.frames_in_flight
+            );
+            *self = FrameStats::new(self.frames_in_flight);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferUsage, CopyImageToBufferInfo};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::PersistentDescriptorSet;
+use vulkano::device::{Device, DeviceExtensions, Queue};
+use vulkano::format::Format;
+use vulkano::image::Image;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, PipelineLayout};
+use vulkano::render_pass::Framebuffer;
+use vulkano::sync::GpuFuture;
+use crate::render_core;
+use crate::render_core::pipeline::{Constants, MyVertex};
+use crate::render_core::shaders::ShaderError;
+use crate::render_core::variants::{ScenePipelines, Variant};
+use crate::render_core::vulkano_core::{set_name, DebugMessenger, DeviceSelector, InitError};
+use crate::scene::Scene;
+
+/// Renders the raymarching pipeline into an offscreen image and reads it back, without a window or surface.
+pub struct HeadlessRenderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    pipeline_layout: Arc<PipelineLayout>,
+    pipelines: ScenePipelines,
+    pipeline: Arc<GraphicsPipeline>,
+    scene: Option<Arc<PersistentDescriptorSet>>,
+    uniforms: Subbuffer<Constants>,
+    frame_set: Option<Arc<PersistentDescriptorSet>>,
+    vertex_buffer: Subbuffer<[MyVertex]>,
+    index_buffer: Subbuffer<[u32]>,
+    image: Arc<Image>,
+    framebuffer: Arc<Framebuffer>,
+    viewport: Viewport,
+    readback_buffer: Subbuffer<[u8]>,
+    /// Declared last so it is dropped last and still reports problems while everything else is destroyed.
+    _debug_messenger: DebugMessenger,
+}
+
+impl HeadlessRenderer {
+    pub fn new(
+        extent: [u32; 2],
+        format: Format,
+        scene: &Scene,
+        codegen: bool,
+        shader_dir: Option<&Path>,
+        device: Option<&DeviceSelector>,
+        validation: bool,
+    ) -> Result<Self, InitError> {
+        let (instance, debug_messenger) = render_core::vulkano_core::init_headless(validation)?;
+        let (device, queue) = render_core::vulkano_core::init_device(instance, DeviceExtensions::empty(), None, device)?;
+        let render_pass = render_core::vulkano_core::init_render_pass(device.clone(), format)?;
+
+        let mut viewport = Viewport {
+            offset: [0.0, 0.0],
+            extent: [0.0, 0.0],
+            depth_range: RangeInclusive::new(0.0, 1.0)
+        };
+
+        let command_buffer_allocator = StandardCommandBufferAllocator::new(device.clone(), StandardCommandBufferAllocatorCreateInfo::default());
+        let buffer_allocator = Arc::new(StandardMemoryAllocator::new_default(device.clone()));
+        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone(), Default::default());
+
+        let (image, framebuffer) = render_core::vulkano_core::init_offscreen_target(buffer_allocator.clone(), render_pass.clone(), extent, &mut viewport)?;
+        // Offline renders draw the default variant unless `set_variant` changes it; a disk cache wouldn't pay off for the
+        // one or two pipelines they use.
+        let (mut pipelines, scene) = render_core::pipeline::init_scene_pipelines(
+            device.clone(),
+            render_pass,
+            buffer_allocator.clone(),
+            &descriptor_set_allocator,
+            scene,
+            codegen,
+            shader_dir,
+            None
+        );
+        let pipeline_layout = pipelines.layout();
+        let pipeline = pipelines.get(Variant::default()).expect("Failed to create graphics pipeline");
+        let (vertex_buffer, index_buffer) = render_core::pipeline::init_quad(buffer_allocator.clone());
+        // One frame at a time, so a single uniform buffer is enough.
+        let uniforms = render_core::pipeline::init_frame_uniforms(buffer_allocator.clone(), "frame uniforms");
+        let frame_set = render_core::pipeline::bind_frame_uniforms(&descriptor_set_allocator, &pipeline, uniforms.clone());
+
+        let readback_buffer = Buffer::new_slice::<u8>(
+            buffer_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_DST,
+                ..BufferCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_RANDOM_ACCESS,
+                ..AllocationCreateInfo::default()
+            },
+            extent[0] as u64 * extent[1] as u64 * format.block_size()
+        ).expect("Failed to create readback buffer");
+        set_name(readback_buffer.buffer().as_ref(), "readback buffer");
+
+        Ok(Self {
+            device,
+            queue,
+            command_buffer_allocator,
+            pipeline_layout,
+            pipelines,
+            pipeline,
+            scene,
+            uniforms,
+            frame_set,
+            vertex_buffer,
+            index_buffer,
+            image,
+            framebuffer,
+            viewport,
+            readback_buffer,
+            _debug_messenger: debug_messenger,
+        })
+    }
+
+    /// Draws the following frames with the pipeline of `variant`.
+    pub fn set_variant(&mut self, variant: Variant) -> Result<(), ShaderError> {
+        // All variants share the pipeline layout, so the bound descriptor sets stay valid.
+        self.pipeline = self.pipelines.get(variant)?;
+        Ok(())
+    }
+
+    pub fn format(&self) -> Format {
+        self.image.format()
+    }
+
+    /// Renders one frame and returns the tightly packed texels of the target, in the target's format.
+    pub fn render(&self, constants: Constants) -> Vec<u8> {
+        *self.uniforms.write().unwrap() = constants;
+
+        let mut builder = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit
+        ).unwrap();
+
+        render_core::pipeline::record_draw(
+            &mut builder,
+            self.framebuffer.clone(),
+            &self.viewport,
+            self.pipeline_layout.clone(),
+            self.pipeline.clone(),
+            None::<Constants>,
+            self.scene.clone(),
+            self.frame_set.clone(),
+            self.vertex_buffer.clone(),
+            self.index_buffer.clone(),
+        );
+        builder
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(self.image.clone(), self.readback_buffer.clone()))
+            .unwrap();
+        let command_buffer = builder.build().unwrap();
+
+        vulkano::sync::now(self.device.clone())
+            .then_execute(self.queue.clone(), command_buffer).unwrap()
+            .then_signal_fence_and_flush().expect("Failed to flush headless frame")
+            .wait(None).expect("Failed to wait for headless frame");
+
+        self.readback_buffer.read().unwrap().to_vec()
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Editors often save in several steps (truncate, write, rename), so changes are reported once they have settled.
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+const SHADER_EXTENSIONS: [&str; 4] = ["vert", "frag", "glsl", "comp"];
+
+/// Which changed paths count.
+enum Filter {
+    /// GLSL files anywhere below the watched directory.
+    Shaders,
+    /// Exactly these files, as `watched_path` spells them.
+    Files(HashSet<PathBuf>),
+}
+
+/// Watches a shader directory for changed GLSL files, or with `for_files` a list of files.
+pub struct ShaderWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    filter: Filter,
+    changed_at: Option<Instant>,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: &Path) -> notify::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+
+        Ok(Self { _watcher: watcher, events, filter: Filter::Shaders, changed_at: None })
+    }
+
+    /// Reports changes to `files` only. Their directories are watched rather than the files themselves, so a file an
+    /// editor replaces by renaming over it is still seen, but nothing else in them or below them counts.
+    pub fn for_files(files: &[PathBuf]) -> notify::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        let mut dirs = HashSet::new();
+        let mut paths = HashSet::new();
+        for file in files {
+            let path = watched_path(file);
+            if let Some(dir) = path.parent() {
+                if dirs.insert(dir.to_path_buf()) {
+                    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+                }
+            }
+            paths.insert(path);
+        }
+
+        Ok(Self { _watcher: watcher, events, filter: Filter::Files(paths), changed_at: None })
+    }
+
+    /// Returns true once after watched files changed and no further change came in for `SETTLE_TIME`.
+    pub fn poll(&mut self) -> bool {
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if is_change(&event, &self.filter) => self.changed_at = Some(Instant::now()),
+                Ok(_) => {}
+                Err(e) => eprintln!("Shader watcher error: {}", e),
+            }
+        }
+
+        match self.changed_at {
+            Some(changed_at) if changed_at.elapsed() >= SETTLE_TIME => {
+                self.changed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `file` with its directory canonicalized, like the paths in the events of a watch on that directory; the file itself
+/// may be missing for a moment while it is being saved.
+fn watched_path(file: &Path) -> PathBuf {
+    let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    match file.file_name() {
+        Some(name) => dir.join(name),
+        None => dir,
+    }
+}
+
+fn is_change(event: &Event, filter: &Filter) -> bool {
+    matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_))
+        && event.paths.iter().any(|path| match filter {
+            Filter::Shaders => path.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| SHADER_EXTENSIONS.contains(&e)),
+            Filter::Files(files) => files.contains(&watched_path(path)),
+        })
+}
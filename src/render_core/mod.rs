@@ -1,2 +1,14 @@
 pub mod vulkano_core;
-pub mod shaders;
\ No newline at end of file
+pub mod shaders;
+pub mod pipeline;
+pub mod headless;
+pub mod hot_reload;
+pub mod frames;
+pub mod tonemap;
+pub mod quality;
+pub mod variants;
+pub mod debug_view;
+pub mod pipeline_cache;
+pub mod accumulation;
+pub mod exposure;
+pub mod shadertoy;
\ No newline at end of file
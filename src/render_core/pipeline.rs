@@ -0,0 +1,470 @@
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryAutoCommandBuffer, RenderPassBeginInfo, SubpassBeginInfo, SubpassContents, SubpassEndInfo};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::device::{Device, DeviceOwned};
+use vulkano::format::{Format, NumericFormat};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState};
+use vulkano::pipeline::graphics::input_assembly::InputAssemblyState;
+use vulkano::pipeline::graphics::multisample::MultisampleState;
+use vulkano::pipeline::graphics::rasterization::RasterizationState;
+use vulkano::pipeline::graphics::vertex_input::{Vertex, VertexDefinition};
+use vulkano::pipeline::graphics::viewport::{Viewport, ViewportState};
+use vulkano::pipeline::graphics::GraphicsPipelineCreateInfo;
+use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::{DynamicState, GraphicsPipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo};
+use vulkano::render_pass::{Framebuffer, RenderPass, Subpass};
+use vulkano::shader::{EntryPoint, SpecializationConstant};
+use crate::render_core::quality::Quality;
+use crate::render_core::shaders;
+use crate::render_core::variants::ScenePipelines;
+use crate::render_core::vulkano_core::set_name;
+use crate::render_core::shaders::{RaymarchingShaders, ShaderError};
+use crate::scene::shapes_2d::Shape2d;
+use crate::scene::{CompiledScene, Scene};
+
+#[repr(C)]
+#[derive(BufferContents, Vertex)]
+pub struct MyVertex {
+    #[format(R32G32_SFLOAT)]
+    pub position: [f32; 2],
+}
+
+/// Per-frame data of the 3D scene and Shadertoy passes, the std140 `Frame` uniform block of `shaders/lib/frame.glsl` at `FRAME_SET`.
+#[repr(C)]
+#[derive(BufferContents, Clone)]
+pub struct Constants {
+    pub view_matrix: [[f32; 4]; 4],
+    pub camera_position: [f32; 4],
+    pub resolution: [f32; 2],
+    /// Scene time in seconds, which stops while paused and runs slower or faster with the playback speed.
+    pub time: f32,
+    /// Scene time since the previous frame, 0 while paused.
+    pub delta_time: f32,
+    /// Cursor position in pixels from the top left, like `gl_FragCoord`, in `.xy`; where the left button was last pressed in `.zw`.
+    pub mouse: [f32; 4],
+    /// Frames rendered so far, counting paused ones.
+    pub frame_index: u32,
+    /// Pressed mouse buttons: `MOUSE_LEFT`, `MOUSE_RIGHT` and `MOUSE_MIDDLE`.
+    pub mouse_buttons: u32,
+    /// std140 aligns the `params` array to 16 bytes.
+    pub padding: [u32; 2],
+    /// The scene's `params`, four per `vec4`.
+    pub params: [[f32; 4]; 4],
+    pub quality: Quality,
+    /// Vertical field of view in degrees.
+    pub fov: f32,
+    /// std140 aligns `jitter` to 8 bytes.
+    pub padding2: u32,
+    /// Sub-pixel offset of the rays in pixels, -0.5 to 0.5, for temporal accumulation; 0 otherwise.
+    pub jitter: [f32; 2],
+}
+
+/// `Constants::fov` unless `--fov` sets another one; `--fov` and zooming in the viewer stay between the limits.
+pub const DEFAULT_FOV: f32 = 90.0;
+pub const MIN_FOV: f32 = 10.0;
+pub const MAX_FOV: f32 = 150.0;
+
+pub const MOUSE_LEFT: u32 = 1;
+pub const MOUSE_RIGHT: u32 = 2;
+pub const MOUSE_MIDDLE: u32 = 4;
+
+/// Push constants of the 2D SDF mode.
+#[repr(C)]
+#[derive(BufferContents, Clone)]
+pub struct Constants2d {
+    pub resolution: [f32; 2],
+    /// Point shown in the top left corner, in pixels at zoom 1.
+    pub pan: [f32; 2],
+    pub zoom: f32,
+    /// Seconds since startup.
+    pub time: f32,
+}
+
+/// Descriptor set of the per-frame uniform buffer; set 0 holds the scene storage buffers or the 2D shapes.
+pub const FRAME_SET: u32 = 1;
+
+/// Bindings of the scene storage buffers in set 0 of `shaders/raymarching-3d.frag`. Only the interpreter reads the program.
+pub const PROGRAM_BINDING: u32 = 0;
+pub const MATERIALS_BINDING: u32 = 1;
+pub const LIGHTS_BINDING: u32 = 2;
+
+/// `constant_id` of `ENCODE_SRGB` in `shaders/lib/color.glsl`.
+pub const ENCODE_SRGB_CONSTANT: u32 = 0;
+
+/// Whether the shaders have to encode their linear output to sRGB themselves before writing it to a `format` target.
+/// UNORM targets store values as they are and are displayed, or saved to PNG, as sRGB; `_SRGB` targets encode in hardware
+/// and float targets keep linear values.
+pub fn encodes_srgb_in_shader(format: Format) -> bool {
+    format.numeric_format_color() == Some(NumericFormat::UNORM)
+}
+
+/// Sets `ENCODE_SRGB` for the render target format and the `constants` of a variant. Shaders that don't declare a
+/// constant are left as they are.
+fn specialize(fragment: &EntryPoint, format: Format, constants: &[(u32, SpecializationConstant)]) -> Result<EntryPoint, ShaderError> {
+    let constants = [(ENCODE_SRGB_CONSTANT, SpecializationConstant::Bool(encodes_srgb_in_shader(format)))]
+        .into_iter()
+        .chain(constants.iter().copied())
+        .collect();
+    let module = fragment.module().base_module().specialize(constants).map_err(|e| ShaderError::Pipeline(e.to_string()))?;
+    Ok(module.entry_point("main").unwrap())
+}
+
+/// Fails instead of panicking, so a broken shader edit can't take the viewer down.
+pub fn init_pipeline(device: Arc<Device>, render_pass: Arc<RenderPass>, shaders: RaymarchingShaders) -> Result<(Arc<PipelineLayout>, Arc<GraphicsPipeline>), ShaderError> {
+    init_pipeline_with_blend(device, render_pass, shaders, Some(AttachmentBlend::alpha()))
+}
+
+/// Like `init_pipeline`, with `None` writing the shader's output as it is, alpha included.
+pub fn init_pipeline_with_blend(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    shaders: RaymarchingShaders,
+    blend: Option<AttachmentBlend>,
+) -> Result<(Arc<PipelineLayout>, Arc<GraphicsPipeline>), ShaderError> {
+    let pipeline_layout = init_layout(device, &shaders)?;
+    let pipeline = create_pipeline(render_pass, &shaders, pipeline_layout.clone(), blend, &[], None)?;
+    Ok((pipeline_layout, pipeline))
+}
+
+/// Push constants, uniform buffers and the scene storage buffers in set 0, as declared by the shaders. Specialization
+/// doesn't change the declarations, so the layout fits every variant of the shaders.
+pub fn init_layout(device: Arc<Device>, shaders: &RaymarchingShaders) -> Result<Arc<PipelineLayout>, ShaderError> {
+    let stages = [
+        PipelineShaderStageCreateInfo::new(shaders.vertex.clone()),
+        PipelineShaderStageCreateInfo::new(shaders.fragment.clone())
+    ];
+    let layout_create_info = PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+        .into_pipeline_layout_create_info(device.clone())
+        .map_err(|e| ShaderError::Pipeline(format!("{:?}", e)))?;
+    PipelineLayout::new(device, layout_create_info).map_err(|e| ShaderError::Pipeline(e.to_string()))
+}
+
+/// Creates the pipeline of `shaders` specialized with `constants`, looking it up in and adding it to `cache` if given.
+pub fn create_pipeline(
+    render_pass: Arc<RenderPass>,
+    shaders: &RaymarchingShaders,
+    layout: Arc<PipelineLayout>,
+    blend: Option<AttachmentBlend>,
+    constants: &[(u32, SpecializationConstant)],
+    cache: Option<Arc<PipelineCache>>,
+) -> Result<Arc<GraphicsPipeline>, ShaderError> {
+    let vertex_input_state = MyVertex::per_vertex()
+        .definition(&shaders.vertex.info().input_interface)
+        .map_err(|e| ShaderError::Pipeline(e.to_string()))?;
+
+    let fragment = specialize(&shaders.fragment, render_pass.attachments()[0].format, constants)?;
+    let stages = vec![
+        PipelineShaderStageCreateInfo::new(shaders.vertex.clone()),
+        PipelineShaderStageCreateInfo::new(fragment)
+    ];
+
+    let subpass = Subpass::from(render_pass, 0).unwrap();
+
+    let pipeline = GraphicsPipeline::new(
+        layout.device().clone(),
+        cache,
+        GraphicsPipelineCreateInfo {
+            flags: Default::default(),
+            stages: stages.into(),
+            vertex_input_state: Some(vertex_input_state),
+            viewport_state: Some(ViewportState::default()),
+            multisample_state: Some(MultisampleState::default()),
+            input_assembly_state: Some(InputAssemblyState::default()),
+            rasterization_state: Some(RasterizationState::default()),
+            color_blend_state: Some(ColorBlendState::with_attachment_states(
+                subpass.num_color_attachments(),
+                ColorBlendAttachmentState {
+                    blend,
+                    ..ColorBlendAttachmentState::default()
+                }
+            )),
+            subpass: Some(subpass.into()),
+            dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+            ..GraphicsPipelineCreateInfo::layout(layout)
+        }
+    ).map_err(|e| ShaderError::Pipeline(e.to_string()))?;
+    set_name(pipeline.as_ref(), "raymarching pipeline");
+
+    Ok(pipeline)
+}
+
+/// Full-screen quad the raymarching fragment shader is drawn onto.
+pub fn init_quad(buffer_allocator: Arc<StandardMemoryAllocator>) -> (Subbuffer<[MyVertex]>, Subbuffer<[u32]>) {
+    let vertices = vec![
+        MyVertex { position: [-1.0, -1.0] },
+        MyVertex { position: [-1.0, 1.0] },
+        MyVertex { position: [1.0, -1.0] },
+        MyVertex { position: [1.0, 1.0] }
+    ];
+    let indices = vec![0u32, 1, 2, 1, 2, 3];
+
+    let vertex_buffer = Buffer::from_iter(
+        buffer_allocator.clone(),
+        BufferCreateInfo {
+            usage: BufferUsage::VERTEX_BUFFER,
+            ..BufferCreateInfo::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..AllocationCreateInfo::default()
+        },
+        vertices
+    ).expect("Failed to create vertex buffer");
+
+    let index_buffer = Buffer::from_iter(
+        buffer_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::INDEX_BUFFER,
+            ..BufferCreateInfo::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..AllocationCreateInfo::default()
+        },
+        indices
+    ).expect("Failed to create index buffer");
+    set_name(vertex_buffer.buffer().as_ref(), "quad vertices");
+    set_name(index_buffer.buffer().as_ref(), "quad indices");
+
+    (vertex_buffer, index_buffer)
+}
+
+/// Pipeline layout, pipeline and the scene storage buffers, or the 2D shapes, to bind to set 0.
+pub type ScenePipeline = (Arc<PipelineLayout>, Arc<GraphicsPipeline>, Option<Arc<PersistentDescriptorSet>>);
+
+/// Compiles the shaders for `scene`, to build its pipelines from, and uploads its buffers. With `codegen` the fragment
+/// shader gets a `map()` generated for the scene; otherwise the shader interprets the scene program. Either way the scene's
+/// buffers are returned as the descriptor set to bind. With `shader_dir` the shaders are compiled from the GLSL files in it
+/// instead of using the built-in ones; `cache` is the pipeline cache the variants are created with.
+#[allow(clippy::too_many_arguments)]
+pub fn build_scene_pipelines(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    scene: &Scene,
+    codegen: bool,
+    shader_dir: Option<&Path>,
+    cache: Option<Arc<PipelineCache>>,
+) -> Result<(ScenePipelines, Option<Arc<PersistentDescriptorSet>>), ShaderError> {
+    let shaders = shaders::load(device.clone(), shader_dir, codegen.then_some(scene))?;
+    let pipelines = ScenePipelines::new(device, render_pass, shaders, cache)?;
+
+    let scene = scene.compile().expect("scene was validated when it was loaded");
+    let scene = init_scene(buffer_allocator, descriptor_set_allocator, &pipelines.layout(), scene);
+    Ok((pipelines, scene))
+}
+
+/// Like `build_scene_pipelines`, but falls back to the built-in interpreting shader, which always works, on errors.
+#[allow(clippy::too_many_arguments)]
+pub fn init_scene_pipelines(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    scene: &Scene,
+    codegen: bool,
+    shader_dir: Option<&Path>,
+    cache: Option<Arc<PipelineCache>>,
+) -> (ScenePipelines, Option<Arc<PersistentDescriptorSet>>) {
+    build_scene_pipelines(
+        device.clone(), render_pass.clone(), buffer_allocator.clone(), descriptor_set_allocator, scene, codegen, shader_dir, cache.clone()
+    ).unwrap_or_else(|e| {
+        eprintln!("{}\nFalling back to the built-in scene interpreter", e);
+        build_scene_pipelines(device, render_pass, buffer_allocator, descriptor_set_allocator, scene, false, None, cache)
+            .expect("Failed to create graphics pipeline")
+    })
+}
+
+/// Builds the pipeline of the 2D SDF mode and uploads `shapes` for it; there has to be at least one shape.
+pub fn build_2d_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    shapes: &[Shape2d],
+    shader_dir: Option<&Path>,
+) -> Result<ScenePipeline, ShaderError> {
+    let shaders = shaders::load_2d(device.clone(), shader_dir)?;
+    let (pipeline_layout, pipeline) = init_pipeline(device, render_pass, shaders)?;
+    let shapes = init_storage_buffer(buffer_allocator, shapes.to_vec(), "2D shapes");
+    let shapes = PersistentDescriptorSet::new(
+        descriptor_set_allocator,
+        pipeline.layout().set_layouts()[0].clone(),
+        [WriteDescriptorSet::buffer(0, shapes)],
+        []
+    ).expect("Failed to create 2D shapes descriptor set");
+    Ok((pipeline_layout, pipeline, Some(shapes)))
+}
+
+/// Like `init_scene_pipelines` for the 2D mode: falls back to the built-in 2D shader if the one in `shader_dir` fails.
+pub fn init_2d_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    shapes: &[Shape2d],
+    shader_dir: Option<&Path>,
+) -> ScenePipeline {
+    build_2d_pipeline(device.clone(), render_pass.clone(), buffer_allocator.clone(), descriptor_set_allocator, shapes, shader_dir)
+        .unwrap_or_else(|e| {
+            eprintln!("{}\nFalling back to the built-in 2D shader", e);
+            build_2d_pipeline(device, render_pass, buffer_allocator, descriptor_set_allocator, shapes, None)
+                .expect("Failed to create graphics pipeline")
+        })
+}
+
+/// Uploads `data`, which must not be empty, into a storage buffer; `name` labels it for the validation layer.
+fn init_storage_buffer<T: BufferContents>(buffer_allocator: Arc<StandardMemoryAllocator>, data: Vec<T>, name: &str) -> Subbuffer<[T]> {
+    let buffer = Buffer::from_iter(
+        buffer_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::STORAGE_BUFFER,
+            ..BufferCreateInfo::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..AllocationCreateInfo::default()
+        },
+        data
+    ).expect("Failed to create scene buffer");
+    set_name(buffer.buffer().as_ref(), name);
+    buffer
+}
+
+/// Uploads the scene program, material table and lights and binds the ones the shader declares to set 0 of `layout`;
+/// `None` if it declares none of them.
+fn init_scene(
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    layout: &PipelineLayout,
+    scene: CompiledScene,
+) -> Option<Arc<PersistentDescriptorSet>> {
+    let layout = layout.set_layouts().first()?;
+    let declared = |binding| layout.bindings().contains_key(&binding);
+
+    let mut writes = Vec::new();
+    if declared(PROGRAM_BINDING) {
+        let program = init_storage_buffer(buffer_allocator.clone(), scene.program, "scene program");
+        writes.push(WriteDescriptorSet::buffer(PROGRAM_BINDING, program));
+    }
+    if declared(MATERIALS_BINDING) {
+        let materials = init_storage_buffer(buffer_allocator.clone(), scene.materials, "scene materials");
+        writes.push(WriteDescriptorSet::buffer(MATERIALS_BINDING, materials));
+    }
+    if declared(LIGHTS_BINDING) {
+        let lights = init_storage_buffer(buffer_allocator, scene.lights, "scene lights");
+        writes.push(WriteDescriptorSet::buffer(LIGHTS_BINDING, lights));
+    }
+    if writes.is_empty() {
+        return None;
+    }
+
+    let set = PersistentDescriptorSet::new(descriptor_set_allocator, layout.clone(), writes, [])
+        .expect("Failed to create scene descriptor set");
+    Some(set)
+}
+
+/// A host-visible uniform buffer for one frame's `Constants`.
+pub fn init_frame_uniforms(buffer_allocator: Arc<StandardMemoryAllocator>, name: &str) -> Subbuffer<Constants> {
+    let buffer = Buffer::new_sized(
+        buffer_allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::UNIFORM_BUFFER,
+            ..BufferCreateInfo::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+            ..AllocationCreateInfo::default()
+        }
+    ).expect("Failed to create uniform buffer");
+    set_name(buffer.buffer().as_ref(), name);
+    buffer
+}
+
+/// Binds `uniforms` to `FRAME_SET` of `pipeline`; `None` for pipelines without per-frame uniforms, like the 2D mode's.
+pub fn bind_frame_uniforms(
+    descriptor_set_allocator: &StandardDescriptorSetAllocator,
+    pipeline: &GraphicsPipeline,
+    uniforms: Subbuffer<Constants>,
+) -> Option<Arc<PersistentDescriptorSet>> {
+    let layout = pipeline.layout().set_layouts().get(FRAME_SET as usize)?;
+    let set = PersistentDescriptorSet::new(
+        descriptor_set_allocator,
+        layout.clone(),
+        [WriteDescriptorSet::buffer(0, uniforms)],
+        []
+    ).expect("Failed to create frame descriptor set");
+    Some(set)
+}
+
+/// `descriptor_set` is bound to set 0, `frame_set` to `FRAME_SET`; pipelines whose data all fits into uniforms have no
+/// `push_constants`.
+#[allow(clippy::too_many_arguments)]
+pub fn record_draw<P: BufferContents>(
+    builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    framebuffer: Arc<Framebuffer>,
+    viewport: &Viewport,
+    pipeline_layout: Arc<PipelineLayout>,
+    pipeline: Arc<GraphicsPipeline>,
+    push_constants: Option<P>,
+    descriptor_set: Option<Arc<PersistentDescriptorSet>>,
+    frame_set: Option<Arc<PersistentDescriptorSet>>,
+    vertex_buffer: Subbuffer<[MyVertex]>,
+    index_buffer: Subbuffer<[u32]>,
+) {
+    let clear_values = vec![Some([0.0, 0.0, 0.0, 1.0].into())];
+    let index_count = index_buffer.len() as u32;
+
+    builder
+        .begin_render_pass(
+            RenderPassBeginInfo {
+                clear_values,
+                ..RenderPassBeginInfo::framebuffer(framebuffer)
+            },
+            SubpassBeginInfo {
+                contents: SubpassContents::Inline,
+                ..SubpassBeginInfo::default()
+            }
+        ).unwrap()
+        .set_viewport(0, vec![viewport.clone()].into()).unwrap()
+        .bind_pipeline_graphics(pipeline).unwrap();
+    if let Some(push_constants) = push_constants {
+        builder.push_constants(pipeline_layout.clone(), 0, push_constants).unwrap();
+    }
+    if let Some(descriptor_set) = descriptor_set {
+        builder.bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline_layout.clone(), 0, descriptor_set).unwrap();
+    }
+    if let Some(frame_set) = frame_set {
+        builder.bind_descriptor_sets(PipelineBindPoint::Graphics, pipeline_layout, FRAME_SET, frame_set).unwrap();
+    }
+    builder
+        .bind_vertex_buffers(0, vec![vertex_buffer]).unwrap()
+        .bind_index_buffer(index_buffer).unwrap()
+        .draw_indexed(index_count, 1, 0, 0, 0).unwrap()
+        .end_render_pass(SubpassEndInfo::default()).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::{offset_of, size_of};
+    use super::*;
+
+    /// The std140 offsets of the `Frame` block in `shaders/lib/frame.glsl`.
+    #[test]
+    fn constants_match_the_frame_block() {
+        assert_eq!(offset_of!(Constants, camera_position), 64);
+        assert_eq!(offset_of!(Constants, resolution), 80);
+        assert_eq!(offset_of!(Constants, frame_index), 112);
+        assert_eq!(offset_of!(Constants, params), 128);
+        assert_eq!(offset_of!(Constants, quality), 192);
+        assert_eq!(offset_of!(Constants, fov), 192 + size_of::<Quality>());
+        assert_eq!(offset_of!(Constants, jitter), 232);
+        assert_eq!(size_of::<Constants>(), 240);
+    }
+}
@@ -0,0 +1,110 @@
+//! The Vulkan pipeline cache, saved to disk when the viewer exits and loaded at the next start, so the driver doesn't
+//! compile the pipelines of every shader variant again. Drivers trust the data they are given, so it is only handed
+//! over if the file is complete and was written by the same device and driver.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::device::Device;
+use vulkano::pipeline::cache::{PipelineCache, PipelineCacheCreateInfo};
+
+/// Our own header in front of the driver's data: the magic, the data length and its FNV-1a hash, which catch files cut
+/// short or damaged on disk.
+const MAGIC: &[u8; 8] = b"VRMPC001";
+const HEADER_SIZE: usize = 24;
+
+/// Size of `VkPipelineCacheHeaderVersionOne`, which starts the driver's data.
+const VULKAN_HEADER_SIZE: usize = 32;
+const VULKAN_HEADER_VERSION_ONE: u32 = 1;
+
+/// `pipeline-cache.bin` in the user's cache directory: `$XDG_CACHE_HOME`, `~/.cache` or `%LOCALAPPDATA%`.
+pub fn default_path() -> Option<PathBuf> {
+    let non_empty = |name| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    let dir = non_empty("XDG_CACHE_HOME")
+        .or_else(|| non_empty("HOME").map(|home| home.join(".cache")))
+        .or_else(|| non_empty("LOCALAPPDATA"))?;
+    Some(dir.join("vulkan-raymarching").join("pipeline-cache.bin"))
+}
+
+/// Creates the pipeline cache with the data saved at `path`, or empty if there is none or it doesn't fit this device.
+pub fn load(device: Arc<Device>, path: Option<&Path>) -> Arc<PipelineCache> {
+    let initial_data = path.and_then(|path| match fs::read(path) {
+        Ok(file) => match unwrap_data(&file, device.physical_device()) {
+            Ok(data) => {
+                log::info!("Loaded {} bytes of pipeline cache from {}", data.len(), path.display());
+                Some(data.to_vec())
+            }
+            Err(reason) => {
+                log::info!("Ignoring the pipeline cache in {}: {}", path.display(), reason);
+                None
+            }
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            log::warn!("Failed to read the pipeline cache {}: {}", path.display(), e);
+            None
+        }
+    });
+
+    // Safety: the data was written by `save` for a device with the same IDs and pipeline cache UUID, and its hash matches.
+    let cache = unsafe {
+        PipelineCache::new(device.clone(), PipelineCacheCreateInfo {
+            initial_data: initial_data.unwrap_or_default(),
+            ..PipelineCacheCreateInfo::default()
+        })
+    };
+    cache.or_else(|e| {
+        log::warn!("The driver rejected the pipeline cache, starting with an empty one: {}", e);
+        unsafe { PipelineCache::new(device, PipelineCacheCreateInfo::default()) }
+    }).expect("Failed to create pipeline cache")
+}
+
+/// Writes the cache's data to `path`, through a temporary file so an interrupted write can't leave a broken cache behind.
+pub fn save(cache: &PipelineCache, path: &Path) -> io::Result<()> {
+    let data = cache.get_data().map_err(|e| io::Error::other(e.to_string()))?;
+    let mut file = Vec::with_capacity(HEADER_SIZE + data.len());
+    file.extend_from_slice(MAGIC);
+    file.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    file.extend_from_slice(&fnv1a(&data).to_le_bytes());
+    file.extend_from_slice(&data);
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, file)?;
+    fs::rename(&temporary, path)
+}
+
+/// The driver's data in `file`, if it is intact and `device` can use it.
+fn unwrap_data<'a>(file: &'a [u8], device: &PhysicalDevice) -> Result<&'a [u8], &'static str> {
+    if file.len() < HEADER_SIZE || &file[..8] != MAGIC {
+        return Err("not a pipeline cache file");
+    }
+    let (header, data) = file.split_at(HEADER_SIZE);
+    let length = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let hash = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    if length != data.len() as u64 || hash != fnv1a(data) {
+        return Err("the file is damaged");
+    }
+
+    // VkPipelineCacheHeaderVersionOne, which is always little endian: header size, version, vendor and device ID, UUID.
+    if data.len() < VULKAN_HEADER_SIZE {
+        return Err("the data has no header");
+    }
+    let word = |index: usize| u32::from_le_bytes(data[index * 4..index * 4 + 4].try_into().unwrap());
+    let properties = device.properties();
+    if word(0) < VULKAN_HEADER_SIZE as u32 || word(1) != VULKAN_HEADER_VERSION_ONE {
+        return Err("unknown header version");
+    }
+    if word(2) != properties.vendor_id || word(3) != properties.device_id || data[16..32] != properties.pipeline_cache_uuid {
+        return Err("it was written by another device or driver");
+    }
+    Ok(data)
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
@@ -0,0 +1,318 @@
+//! Raymarching quality: the step counts and epsilons of `shaders/raymarching-3d.frag`, passed in the per-frame uniforms so
+//! they can change every frame without rebuilding the pipeline. `--quality` picks a preset, `Q` cycles them in the viewer.
+
+use std::fmt;
+use std::str::FromStr;
+use vulkano::buffer::BufferContents;
+
+/// The quality members of the `Frame` uniform block, in the same order.
+#[repr(C)]
+#[derive(BufferContents, Clone, Copy, Debug, PartialEq)]
+pub struct Quality {
+    /// Steps `march` takes before giving up on a ray.
+    pub max_steps: u32,
+    /// Distance at which rays stop and show the background.
+    pub far_plane: f32,
+    /// A ray hits when the scene is closer than this times the distance it travelled.
+    pub hit_epsilon: f32,
+    /// Offset of the finite differences in `calcNormal`.
+    pub normal_epsilon: f32,
+    /// Steps of each shadow ray in `calculateShadow`; 0 turns shadows off.
+    pub shadow_steps: u32,
+    /// Higher values give harder shadow edges.
+    pub shadow_softness: f32,
+    /// Samples along the normal in `calculateAO`; 0 turns ambient occlusion off.
+    pub ao_taps: u32,
+    /// Surfaces a path of the path-tracing variant bounces off after the first; 0 leaves direct light only.
+    pub bounces: u32,
+}
+
+impl Default for Quality {
+    fn default() -> Self {
+        QualityPreset::High.quality()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QualityPreset {
+    Low,
+    Medium,
+    High,
+    Ultra,
+}
+
+impl QualityPreset {
+    /// High is what the shader used to hard-code.
+    pub fn quality(self) -> Quality {
+        match self {
+            QualityPreset::Low => Quality {
+                max_steps: 64,
+                far_plane: 250.0,
+                hit_epsilon: 0.001,
+                normal_epsilon: 0.001,
+                shadow_steps: 12,
+                shadow_softness: 16.0,
+                ao_taps: 2,
+                bounces: 2,
+            },
+            QualityPreset::Medium => Quality {
+                max_steps: 128,
+                far_plane: 500.0,
+                hit_epsilon: 0.0005,
+                normal_epsilon: 0.0005,
+                shadow_steps: 16,
+                shadow_softness: 16.0,
+                ao_taps: 3,
+                bounces: 3,
+            },
+            QualityPreset::High => Quality {
+                max_steps: 256,
+                far_plane: 1000.0,
+                hit_epsilon: 0.0001,
+                normal_epsilon: 0.0001,
+                shadow_steps: 25,
+                shadow_softness: 16.0,
+                ao_taps: 5,
+                bounces: 4,
+            },
+            QualityPreset::Ultra => Quality {
+                max_steps: 1024,
+                far_plane: 2000.0,
+                hit_epsilon: 0.00005,
+                normal_epsilon: 0.0001,
+                shadow_steps: 64,
+                shadow_softness: 16.0,
+                ao_taps: 8,
+                bounces: 8,
+            },
+        }
+    }
+
+    /// The next preset, for cycling through them in the viewer.
+    pub fn next(self) -> Self {
+        match self {
+            QualityPreset::Low => QualityPreset::Medium,
+            QualityPreset::Medium => QualityPreset::High,
+            QualityPreset::High => QualityPreset::Ultra,
+            QualityPreset::Ultra => QualityPreset::Low,
+        }
+    }
+}
+
+impl FromStr for QualityPreset {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "low" => Ok(QualityPreset::Low),
+            "medium" => Ok(QualityPreset::Medium),
+            "high" => Ok(QualityPreset::High),
+            "ultra" => Ok(QualityPreset::Ultra),
+            _ => Err(format!("'{}' is not a quality preset, expected low, medium, high or ultra", value)),
+        }
+    }
+}
+
+impl fmt::Display for QualityPreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QualityPreset::Low => write!(f, "Low"),
+            QualityPreset::Medium => write!(f, "Medium"),
+            QualityPreset::High => write!(f, "High"),
+            QualityPreset::Ultra => write!(f, "Ultra"),
+        }
+    }
+}
+
+/// One `Quality` value set on top of a preset, named like on the command line.
+#[derive(Clone, Copy, Debug)]
+enum Override {
+    Steps(u32),
+    FarPlane(f32),
+    HitEpsilon(f32),
+    NormalEpsilon(f32),
+    ShadowSteps(u32),
+    ShadowSoftness(f32),
+    AoTaps(u32),
+    Bounces(u32),
+}
+
+impl Override {
+    /// The name on the command line.
+    fn name(self) -> &'static str {
+        match self {
+            Override::Steps(_) => "steps",
+            Override::FarPlane(_) => "far-plane",
+            Override::HitEpsilon(_) => "hit-epsilon",
+            Override::NormalEpsilon(_) => "normal-epsilon",
+            Override::ShadowSteps(_) => "shadow-steps",
+            Override::ShadowSoftness(_) => "shadow-softness",
+            Override::AoTaps(_) => "ao-taps",
+            Override::Bounces(_) => "bounces",
+        }
+    }
+
+    /// The value as `from_str` takes it.
+    fn value(&self) -> &dyn fmt::Display {
+        match self {
+            Override::Steps(value)
+            | Override::ShadowSteps(value)
+            | Override::AoTaps(value)
+            | Override::Bounces(value) => value,
+            Override::FarPlane(value)
+            | Override::HitEpsilon(value)
+            | Override::NormalEpsilon(value)
+            | Override::ShadowSoftness(value) => value,
+        }
+    }
+
+    fn apply(self, quality: &mut Quality) {
+        match self {
+            Override::Steps(value) => quality.max_steps = value,
+            Override::FarPlane(value) => quality.far_plane = value,
+            Override::HitEpsilon(value) => quality.hit_epsilon = value,
+            Override::NormalEpsilon(value) => quality.normal_epsilon = value,
+            Override::ShadowSteps(value) => quality.shadow_steps = value,
+            Override::ShadowSoftness(value) => quality.shadow_softness = value,
+            Override::AoTaps(value) => quality.ao_taps = value,
+            Override::Bounces(value) => quality.bounces = value,
+        }
+    }
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (name, number) = value.split_once('=').ok_or_else(|| format!("'{}' should be <NAME>=<VALUE>", value))?;
+        let count = || number.trim().parse::<u32>().map_err(|_| format!("'{}' is not a valid count", number));
+        let positive = || match number.trim().parse::<f32>() {
+            Ok(number) if number > 0.0 && number.is_finite() => Ok(number),
+            _ => Err(format!("{} must be a positive number, got '{}'", name, number)),
+        };
+        match name.trim() {
+            "steps" => match count()? {
+                0 => Err("steps must not be zero".to_string()),
+                steps => Ok(Override::Steps(steps)),
+            },
+            "far-plane" => Ok(Override::FarPlane(positive()?)),
+            "hit-epsilon" => Ok(Override::HitEpsilon(positive()?)),
+            "normal-epsilon" => Ok(Override::NormalEpsilon(positive()?)),
+            "shadow-steps" => Ok(Override::ShadowSteps(count()?)),
+            "shadow-softness" => Ok(Override::ShadowSoftness(positive()?)),
+            "ao-taps" => Ok(Override::AoTaps(count()?)),
+            "bounces" => Ok(Override::Bounces(count()?)),
+            _ => Err(format!(
+                "'{}' is not a quality setting, expected steps, far-plane, hit-epsilon, normal-epsilon, shadow-steps, \
+                 shadow-softness, ao-taps or bounces",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name(), self.value())
+    }
+}
+
+/// `--quality`: a preset and the settings changed on top of it, which stay when the viewer switches presets.
+#[derive(Clone, Debug)]
+pub struct QualitySettings {
+    pub preset: QualityPreset,
+    overrides: Vec<Override>,
+}
+
+impl QualitySettings {
+    pub fn quality(&self) -> Quality {
+        let mut quality = self.preset.quality();
+        for setting in &self.overrides {
+            setting.apply(&mut quality);
+        }
+        quality
+    }
+}
+
+impl Default for QualitySettings {
+    fn default() -> Self {
+        Self { preset: QualityPreset::High, overrides: Vec::new() }
+    }
+}
+
+impl FromStr for QualitySettings {
+    type Err = String;
+
+    /// `<PRESET>[,<NAME>=<VALUE>...]`, e.g. `medium,shadow-steps=40`. Each setting may appear once, so a typo can't
+    /// silently undo an earlier value.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(',');
+        let preset = parts.next().unwrap_or_default().parse()?;
+        let mut overrides: Vec<Override> = Vec::new();
+        for part in parts {
+            let setting: Override = part.parse()?;
+            if overrides.iter().any(|other| other.name() == setting.name()) {
+                return Err(format!("{} is set more than once", setting.name()));
+            }
+            overrides.push(setting);
+        }
+        Ok(Self { preset, overrides })
+    }
+}
+
+impl fmt::Display for QualitySettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.preset)?;
+        for setting in &self.overrides {
+            write!(f, ", {}", setting)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_preset_with_overrides() {
+        let settings: QualitySettings = "medium, shadow-steps=40 ,ao-taps=0,far-plane=20.5".parse().unwrap();
+        assert_eq!(settings.preset, QualityPreset::Medium);
+        let quality = settings.quality();
+        assert_eq!(quality.shadow_steps, 40);
+        assert_eq!(quality.ao_taps, 0);
+        assert_eq!(quality.far_plane, 20.5);
+        assert_eq!(quality.max_steps, QualityPreset::Medium.quality().max_steps);
+        assert_eq!(settings.to_string(), "Medium, shadow-steps=40, ao-taps=0, far-plane=20.5");
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert!("fast".parse::<QualitySettings>().unwrap_err().contains("not a quality preset"));
+        assert!("high,reflections=2".parse::<QualitySettings>().unwrap_err().contains("not a quality setting"));
+        assert!("high,steps".parse::<QualitySettings>().unwrap_err().contains("<NAME>=<VALUE>"));
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        for (value, error) in [
+            ("high,steps=0", "must not be zero"),
+            ("high,steps=-1", "not a valid count"),
+            ("high,ao-taps=2.5", "not a valid count"),
+            ("high,bounces=", "not a valid count"),
+            ("high,far-plane=0", "must be a positive number"),
+            ("high,hit-epsilon=-0.1", "must be a positive number"),
+            ("high,normal-epsilon=inf", "must be a positive number"),
+            ("high,shadow-softness=soft", "must be a positive number"),
+        ] {
+            let message = value.parse::<QualitySettings>().unwrap_err();
+            assert!(message.contains(error), "{}: {}", value, message);
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_keys() {
+        let message = "low,steps=32,ao-taps=1,steps=64".parse::<QualitySettings>().unwrap_err();
+        assert_eq!(message, "steps is set more than once");
+    }
+}
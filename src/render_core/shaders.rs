@@ -1,7 +1,48 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use shaderc::{CompileOptions, Compiler, EnvVersion, IncludeType, OptimizationLevel, ResolvedInclude, ShaderKind, TargetEnv};
+use vulkano::device::Device;
+use vulkano::shader::{EntryPoint, ShaderModule, ShaderModuleCreateInfo};
+use vulkano::{Validated, VulkanError};
+use crate::scene::{codegen, Scene};
+
 pub mod fs_raymarching {
     vulkano_shaders::shader! {
         ty: "fragment",
-        path: "shaders/raymarching-3d.frag"
+        path: "shaders/raymarching-3d.frag",
+        include: ["shaders/lib"]
+    }
+}
+
+pub mod fs_raymarching_2d {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shaders/raymarching-2d.frag",
+        include: ["shaders/lib"]
+    }
+}
+
+pub mod fs_tonemap {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shaders/tonemap.frag",
+        include: ["shaders/lib"]
+    }
+}
+
+pub mod fs_luminance {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shaders/luminance.frag",
+        include: ["shaders/lib"]
+    }
+}
+
+pub mod fs_accumulate {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "shaders/accumulate.frag"
     }
 }
 
@@ -10,4 +51,226 @@ pub mod vs_raymarching {
         ty: "vertex",
         path: "shaders/raymarching.vert"
     }
-}
\ No newline at end of file
+}
+
+/// Source of `fs_raymarching`, the template `scene::codegen` splices generated scenes into.
+pub const FRAGMENT_TEMPLATE: &str = include_str!("../../shaders/raymarching-3d.frag");
+/// The wrapper `shadertoy` splices the passes of a Shadertoy project into; only ever compiled at runtime.
+pub const SHADERTOY_TEMPLATE: &str = include_str!("../../shaders/shadertoy.frag");
+
+pub const VERTEX_FILE: &str = "raymarching.vert";
+pub const FRAGMENT_FILE: &str = "raymarching-3d.frag";
+pub const FRAGMENT_2D_FILE: &str = "raymarching-2d.frag";
+pub const TONEMAP_FILE: &str = "tonemap.frag";
+pub const ACCUMULATE_FILE: &str = "accumulate.frag";
+pub const LUMINANCE_FILE: &str = "luminance.frag";
+pub const SHADERTOY_FILE: &str = "shadertoy.frag";
+
+/// Directory of the shared GLSL library below the shader directory, searched by `#include <...>`.
+pub const LIBRARY_DIR: &str = "lib";
+
+/// The library as built into the binary, for compiling without a shader directory.
+const LIBRARY: [(&str, &str); 8] = [
+    ("lib/color.glsl", include_str!("../../shaders/lib/color.glsl")),
+    ("lib/frame.glsl", include_str!("../../shaders/lib/frame.glsl")),
+    ("lib/noise.glsl", include_str!("../../shaders/lib/noise.glsl")),
+    ("lib/sdf.glsl", include_str!("../../shaders/lib/sdf.glsl")),
+    ("lib/operators.glsl", include_str!("../../shaders/lib/operators.glsl")),
+    ("lib/lighting.glsl", include_str!("../../shaders/lib/lighting.glsl")),
+    ("lib/debug.glsl", include_str!("../../shaders/lib/debug.glsl")),
+    ("lib/sampling.glsl", include_str!("../../shaders/lib/sampling.glsl")),
+];
+
+/// `shaders/` of the source tree this binary was built from; the viewer compiles and watches it if it still exists.
+pub fn source_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("shaders")
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io(PathBuf, std::io::Error),
+    /// GLSL errors as reported by shaderc, one `file:line: error: ...` per line.
+    Compile(String),
+    Codegen(String),
+    Module(Validated<VulkanError>),
+    Pipeline(String),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ShaderError::Compile(message) => write!(f, "{}", message),
+            ShaderError::Codegen(message) => write!(f, "failed to generate the scene shader: {}", message),
+            ShaderError::Module(e) => write!(f, "failed to create shader module: {}", e),
+            ShaderError::Pipeline(message) => write!(f, "failed to create pipeline: {}", message),
+        }
+    }
+}
+
+/// Vertex and fragment entry points of the raymarching pipeline.
+pub struct RaymarchingShaders {
+    pub vertex: EntryPoint,
+    pub fragment: EntryPoint,
+}
+
+/// Loads the shaders. `dir` compiles the GLSL files in it at runtime instead of using the ones built into the binary,
+/// `scene` replaces the interpreting `map()` of the fragment shader with one generated for that scene.
+pub fn load(device: Arc<Device>, dir: Option<&Path>, scene: Option<&Scene>) -> Result<RaymarchingShaders, ShaderError> {
+    let (vertex, fragment) = match dir {
+        Some(dir) => {
+            let vertex_source = read(&dir.join(VERTEX_FILE))?;
+            let vertex = compile(device.clone(), &vertex_source, ShaderKind::Vertex, VERTEX_FILE, Some(dir))?;
+            (vertex, fragment_module(device, &read(&dir.join(FRAGMENT_FILE))?, scene, Some(dir))?)
+        }
+        None => {
+            let vertex = vs_raymarching::load(device.clone()).map_err(ShaderError::Module)?;
+            let fragment = match scene {
+                Some(_) => fragment_module(device, FRAGMENT_TEMPLATE, scene, None)?,
+                None => fs_raymarching::load(device).map_err(ShaderError::Module)?,
+            };
+            (vertex, fragment)
+        }
+    };
+
+    Ok(RaymarchingShaders {
+        vertex: vertex.entry_point("main").unwrap(),
+        fragment: fragment.entry_point("main").unwrap(),
+    })
+}
+
+/// Loads the shaders of the 2D SDF mode, from `dir` like `load` if given.
+pub fn load_2d(device: Arc<Device>, dir: Option<&Path>) -> Result<RaymarchingShaders, ShaderError> {
+    load_full_screen(device, dir, FRAGMENT_2D_FILE, fs_raymarching_2d::load)
+}
+
+/// Loads the shaders of the tone mapping pass, from `dir` like `load` if given.
+pub fn load_tonemap(device: Arc<Device>, dir: Option<&Path>) -> Result<RaymarchingShaders, ShaderError> {
+    load_full_screen(device, dir, TONEMAP_FILE, fs_tonemap::load)
+}
+
+/// Loads the shaders of the temporal accumulation pass, from `dir` like `load` if given.
+pub fn load_accumulate(device: Arc<Device>, dir: Option<&Path>) -> Result<RaymarchingShaders, ShaderError> {
+    load_full_screen(device, dir, ACCUMULATE_FILE, fs_accumulate::load)
+}
+
+/// Loads the shaders of the auto exposure pass, from `dir` like `load` if given.
+pub fn load_luminance(device: Arc<Device>, dir: Option<&Path>) -> Result<RaymarchingShaders, ShaderError> {
+    load_full_screen(device, dir, LUMINANCE_FILE, fs_luminance::load)
+}
+
+/// The Shadertoy wrapper, from `dir` if given, to splice passes into.
+pub fn shadertoy_template(dir: Option<&Path>) -> Result<String, ShaderError> {
+    match dir {
+        Some(dir) => read(&dir.join(SHADERTOY_FILE)),
+        None => Ok(SHADERTOY_TEMPLATE.to_string()),
+    }
+}
+
+/// The full-screen quad's vertex shader, from `dir` like `load` if given, with `fragment_source` compiled as the fragment
+/// shader; `name` is what compile errors outside of `#line` sections refer to.
+pub fn load_shadertoy(device: Arc<Device>, dir: Option<&Path>, fragment_source: &str, name: &str) -> Result<RaymarchingShaders, ShaderError> {
+    let vertex = match dir {
+        Some(dir) => compile(device.clone(), &read(&dir.join(VERTEX_FILE))?, ShaderKind::Vertex, VERTEX_FILE, Some(dir))?,
+        None => vs_raymarching::load(device.clone()).map_err(ShaderError::Module)?,
+    };
+    let fragment = compile(device, fragment_source, ShaderKind::Fragment, name, dir)?;
+
+    Ok(RaymarchingShaders {
+        vertex: vertex.entry_point("main").unwrap(),
+        fragment: fragment.entry_point("main").unwrap(),
+    })
+}
+
+/// `load` of a `shader!` module.
+type BuiltInShader = fn(Arc<Device>) -> Result<Arc<ShaderModule>, Validated<VulkanError>>;
+
+/// The full-screen quad's vertex shader with the fragment shader `file` from `dir`, or `built_in` without `dir`.
+fn load_full_screen(
+    device: Arc<Device>,
+    dir: Option<&Path>,
+    file: &str,
+    built_in: BuiltInShader,
+) -> Result<RaymarchingShaders, ShaderError> {
+    let (vertex, fragment) = match dir {
+        Some(dir) => {
+            let vertex_source = read(&dir.join(VERTEX_FILE))?;
+            let vertex = compile(device.clone(), &vertex_source, ShaderKind::Vertex, VERTEX_FILE, Some(dir))?;
+            let fragment_source = read(&dir.join(file))?;
+            (vertex, compile(device, &fragment_source, ShaderKind::Fragment, file, Some(dir))?)
+        }
+        None => (
+            vs_raymarching::load(device.clone()).map_err(ShaderError::Module)?,
+            built_in(device).map_err(ShaderError::Module)?,
+        ),
+    };
+
+    Ok(RaymarchingShaders {
+        vertex: vertex.entry_point("main").unwrap(),
+        fragment: fragment.entry_point("main").unwrap(),
+    })
+}
+
+fn fragment_module(
+    device: Arc<Device>,
+    template: &str,
+    scene: Option<&Scene>,
+    dir: Option<&Path>,
+) -> Result<Arc<ShaderModule>, ShaderError> {
+    match scene {
+        Some(scene) => {
+            let source = codegen::fragment_source(template, scene).map_err(|e| ShaderError::Codegen(e.to_string()))?;
+            compile(device, &source, ShaderKind::Fragment, FRAGMENT_FILE, dir)
+        }
+        None => compile(device, template, ShaderKind::Fragment, FRAGMENT_FILE, dir),
+    }
+}
+
+fn read(path: &Path) -> Result<String, ShaderError> {
+    std::fs::read_to_string(path).map_err(|e| ShaderError::Io(path.to_path_buf(), e))
+}
+
+/// Compiles GLSL to a shader module at runtime; `name` is what compile errors refer to, a path relative to `dir`.
+/// Includes are resolved like the `shader!` macros do at build time: `#include "..."` relative to the including file,
+/// `#include <...>` in the library. Without `dir` they come from the library built into the binary.
+pub fn compile(
+    device: Arc<Device>,
+    source: &str,
+    kind: ShaderKind,
+    name: &str,
+    dir: Option<&Path>,
+) -> Result<Arc<ShaderModule>, ShaderError> {
+    let compiler = Compiler::new().ok_or_else(|| ShaderError::Compile("failed to initialize shaderc".to_string()))?;
+    let mut options = CompileOptions::new().ok_or_else(|| ShaderError::Compile("failed to initialize shaderc".to_string()))?;
+    options.set_target_env(TargetEnv::Vulkan, EnvVersion::Vulkan1_0 as u32);
+    options.set_optimization_level(OptimizationLevel::Performance);
+    let dir = dir.map(Path::to_path_buf);
+    options.set_include_callback(move |requested, ty, including, _depth| resolve_include(dir.as_deref(), requested, ty, including));
+
+    let artifact = compiler
+        .compile_into_spirv(source, kind, name, "main", Some(&options))
+        .map_err(|e| ShaderError::Compile(e.to_string()))?;
+
+    // SAFETY: shaderc only returns successfully compiled, valid SPIR-V.
+    unsafe { ShaderModule::new(device, ShaderModuleCreateInfo::new(artifact.as_binary())) }.map_err(ShaderError::Module)
+}
+
+/// Names are paths relative to the shader directory, so errors in included files read e.g. `lib/sdf.glsl:12: error: ...`.
+fn resolve_include(dir: Option<&Path>, requested: &str, ty: IncludeType, including: &str) -> Result<ResolvedInclude, String> {
+    let path = match ty {
+        IncludeType::Relative => Path::new(including).parent().unwrap_or(Path::new("")).join(requested),
+        IncludeType::Standard => Path::new(LIBRARY_DIR).join(requested),
+    };
+    // Forward slashes on every platform, to match the keys of `LIBRARY`.
+    let name = path.to_string_lossy().replace('\\', "/");
+
+    let content = match dir {
+        Some(dir) => std::fs::read_to_string(dir.join(&path)).map_err(|e| format!("{}: {}", name, e))?,
+        None => LIBRARY
+            .iter()
+            .find(|(file, _)| *file == name)
+            .map(|(_, content)| content.to_string())
+            .ok_or_else(|| format!("{}: not in the built-in shader library", name))?,
+    };
+    Ok(ResolvedInclude { resolved_name: name, content })
+}
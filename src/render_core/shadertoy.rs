@@ -0,0 +1,333 @@
+//! Runs a Shadertoy project: each buffer pass draws into a pair of float images it alternates between every frame, so
+//! passes can read a buffer's previous frame while it's being overwritten, then the Image pass draws to the swapchain.
+
+use std::path::Path;
+use std::sync::Arc;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::allocator::{StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, ClearColorImageInfo, CommandBufferUsage, CopyBufferToImageInfo, PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::device::{Device, Queue};
+use vulkano::format::{Format, FormatFeatures};
+use vulkano::image::sampler::{Filter, Sampler, SamplerAddressMode, SamplerCreateInfo};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::graphics::viewport::Viewport;
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineLayout};
+use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass};
+use vulkano::sync::GpuFuture;
+use crate::render_core::pipeline;
+use crate::render_core::pipeline::{Constants, MyVertex, FRAME_SET};
+use crate::render_core::shaders;
+use crate::render_core::vulkano_core::{set_name, InitError};
+use crate::shadertoy::{load_texture, pass_name, Channel, Project, ShadertoyError, TextureData, MAX_CHANNELS};
+
+/// What a channel of a pass is bound to.
+#[derive(Clone)]
+enum Input {
+    Texture(Arc<ImageView>),
+    /// Index into the buffer targets; `previous` reads the image the buffer wrote the frame before.
+    Buffer { target: usize, previous: bool },
+}
+
+struct PassPipeline {
+    layout: Arc<PipelineLayout>,
+    pipeline: Arc<GraphicsPipeline>,
+    inputs: Vec<Input>,
+    /// Set 0 with the channels for either parity, see `ShadertoyRenderer::parity`.
+    sets: Vec<Arc<PersistentDescriptorSet>>,
+}
+
+/// The two images of a buffer pass.
+struct BufferTarget {
+    images: [Arc<Image>; 2],
+    views: [Arc<ImageView>; 2],
+    framebuffers: [Arc<Framebuffer>; 2],
+}
+
+pub struct ShadertoyRenderer {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    command_buffer_allocator: StandardCommandBufferAllocator,
+    descriptor_set_allocator: StandardDescriptorSetAllocator,
+    /// The swapchain's, which the Image pass draws into.
+    render_pass: Arc<RenderPass>,
+    buffer_render_pass: Arc<RenderPass>,
+    texture_sampler: Arc<Sampler>,
+    buffer_sampler: Arc<Sampler>,
+    /// The buffer passes in the order they run, then the Image pass.
+    passes: Vec<PassPipeline>,
+    targets: Vec<BufferTarget>,
+    extent: [u32; 2],
+    /// Which image of each pair the buffer passes write this frame; flips after every frame.
+    parity: usize,
+    /// New targets hold garbage until they are cleared at the start of the next frame.
+    clear_targets: bool,
+}
+
+impl ShadertoyRenderer {
+    /// `render_pass` is the swapchain's, `extent` its size; with `shader_dir` the wrapper and the library come from there.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        buffer_allocator: Arc<StandardMemoryAllocator>,
+        render_pass: Arc<RenderPass>,
+        project: &Project,
+        extent: [u32; 2],
+        shader_dir: Option<&Path>,
+    ) -> Result<Self, ShadertoyError> {
+        let buffer_render_pass = crate::render_core::vulkano_core::init_render_pass(device.clone(), buffer_format(&device))
+            .map_err(ShadertoyError::Init)?;
+        let mut renderer = Self {
+            command_buffer_allocator: StandardCommandBufferAllocator::new(device.clone(), StandardCommandBufferAllocatorCreateInfo::default()),
+            descriptor_set_allocator: StandardDescriptorSetAllocator::new(device.clone(), Default::default()),
+            texture_sampler: init_sampler(device.clone(), SamplerAddressMode::Repeat),
+            buffer_sampler: init_sampler(device.clone(), SamplerAddressMode::ClampToEdge),
+            device,
+            queue,
+            buffer_allocator,
+            render_pass,
+            buffer_render_pass,
+            passes: Vec::new(),
+            targets: Vec::new(),
+            extent,
+            parity: 0,
+            clear_targets: true,
+        };
+        renderer.reload(project, shader_dir)?;
+        Ok(renderer)
+    }
+
+    /// Rebuilds the passes from `project`, re-reading its sources and textures, and clears the buffers.
+    /// On failure the previous passes stay in use.
+    pub fn reload(&mut self, project: &Project, shader_dir: Option<&Path>) -> Result<(), ShadertoyError> {
+        project.validate()?;
+        let template = shaders::shadertoy_template(shader_dir).map_err(|e| ShadertoyError::Shader(shaders::SHADERTOY_FILE.to_string(), e))?;
+        let buffers: Vec<_> = project.buffers.keys().copied().collect();
+
+        let mut uploads = AutoCommandBufferBuilder::primary(
+            &self.command_buffer_allocator,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit
+        ).unwrap();
+        let empty = self.upload_texture(&mut uploads, TextureData {
+            extent: [1, 1],
+            format: Format::R8G8B8A8_UNORM,
+            texels: vec![0; 4],
+        }, "empty channel")?;
+
+        let mut passes = Vec::new();
+        for (index, (name, pass)) in project.passes().enumerate() {
+            let shader_error = |e| ShadertoyError::Shader(pass_name(name), e);
+            let source = project.fragment_source(&template, name, pass)?;
+            let shaders = shaders::load_shadertoy(self.device.clone(), shader_dir, &source, shaders::SHADERTOY_FILE)
+                .map_err(shader_error)?;
+            let render_pass = if name.is_some() { self.buffer_render_pass.clone() } else { self.render_pass.clone() };
+            let (layout, pipeline) = pipeline::init_pipeline_with_blend(self.device.clone(), render_pass, shaders, None)
+                .map_err(shader_error)?;
+            set_name(pipeline.as_ref(), &format!("Shadertoy {} pipeline", pass_name(name)));
+
+            let mut inputs = Vec::new();
+            for channel in (0..MAX_CHANNELS).map(|i| pass.channels.get(i).unwrap_or(&Channel::Empty)) {
+                inputs.push(match channel {
+                    Channel::Empty => Input::Texture(empty.clone()),
+                    Channel::Texture(path) => {
+                        let path = project.path(path);
+                        let texture = load_texture(&path)?;
+                        Input::Texture(self.upload_texture(&mut uploads, texture, &path.display().to_string())?)
+                    }
+                    Channel::Buffer(buffer) => {
+                        let target = buffers.iter().position(|b| b == buffer).expect("buffers were validated");
+                        Input::Buffer { target, previous: target >= index }
+                    }
+                });
+            }
+            passes.push(PassPipeline { layout, pipeline, inputs, sets: Vec::new() });
+        }
+
+        uploads.build().unwrap()
+            .execute(self.queue.clone()).unwrap()
+            .then_signal_fence_and_flush().expect("Failed to upload Shadertoy textures")
+            .wait(None).expect("Failed to upload Shadertoy textures");
+
+        let targets = self.init_targets(buffers.len())?;
+        self.passes = passes;
+        self.targets = targets;
+        self.bind_channels();
+        Ok(())
+    }
+
+    /// Recreates the buffers at the new swapchain size, cleared.
+    pub fn resize(&mut self, extent: [u32; 2]) -> Result<(), ShadertoyError> {
+        self.extent = extent;
+        self.targets = self.init_targets(self.targets.len())?;
+        self.bind_channels();
+        Ok(())
+    }
+
+    /// The Image pass's pipeline, to bind the frame uniforms to.
+    pub fn pipeline(&self) -> &GraphicsPipeline {
+        &self.passes.last().expect("there is always an Image pass").pipeline
+    }
+
+    /// Records the buffer passes, then the Image pass into `framebuffer`. `frame_set` has to be bound to `pipeline()`.
+    pub fn record(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        framebuffer: Arc<Framebuffer>,
+        viewport: &Viewport,
+        frame_set: Option<Arc<PersistentDescriptorSet>>,
+        vertex_buffer: Subbuffer<[MyVertex]>,
+        index_buffer: Subbuffer<[u32]>,
+    ) {
+        if self.clear_targets {
+            for image in self.targets.iter().flat_map(|target| &target.images) {
+                builder.clear_color_image(ClearColorImageInfo::image(image.clone())).unwrap();
+            }
+            self.clear_targets = false;
+        }
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let framebuffer = match self.targets.get(index) {
+                Some(target) => target.framebuffers[self.parity].clone(),
+                None => framebuffer.clone(),
+            };
+            // The optimizer drops the uniform block from passes that don't read it.
+            let frame_set = frame_set.clone().filter(|_| pass.layout.set_layouts().len() > FRAME_SET as usize);
+            pipeline::record_draw(
+                builder,
+                framebuffer,
+                viewport,
+                pass.layout.clone(),
+                pass.pipeline.clone(),
+                None::<Constants>,
+                Some(pass.sets[self.parity].clone()),
+                frame_set,
+                vertex_buffer.clone(),
+                index_buffer.clone(),
+            );
+        }
+        self.parity = 1 - self.parity;
+    }
+
+    fn upload_texture(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+        texture: TextureData,
+        name: &str,
+    ) -> Result<Arc<ImageView>, ShadertoyError> {
+        let image = Image::new(
+            self.buffer_allocator.clone(),
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: texture.format,
+                extent: [texture.extent[0], texture.extent[1], 1],
+                usage: ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+                ..ImageCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..AllocationCreateInfo::default()
+            }
+        ).map_err(|e| ShadertoyError::Init(InitError::Image(e)))?;
+        set_name(image.as_ref(), name);
+
+        let staging = Buffer::from_iter(
+            self.buffer_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::TRANSFER_SRC,
+                ..BufferCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_HOST | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..AllocationCreateInfo::default()
+            },
+            texture.texels
+        ).expect("Failed to create texture staging buffer");
+        builder.copy_buffer_to_image(CopyBufferToImageInfo::buffer_image(staging, image.clone())).unwrap();
+
+        ImageView::new_default(image).map_err(|e| ShadertoyError::Init(InitError::Framebuffer(e)))
+    }
+
+    fn init_targets(&mut self, count: usize) -> Result<Vec<BufferTarget>, ShadertoyError> {
+        self.clear_targets = true;
+        (0..count).map(|i| {
+            let image = |half| -> Result<_, InitError> {
+                let image = Image::new(
+                    self.buffer_allocator.clone(),
+                    ImageCreateInfo {
+                        image_type: ImageType::Dim2d,
+                        format: self.buffer_render_pass.attachments()[0].format,
+                        extent: [self.extent[0], self.extent[1], 1],
+                        usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_DST,
+                        ..ImageCreateInfo::default()
+                    },
+                    AllocationCreateInfo {
+                        memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                        ..AllocationCreateInfo::default()
+                    }
+                ).map_err(InitError::Image)?;
+                set_name(image.as_ref(), &format!("Shadertoy buffer {} image {}", i, half));
+                let view = ImageView::new_default(image.clone()).map_err(InitError::Framebuffer)?;
+                let framebuffer = Framebuffer::new(
+                    self.buffer_render_pass.clone(),
+                    FramebufferCreateInfo {
+                        attachments: vec![view.clone()],
+                        ..FramebufferCreateInfo::default()
+                    }
+                ).map_err(InitError::Framebuffer)?;
+                Ok((image, view, framebuffer))
+            };
+            let (first, second) = (image(0)?, image(1)?);
+            Ok(BufferTarget {
+                images: [first.0, second.0],
+                views: [first.1, second.1],
+                framebuffers: [first.2, second.2],
+            })
+        }).collect::<Result<_, InitError>>().map_err(ShadertoyError::Init)
+    }
+
+    /// Creates the channel sets of every pass for both parities, after the passes or the targets changed.
+    fn bind_channels(&mut self) {
+        for pass in &mut self.passes {
+            pass.sets = (0..2).map(|parity| {
+                let writes = pass.inputs.iter().enumerate().map(|(binding, input)| {
+                    let (view, sampler) = match input {
+                        Input::Texture(view) => (view.clone(), self.texture_sampler.clone()),
+                        Input::Buffer { target, previous } => {
+                            let image = if *previous { 1 - parity } else { parity };
+                            (self.targets[*target].views[image].clone(), self.buffer_sampler.clone())
+                        }
+                    };
+                    WriteDescriptorSet::image_view_sampler(binding as u32, view, sampler)
+                });
+                PersistentDescriptorSet::new(
+                    &self.descriptor_set_allocator,
+                    pass.pipeline.layout().set_layouts()[0].clone(),
+                    writes,
+                    []
+                ).expect("Failed to create Shadertoy channel descriptor set")
+            }).collect();
+        }
+    }
+}
+
+/// 32-bit float buffers like Shadertoy's where the device can render to and filter them, half floats otherwise.
+fn buffer_format(device: &Device) -> Format {
+    let required = FormatFeatures::COLOR_ATTACHMENT | FormatFeatures::SAMPLED_IMAGE_FILTER_LINEAR;
+    let supported = device.physical_device().format_properties(Format::R32G32B32A32_SFLOAT)
+        .is_ok_and(|properties| properties.optimal_tiling_features.contains(required));
+    if supported { Format::R32G32B32A32_SFLOAT } else { Format::R16G16B16A16_SFLOAT }
+}
+
+fn init_sampler(device: Arc<Device>, address_mode: SamplerAddressMode) -> Arc<Sampler> {
+    Sampler::new(device, SamplerCreateInfo {
+        mag_filter: Filter::Linear,
+        min_filter: Filter::Linear,
+        address_mode: [address_mode; 3],
+        ..SamplerCreateInfo::default()
+    }).expect("Failed to create Shadertoy sampler")
+}
@@ -0,0 +1,288 @@
+//! HDR rendering: the viewer draws into a float target, `shaders/tonemap.frag` maps it to the swapchain.
+//! Offline renders read the float target back instead, `ToneMapping::apply` maps them on the CPU for PNG and Y4M output,
+//! and EXR files keep the HDR values.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use vulkano::buffer::BufferContents;
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::image::sampler::{Sampler, SamplerCreateInfo, LOD_CLAMP_NONE};
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
+use vulkano::pipeline::{GraphicsPipeline, Pipeline, PipelineLayout};
+use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass};
+use crate::render_core::exposure::ExposureAverage;
+use crate::render_core::pipeline;
+use crate::render_core::shaders;
+use crate::render_core::shaders::ShaderError;
+use crate::render_core::vulkano_core::{set_name, InitError};
+
+/// Format of the viewer's HDR target; offline renders use `R32G32B32A32_SFLOAT`.
+pub const HDR_FORMAT: Format = Format::R16G16B16A16_SFLOAT;
+
+/// Auto exposure maps the average luminance of the frame, the mean of its logarithm, to this.
+const MIDDLE_GREY: f32 = 0.18;
+/// Darkest luminance auto exposure counts, so black pixels don't pull the average to zero; `MIN_LUMINANCE` in
+/// `shaders/luminance.frag`.
+const MIN_LUMINANCE: f32 = 1e-4;
+
+/// `operator` of `ToneMapConstants` that leaves the frame as it is, `OPERATOR_NONE` in `shaders/tonemap.frag`.
+const OPERATOR_NONE: u32 = 3;
+
+/// Curve from scene to display values; the discriminants are the `OPERATOR_*` constants of `shaders/tonemap.frag`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    Reinhard = 0,
+    Aces = 1,
+    Agx = 2,
+}
+
+impl ToneMapOperator {
+    /// The next operator, for cycling through them in the viewer.
+    pub fn next(self) -> Self {
+        match self {
+            ToneMapOperator::Reinhard => ToneMapOperator::Aces,
+            ToneMapOperator::Aces => ToneMapOperator::Agx,
+            ToneMapOperator::Agx => ToneMapOperator::Reinhard,
+        }
+    }
+
+    fn map(self, color: [f32; 3]) -> [f32; 3] {
+        match self {
+            ToneMapOperator::Reinhard => color.map(|c| c / (1.0 + c)),
+            ToneMapOperator::Aces => color.map(|c| ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)),
+            ToneMapOperator::Agx => agx(color),
+        }
+    }
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "aces" => Ok(ToneMapOperator::Aces),
+            "agx" => Ok(ToneMapOperator::Agx),
+            _ => Err(format!("'{}' is not a tone mapping operator, expected reinhard, aces or agx", value)),
+        }
+    }
+}
+
+impl fmt::Display for ToneMapOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToneMapOperator::Reinhard => write!(f, "Reinhard"),
+            ToneMapOperator::Aces => write!(f, "ACES"),
+            ToneMapOperator::Agx => write!(f, "AgX"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    /// In stops; with `auto_exposure` a compensation on top of the automatic exposure.
+    pub exposure: f32,
+    /// Scales each frame so its average luminance becomes middle grey.
+    pub auto_exposure: bool,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Aces,
+            exposure: 0.0,
+            auto_exposure: false,
+        }
+    }
+}
+
+impl fmt::Display for ToneMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, exposure {:+.1} EV", self.operator, self.exposure)?;
+        if self.auto_exposure {
+            write!(f, " (auto)")?;
+        }
+        Ok(())
+    }
+}
+
+impl ToneMapping {
+    /// Constants that draw the frame unchanged, without exposure or curve, for debug views whose colors carry values.
+    pub fn passthrough_constants() -> ToneMapConstants {
+        ToneMapConstants {
+            exposure: 1.0,
+            operator: OPERATOR_NONE,
+            auto_exposure: 0,
+            average_lod: 0.0,
+        }
+    }
+
+    pub fn constants(&self, average: &ExposureAverage) -> ToneMapConstants {
+        ToneMapConstants {
+            exposure: self.exposure.exp2(),
+            operator: self.operator as u32,
+            auto_exposure: self.auto_exposure as u32,
+            average_lod: average.average_lod(),
+        }
+    }
+
+    /// Tone maps linear HDR pixels in place like `shaders/tonemap.frag`, with the average over all of `pixels` for auto exposure.
+    pub fn apply(&self, pixels: &mut [[f32; 4]]) {
+        let mut exposure = self.exposure.exp2();
+        if self.auto_exposure && !pixels.is_empty() {
+            let log_sum = pixels.iter().map(|&[r, g, b, _]| luminance([r, g, b]).max(MIN_LUMINANCE).ln()).sum::<f32>();
+            exposure *= MIDDLE_GREY / (log_sum / pixels.len() as f32).exp();
+        }
+
+        for pixel in pixels {
+            let mapped = self.operator.map([pixel[0] * exposure, pixel[1] * exposure, pixel[2] * exposure]);
+            pixel[..3].copy_from_slice(&mapped);
+        }
+    }
+}
+
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Column-major like the GLSL `mat3` constructor: `m[column][row]`.
+fn mul(m: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|row| m[0][row] * v[0] + m[1][row] * v[1] + m[2][row] * v[2])
+}
+
+// The same digits as the shader, so the two are easy to compare.
+#[allow(clippy::excessive_precision)]
+fn agx(color: [f32; 3]) -> [f32; 3] {
+    const INSET: [[f32; 3]; 3] = [
+        [0.842479062253094, 0.0423282422610123, 0.0423756549057051],
+        [0.0784335999999992, 0.878468636469772, 0.0784336],
+        [0.0792237451477643, 0.0791661274605434, 0.879142973793104],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.19687900512017, -0.0528968517574562, -0.0529716355144438],
+        [-0.0980208811401368, 1.15190312990417, -0.0980434501171241],
+        [-0.0990297440797205, -0.0989611768448433, 1.15107367264116],
+    ];
+    const MIN_EV: f32 = -12.47393;
+    const MAX_EV: f32 = 4.026069;
+
+    let curve = mul(&INSET, color).map(|c| {
+        let x = (c.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+    });
+    mul(&OUTSET, curve).map(|c| c.max(0.0).powf(2.2))
+}
+
+/// Push constants of `shaders/tonemap.frag`.
+#[repr(C)]
+#[derive(BufferContents, Clone)]
+pub struct ToneMapConstants {
+    /// Linear scale, `2^exposure`.
+    pub exposure: f32,
+    pub operator: u32,
+    pub auto_exposure: u32,
+    /// Mip level of the log luminance image that holds the 1x1 average, see `ExposureAverage`.
+    pub average_lod: f32,
+}
+
+/// What the viewer renders into: a float image, which `ExposureAverage` averages for auto exposure.
+pub struct HdrTarget {
+    pub image: Arc<Image>,
+    pub framebuffer: Arc<Framebuffer>,
+    /// For the passes that read the frame.
+    view: Arc<ImageView>,
+}
+
+impl HdrTarget {
+    /// `render_pass` has to be made for `HDR_FORMAT`.
+    pub fn new(buffer_allocator: Arc<StandardMemoryAllocator>, render_pass: Arc<RenderPass>, extent: [u32; 2]) -> Result<Self, InitError> {
+        let extent = [extent[0], extent[1], 1];
+        let image = Image::new(
+            buffer_allocator,
+            ImageCreateInfo {
+                image_type: ImageType::Dim2d,
+                format: HDR_FORMAT,
+                extent,
+                usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::SAMPLED | ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST,
+                ..ImageCreateInfo::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+                ..AllocationCreateInfo::default()
+            }
+        ).map_err(InitError::Image)?;
+        set_name(image.as_ref(), "HDR target");
+
+        let view = ImageView::new_default(image.clone()).map_err(InitError::Framebuffer)?;
+        let framebuffer = Framebuffer::new(
+            render_pass,
+            FramebufferCreateInfo {
+                attachments: vec![view.clone()],
+                ..FramebufferCreateInfo::default()
+            }
+        ).map_err(InitError::Framebuffer)?;
+        set_name(framebuffer.as_ref(), "HDR framebuffer");
+
+        Ok(Self { image, framebuffer, view })
+    }
+
+    /// For passes that read the frame.
+    pub fn view(&self) -> Arc<ImageView> {
+        self.view.clone()
+    }
+
+    /// Binds the target and the auto exposure `average` of it to set 0 of the tone mapping pipeline.
+    pub fn descriptor_set(
+        &self,
+        descriptor_set_allocator: &StandardDescriptorSetAllocator,
+        pipeline: &GraphicsPipeline,
+        sampler: Arc<Sampler>,
+        average: &ExposureAverage,
+    ) -> Arc<PersistentDescriptorSet> {
+        PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::image_view_sampler(0, self.view.clone(), sampler.clone()),
+                WriteDescriptorSet::image_view_sampler(1, average.view(), sampler),
+            ],
+            []
+        ).expect("Failed to create HDR target descriptor set")
+    }
+}
+
+/// Nearest texels from any mip level: the tone mapping pass reads the frame 1:1 and the average from the last level.
+pub fn init_sampler(device: Arc<Device>) -> Arc<Sampler> {
+    Sampler::new(device, SamplerCreateInfo {
+        lod: 0.0..=LOD_CLAMP_NONE,
+        ..SamplerCreateInfo::default()
+    }).expect("Failed to create HDR sampler")
+}
+
+/// Builds the tone mapping pipeline, which draws into `render_pass`, the swapchain's.
+pub fn build_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPass>,
+    shader_dir: Option<&Path>,
+) -> Result<(Arc<PipelineLayout>, Arc<GraphicsPipeline>), ShaderError> {
+    let shaders = shaders::load_tonemap(device.clone(), shader_dir)?;
+    pipeline::init_pipeline(device, render_pass, shaders)
+}
+
+/// Like `build_pipeline`, but falls back to the built-in shader if the one in `shader_dir` fails.
+pub fn init_pipeline(device: Arc<Device>, render_pass: Arc<RenderPass>, shader_dir: Option<&Path>) -> (Arc<PipelineLayout>, Arc<GraphicsPipeline>) {
+    build_pipeline(device.clone(), render_pass.clone(), shader_dir).unwrap_or_else(|e| {
+        eprintln!("{}\nFalling back to the built-in tone mapping shader", e);
+        build_pipeline(device, render_pass, None).expect("Failed to create tone mapping pipeline")
+    })
+}
@@ -0,0 +1,201 @@
+//! Shader variants: features of `shaders/raymarching-3d.frag` that are compiled in or out with specialization constants
+//! instead of being branched on per pixel. `ScenePipelines` compiles the scene's shaders once and creates the pipeline of
+//! a variant the first time it is drawn, through the `PipelineCache` that `pipeline_cache` keeps on disk.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use vulkano::device::Device;
+use vulkano::pipeline::cache::PipelineCache;
+use vulkano::pipeline::graphics::color_blend::AttachmentBlend;
+use vulkano::pipeline::{GraphicsPipeline, PipelineLayout};
+use vulkano::render_pass::RenderPass;
+use vulkano::shader::SpecializationConstant;
+use crate::render_core::debug_view::DebugView;
+use crate::render_core::pipeline;
+use crate::render_core::shaders::{RaymarchingShaders, ShaderError};
+use crate::render_core::vulkano_core::set_name;
+
+/// `constant_id`s of the feature constants in `shaders/raymarching-3d.frag`; 0 is `ENCODE_SRGB`.
+pub const SHADOWS_CONSTANT: u32 = 1;
+pub const AO_CONSTANT: u32 = 2;
+pub const FOG_CONSTANT: u32 = 3;
+pub const TERRAIN_CONSTANT: u32 = 4;
+pub const DEBUG_VIEW_CONSTANT: u32 = 5;
+pub const PATH_TRACING_CONSTANT: u32 = 6;
+
+/// A feature that can be switched off, named like on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// Soft shadows from every light.
+    Shadows,
+    /// Ambient occlusion.
+    Ao,
+    /// Fading into the background with the distance.
+    Fog,
+    /// The noise terrain; without it terrain is flat ground at its base height.
+    Terrain,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 4] = [Feature::Shadows, Feature::Ao, Feature::Fog, Feature::Terrain];
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "shadows" => Ok(Feature::Shadows),
+            "ao" => Ok(Feature::Ao),
+            "fog" => Ok(Feature::Fog),
+            "terrain" => Ok(Feature::Terrain),
+            _ => Err(format!("'{}' is not a feature, expected shadows, ao, fog or terrain", value)),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Feature::Shadows => write!(f, "shadows"),
+            Feature::Ao => write!(f, "ao"),
+            Feature::Fog => write!(f, "fog"),
+            Feature::Terrain => write!(f, "terrain"),
+        }
+    }
+}
+
+/// One permutation of the 3D shader's specialization constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Variant {
+    pub shadows: bool,
+    pub ao: bool,
+    pub fog: bool,
+    pub terrain: bool,
+    pub debug_view: DebugView,
+    /// Path trace the scene instead of shading it; shadows, AO and fog don't apply, debug views still do.
+    pub path_tracing: bool,
+}
+
+impl Default for Variant {
+    /// Fog is off, so the default looks like the shader did before there were variants.
+    fn default() -> Self {
+        Self { shadows: true, ao: true, fog: false, terrain: true, debug_view: DebugView::Off, path_tracing: false }
+    }
+}
+
+impl Variant {
+    /// The variant with exactly `features` on, keeping the debug view and path tracing of `self`.
+    pub fn with_features(self, features: &[Feature]) -> Self {
+        Self {
+            shadows: features.contains(&Feature::Shadows),
+            ao: features.contains(&Feature::Ao),
+            fog: features.contains(&Feature::Fog),
+            terrain: features.contains(&Feature::Terrain),
+            ..self
+        }
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Shadows => self.shadows,
+            Feature::Ao => self.ao,
+            Feature::Fog => self.fog,
+            Feature::Terrain => self.terrain,
+        }
+    }
+
+    pub fn toggle(&mut self, feature: Feature) {
+        let flag = match feature {
+            Feature::Shadows => &mut self.shadows,
+            Feature::Ao => &mut self.ao,
+            Feature::Fog => &mut self.fog,
+            Feature::Terrain => &mut self.terrain,
+        };
+        *flag = !*flag;
+    }
+
+    pub fn constants(&self) -> [(u32, SpecializationConstant); 6] {
+        [
+            (SHADOWS_CONSTANT, SpecializationConstant::Bool(self.shadows)),
+            (AO_CONSTANT, SpecializationConstant::Bool(self.ao)),
+            (FOG_CONSTANT, SpecializationConstant::Bool(self.fog)),
+            (TERRAIN_CONSTANT, SpecializationConstant::Bool(self.terrain)),
+            (DEBUG_VIEW_CONSTANT, SpecializationConstant::U32(self.debug_view.value())),
+            (PATH_TRACING_CONSTANT, SpecializationConstant::Bool(self.path_tracing)),
+        ]
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let features: Vec<String> = Feature::ALL.iter().filter(|feature| self.has(**feature)).map(ToString::to_string).collect();
+        if features.is_empty() {
+            write!(f, "no features")?;
+        } else {
+            write!(f, "{}", features.join(", "))?;
+        }
+        if self.path_tracing {
+            write!(f, ", path traced")?;
+        }
+        if self.debug_view != DebugView::Off {
+            write!(f, ", debug view {}", self.debug_view)?;
+        }
+        Ok(())
+    }
+}
+
+/// `--features`: a comma separated list of features, or `none`.
+pub fn parse_features(value: &str) -> Result<Vec<Feature>, String> {
+    if value.trim().eq_ignore_ascii_case("none") {
+        return Ok(Vec::new());
+    }
+    value.split(',').map(str::parse).collect()
+}
+
+/// The scene's compiled shaders and the pipelines of the variants drawn so far. All variants share one pipeline layout,
+/// so descriptor sets bound for one of them work with the others.
+pub struct ScenePipelines {
+    render_pass: Arc<RenderPass>,
+    shaders: RaymarchingShaders,
+    layout: Arc<PipelineLayout>,
+    cache: Option<Arc<PipelineCache>>,
+    pipelines: HashMap<Variant, Arc<GraphicsPipeline>>,
+}
+
+impl ScenePipelines {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPass>,
+        shaders: RaymarchingShaders,
+        cache: Option<Arc<PipelineCache>>,
+    ) -> Result<Self, ShaderError> {
+        let layout = pipeline::init_layout(device, &shaders)?;
+        Ok(Self { render_pass, shaders, layout, cache, pipelines: HashMap::new() })
+    }
+
+    pub fn layout(&self) -> Arc<PipelineLayout> {
+        self.layout.clone()
+    }
+
+    /// The pipeline of `variant`, created the first time it is asked for.
+    pub fn get(&mut self, variant: Variant) -> Result<Arc<GraphicsPipeline>, ShaderError> {
+        if let Some(pipeline) = self.pipelines.get(&variant) {
+            return Ok(pipeline.clone());
+        }
+        let pipeline = pipeline::create_pipeline(
+            self.render_pass.clone(),
+            &self.shaders,
+            self.layout.clone(),
+            Some(AttachmentBlend::alpha()),
+            &variant.constants(),
+            self.cache.clone(),
+        )?;
+        set_name(pipeline.as_ref(), &format!("scene pipeline ({})", variant));
+        log::info!("Created the pipeline of the {} variant", variant);
+        self.pipelines.insert(variant, pipeline.clone());
+        Ok(pipeline)
+    }
+}
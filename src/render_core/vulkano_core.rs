@@ -1,116 +1,544 @@
+use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use vulkano::instance::{Instance, InstanceCreateInfo};
-use vulkano::{single_pass_renderpass, Version, VulkanLibrary};
-use vulkano::device::{Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags};
-use vulkano::device::physical::PhysicalDeviceType;
-use vulkano::image::Image;
+use log::{Level, LevelFilter};
+use vulkano::instance::debug::{
+    DebugUtilsMessageSeverity, DebugUtilsMessageType, DebugUtilsMessenger, DebugUtilsMessengerCallback,
+    DebugUtilsMessengerCallbackData, DebugUtilsMessengerCreateInfo,
+};
+use vulkano::instance::{Instance, InstanceCreateInfo, InstanceExtensions};
+use vulkano::{single_pass_renderpass, LoadingError, Validated, Version, VulkanError, VulkanLibrary, VulkanObject};
+use vulkano::device::{Device, DeviceCreateInfo, DeviceExtensions, DeviceOwned, Queue, QueueCreateInfo, QueueFlags};
+use vulkano::device::physical::{PhysicalDevice, PhysicalDeviceType};
+use vulkano::format::{Format, NumericFormat};
+use vulkano::image::{AllocateImageError, Image, ImageCreateInfo, ImageType, ImageUsage};
 use vulkano::image::view::ImageView;
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryTypeFilter, StandardMemoryAllocator};
 use vulkano::pipeline::graphics::viewport::Viewport;
 use vulkano::render_pass::{Framebuffer, FramebufferCreateInfo, RenderPass};
-use vulkano::swapchain::{CompositeAlpha, Surface, SurfaceInfo, Swapchain, SwapchainCreateInfo};
+use vulkano::swapchain::{ColorSpace, CompositeAlpha, PresentMode, Surface, SurfaceInfo, Swapchain, SwapchainCreateInfo};
 use winit::event_loop::EventLoop;
 use winit::window::Window;
 
-pub(crate) fn init(event_loop: &EventLoop<()>, window: Arc<Window>) -> (Arc<Instance>, Arc<Surface>) {
-    let instance = {
-        let library = VulkanLibrary::new().expect("VKC: Failed to load VulkanLibrary");
-        let extensions = Surface::required_extensions(&event_loop);
+/// API versions to try, newest first; drivers that reject an instance for 1.1 may still create one for 1.0.
+const API_VERSIONS: [Version; 2] = [Version::V1_1, Version::V1_0];
 
-        Instance::new(
-            library,
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Logs validation messages while alive; `None` without `--validation` or if debug utils aren't available.
+pub type DebugMessenger = Option<DebugUtilsMessenger>;
+
+/// Validation errors reported through the debug messenger so far, for tests to fail on.
+static VALIDATION_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+/// Why Vulkan couldn't be set up. `Display` is meant for the user and includes a device report where one helps.
+#[derive(Debug)]
+pub enum InitError {
+    Library(LoadingError),
+    /// Instance creation failed for every version in `API_VERSIONS`; the error of the last attempt.
+    Instance(Validated<VulkanError>),
+    Surface(Validated<VulkanError>),
+    EnumerateDevices(VulkanError),
+    NoSuitableDevice(DeviceReport),
+    /// Swapchain creation failed for every format the surface supports.
+    Swapchain(Vec<(Format, Validated<VulkanError>)>),
+    RenderPass(Validated<VulkanError>),
+    Framebuffer(Validated<VulkanError>),
+    Image(Validated<AllocateImageError>),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::Library(e) => write!(f, "failed to load the Vulkan library: {}", e),
+            InitError::Instance(e) => write!(f, "failed to create a Vulkan instance: {}", e),
+            InitError::Surface(e) => write!(f, "failed to set up the window surface: {}", e),
+            InitError::EnumerateDevices(e) => write!(f, "failed to enumerate physical devices: {}", e),
+            InitError::NoSuitableDevice(report) => write!(f, "no suitable physical device found\n{}", report),
+            InitError::Swapchain(attempts) if attempts.is_empty() => write!(f, "the surface supports no image formats"),
+            InitError::Swapchain(attempts) => {
+                write!(f, "failed to create a swapchain with any supported format:")?;
+                for (format, e) in attempts {
+                    write!(f, "\n  {:?}: {}", format, e)?;
+                }
+                Ok(())
+            }
+            InitError::RenderPass(e) => write!(f, "failed to create render pass: {}", e),
+            InitError::Framebuffer(e) => write!(f, "failed to create framebuffer: {}", e),
+            InitError::Image(e) => write!(f, "failed to create offscreen image: {}", e),
+        }
+    }
+}
+
+/// Environment variable read like `--device` when that isn't given, e.g. for CI runs on a software device.
+pub const DEVICE_ENV: &str = "VULKAN_RAYMARCHING_DEVICE";
+
+/// Forces `init_device` to use a particular physical device instead of the best ranked one.
+#[derive(Clone, Debug)]
+pub enum DeviceSelector {
+    /// Position in the enumeration order, as printed by `--list-devices`.
+    Index(usize),
+    /// Case-insensitive part of the device name.
+    Name(String),
+    Type(PhysicalDeviceType),
+}
+
+impl DeviceSelector {
+    pub fn from_env() -> Option<Self> {
+        std::env::var(DEVICE_ENV).ok().filter(|value| !value.is_empty()).and_then(|value| value.parse().ok())
+    }
+
+    fn matches(&self, index: usize, physical_device: &PhysicalDevice) -> bool {
+        let properties = physical_device.properties();
+        match self {
+            DeviceSelector::Index(i) => *i == index,
+            DeviceSelector::Name(name) => properties.device_name.to_lowercase().contains(&name.to_lowercase()),
+            DeviceSelector::Type(device_type) => properties.device_type == *device_type,
+        }
+    }
+}
+
+/// A number is an index, `cpu`, `discrete`, `integrated` and `virtual` are device types, anything else is part of a name.
+impl FromStr for DeviceSelector {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Err("device selection must not be empty".to_string());
+        }
+        Ok(match value.to_lowercase().as_str() {
+            "cpu" => DeviceSelector::Type(PhysicalDeviceType::Cpu),
+            "discrete" => DeviceSelector::Type(PhysicalDeviceType::DiscreteGpu),
+            "integrated" => DeviceSelector::Type(PhysicalDeviceType::IntegratedGpu),
+            "virtual" => DeviceSelector::Type(PhysicalDeviceType::VirtualGpu),
+            _ => match value.parse() {
+                Ok(index) => DeviceSelector::Index(index),
+                Err(_) => DeviceSelector::Name(value.to_string()),
+            },
+        })
+    }
+}
+
+impl fmt::Display for DeviceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSelector::Index(i) => write!(f, "device {}", i),
+            DeviceSelector::Name(name) => write!(f, "name containing '{}'", name),
+            DeviceSelector::Type(device_type) => write!(f, "type {:?}", device_type),
+        }
+    }
+}
+
+/// What `init_device` found: the instance's version and layers, and every physical device with why it wasn't used.
+#[derive(Debug)]
+pub struct DeviceReport {
+    pub api_version: Version,
+    pub layers: Vec<String>,
+    pub devices: Vec<DeviceDiagnosis>,
+}
+
+#[derive(Debug)]
+pub struct DeviceDiagnosis {
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    pub api_version: Version,
+    pub rejected: String,
+}
+
+impl DeviceReport {
+    fn new(instance: &Instance) -> Self {
+        let layers = match instance.library().layer_properties() {
+            Ok(layers) => layers.map(|layer| layer.name().to_string()).collect(),
+            Err(_) => Vec::new(),
+        };
+        Self { api_version: instance.api_version(), layers, devices: Vec::new() }
+    }
+
+    fn reject(&mut self, physical_device: &PhysicalDevice, reason: String) {
+        let properties = physical_device.properties();
+        self.devices.push(DeviceDiagnosis {
+            name: properties.device_name.clone(),
+            device_type: properties.device_type,
+            api_version: physical_device.api_version(),
+            rejected: reason,
+        });
+    }
+}
+
+impl fmt::Display for DeviceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Vulkan instance version: {}", self.api_version)?;
+        if self.layers.is_empty() {
+            writeln!(f, "Instance layers: none")?;
+        } else {
+            writeln!(f, "Instance layers: {}", self.layers.join(", "))?;
+        }
+        if self.devices.is_empty() {
+            write!(f, "Physical devices: none, is a Vulkan driver installed?")
+        } else {
+            write!(f, "Physical devices:")?;
+            for device in &self.devices {
+                write!(f, "\n  {} ({:?}, Vulkan {}): {}", device.name, device.device_type, device.api_version, device.rejected)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// With `validation` the validation layer and debug utils are enabled if they are installed, and their messages are logged
+/// through the returned messenger, which has to be kept alive as long as the instance.
+pub(crate) fn init(
+    event_loop: &EventLoop<()>,
+    window: Arc<Window>,
+    validation: bool,
+) -> Result<(Arc<Instance>, Arc<Surface>, DebugMessenger), InitError> {
+    let (instance, messenger) = create_instance(Surface::required_extensions(&event_loop), validation)?;
+    let surface = Surface::from_window(instance.clone(), window).map_err(InitError::Surface)?;
+
+    Ok((instance, surface, messenger))
+}
+
+/// Creates an `Instance` without any surface extensions, for rendering without a window.
+pub(crate) fn init_headless(validation: bool) -> Result<(Arc<Instance>, DebugMessenger), InitError> {
+    create_instance(InstanceExtensions::empty(), validation)
+}
+
+fn create_instance(
+    mut enabled_extensions: InstanceExtensions,
+    validation: bool,
+) -> Result<(Arc<Instance>, DebugMessenger), InitError> {
+    let library = VulkanLibrary::new().map_err(InitError::Library)?;
+
+    let mut enabled_layers = Vec::new();
+    if validation {
+        let installed = library.layer_properties().is_ok_and(|mut layers| layers.any(|layer| layer.name() == VALIDATION_LAYER));
+        if installed {
+            enabled_layers.push(VALIDATION_LAYER.to_string());
+        } else {
+            log::warn!("{} is not installed, continuing without validation", VALIDATION_LAYER);
+        }
+        // The validation layer usually brings debug utils along if the driver doesn't have them.
+        let supported = library.supported_extensions_with_layers(enabled_layers.iter().map(String::as_str));
+        if supported.is_ok_and(|extensions| extensions.ext_debug_utils) {
+            enabled_extensions.ext_debug_utils = true;
+        } else {
+            log::warn!("ext_debug_utils is not supported, validation messages can't be logged");
+        }
+    }
+
+    let mut last_error = None;
+    for (i, max_api_version) in API_VERSIONS.into_iter().enumerate() {
+        let result = Instance::new(
+            library.clone(),
             InstanceCreateInfo {
-                enabled_extensions: extensions,
-                max_api_version: Some(Version::V1_1),
+                enabled_extensions,
+                enabled_layers: enabled_layers.clone(),
+                max_api_version: Some(max_api_version),
                 ..InstanceCreateInfo::default()
             }
-        ).expect("VKC: Failed to create Instance")
-    };
+        );
+        match result {
+            Ok(instance) => {
+                let messenger = enabled_extensions.ext_debug_utils.then(|| create_messenger(instance.clone())).flatten();
+                return Ok((instance, messenger));
+            }
+            Err(e) => {
+                if let Some(next) = API_VERSIONS.get(i + 1) {
+                    eprintln!("Failed to create a Vulkan {} instance, retrying with {}: {}", max_api_version, next, e);
+                }
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(InitError::Instance(last_error.expect("API_VERSIONS is not empty")))
+}
 
-    let surface = Surface::from_window(instance.clone(), window).expect("VKC: Failed to create Surface");
+fn create_messenger(instance: Arc<Instance>) -> DebugMessenger {
+    // Only ask for the severities the logger is going to show.
+    let mut message_severity = DebugUtilsMessageSeverity::ERROR | DebugUtilsMessageSeverity::WARNING;
+    if log::max_level() >= LevelFilter::Info {
+        message_severity |= DebugUtilsMessageSeverity::INFO;
+    }
+    if log::max_level() >= LevelFilter::Debug {
+        message_severity |= DebugUtilsMessageSeverity::VERBOSE;
+    }
 
-    return (instance, surface);
+    // SAFETY: the callback only logs and counts, it doesn't call into Vulkan.
+    let callback = unsafe { DebugUtilsMessengerCallback::new(log_message) };
+    let create_info = DebugUtilsMessengerCreateInfo {
+        message_severity,
+        message_type: DebugUtilsMessageType::GENERAL | DebugUtilsMessageType::VALIDATION | DebugUtilsMessageType::PERFORMANCE,
+        ..DebugUtilsMessengerCreateInfo::user_callback(callback)
+    };
+    DebugUtilsMessenger::new(instance, create_info)
+        .map_err(|e| log::warn!("Failed to create debug messenger, validation messages won't be logged: {}", e))
+        .ok()
 }
 
-pub fn init_device(instance: Arc<Instance>) -> (Arc<Device>, Arc<Queue>) {
-    let device_extension = DeviceExtensions {
-        khr_swapchain: true,
-        ..DeviceExtensions::empty()
+fn log_message(severity: DebugUtilsMessageSeverity, message_type: DebugUtilsMessageType, data: DebugUtilsMessengerCallbackData<'_>) {
+    let level = if severity.intersects(DebugUtilsMessageSeverity::ERROR) {
+        Level::Error
+    } else if severity.intersects(DebugUtilsMessageSeverity::WARNING) {
+        Level::Warn
+    } else if severity.intersects(DebugUtilsMessageSeverity::INFO) {
+        Level::Info
+    } else {
+        Level::Debug
     };
+    if level == Level::Error && message_type.intersects(DebugUtilsMessageType::VALIDATION) {
+        VALIDATION_ERRORS.fetch_add(1, Ordering::Relaxed);
+    }
+    log::log!(target: "vulkan", level, "{}", data.message);
+}
 
-    let (physical_device, queue_family_index) = instance
-        .enumerate_physical_devices().expect("VKC: Failed to enumerate physical devices")
-        .filter(|p| p.supported_extensions().contains(&device_extension))
-        .filter_map(|p| {
-            p.queue_family_properties().iter().enumerate().find_map(|(i, q)| {
-                if q.queue_flags.contains(QueueFlags::GRAPHICS & QueueFlags::COMPUTE) {
-                    Some((p.clone(), i as u32))
-                } else {
-                    None
-                }
-            })
-        })
-        .min_by_key(|(p, _)| {
-            match p.properties().device_type {
-                PhysicalDeviceType::DiscreteGpu => 0,
-                PhysicalDeviceType::IntegratedGpu => 1,
-                PhysicalDeviceType::VirtualGpu => 2,
-                PhysicalDeviceType::Cpu => 3,
-                PhysicalDeviceType::Other => 4,
-                _ => 5,
+/// Number of validation errors logged since startup.
+pub fn validation_error_count() -> usize {
+    VALIDATION_ERRORS.load(Ordering::Relaxed)
+}
+
+/// Names `object` in validation messages and graphics debuggers; does nothing unless debug utils are enabled.
+pub fn set_name<T: VulkanObject + DeviceOwned>(object: &T, name: &str) {
+    let device = object.device();
+    if device.instance().enabled_extensions().ext_debug_utils {
+        if let Err(e) = device.set_debug_utils_object_name(object, Some(name)) {
+            log::warn!("Failed to name {}: {}", name, e);
+        }
+    }
+}
+
+/// Windowed rendering needs `khr_swapchain` and a queue that can present to `surface`,
+/// headless rendering needs no extensions at all. Devices are tried from discrete GPUs down to software renderers,
+/// until one can be created; `selector` restricts that to the matching devices.
+pub fn init_device(
+    instance: Arc<Instance>,
+    device_extension: DeviceExtensions,
+    surface: Option<&Surface>,
+    selector: Option<&DeviceSelector>,
+) -> Result<(Arc<Device>, Arc<Queue>), InitError> {
+    let mut report = DeviceReport::new(&instance);
+    let mut candidates = Vec::new();
+    let physical_devices = instance.enumerate_physical_devices().map_err(InitError::EnumerateDevices)?;
+    for (index, physical_device) in physical_devices.enumerate() {
+        if let Some(selector) = selector.filter(|selector| !selector.matches(index, &physical_device)) {
+            report.reject(&physical_device, format!("not selected, looking for {}", selector));
+            continue;
+        }
+        match find_queue_family(&physical_device, device_extension, surface) {
+            Ok(queue_family_index) => candidates.push((physical_device, queue_family_index)),
+            Err(reason) => report.reject(&physical_device, reason),
+        }
+    }
+    candidates.sort_by_key(|(p, _)| device_type_rank(p.properties().device_type));
+
+    for (physical_device, queue_family_index) in candidates {
+        let result = Device::new(
+            physical_device.clone(),
+            DeviceCreateInfo {
+                enabled_extensions: device_extension,
+                queue_create_infos: vec![QueueCreateInfo {
+                    queue_family_index,
+                    ..QueueCreateInfo::default()
+                }],
+                ..DeviceCreateInfo::default()
             }
-        })
-        .expect("VKC: Failed to find suitable physical device");
+        );
+        match result {
+            Ok((device, mut queues)) => {
+                let queue = queues.next().expect("a queue was requested");
+                return Ok((device, queue));
+            }
+            Err(e) => {
+                eprintln!("Failed to create a device on {}, trying the next one: {}", physical_device.properties().device_name, e);
+                report.reject(&physical_device, format!("device creation failed: {}", e));
+            }
+        }
+    }
 
-    let (device, mut queues) = Device::new(
-        physical_device,
-        DeviceCreateInfo {
-            enabled_extensions: device_extension,
-            queue_create_infos: vec![QueueCreateInfo {
-                queue_family_index,
-                ..QueueCreateInfo::default()
-            }],
-            ..DeviceCreateInfo::default()
+    Err(InitError::NoSuitableDevice(report))
+}
+
+/// Every physical device with its index for `--device`, type, driver, API version and queue families.
+pub fn describe_devices(instance: &Arc<Instance>) -> Result<String, InitError> {
+    let mut description = String::new();
+    let physical_devices = instance.enumerate_physical_devices().map_err(InitError::EnumerateDevices)?;
+    for (index, physical_device) in physical_devices.enumerate() {
+        let properties = physical_device.properties();
+        let driver = match (&properties.driver_name, &properties.driver_info) {
+            (Some(name), Some(info)) => format!("{} {}", name, info),
+            (Some(name), None) => name.clone(),
+            _ => format!("version {:#x}", properties.driver_version),
+        };
+        writeln!(description, "{}: {} ({:?})", index, properties.device_name, properties.device_type).unwrap();
+        writeln!(description, "   driver: {}, Vulkan {}", driver, physical_device.api_version()).unwrap();
+        for (family, queue) in physical_device.queue_family_properties().iter().enumerate() {
+            writeln!(description, "   queue family {}: {} x {:?}", family, queue.queue_count, queue.queue_flags).unwrap();
         }
-    ).expect("VKC: Failed to create Device");
+    }
+    if description.is_empty() {
+        description.push_str("No Vulkan devices found\n");
+    }
+    Ok(description)
+}
+
+/// A queue family that can draw, and present to `surface` if given, or why there is none.
+fn find_queue_family(physical_device: &PhysicalDevice, extensions: DeviceExtensions, surface: Option<&Surface>) -> Result<u32, String> {
+    if !physical_device.supported_extensions().contains(&extensions) {
+        let missing = extensions.difference(physical_device.supported_extensions());
+        return Err(format!("missing device extensions {:?}", missing));
+    }
 
-    let queue = queues.next().expect("VKC: Failed to get Queue");
+    let graphics = physical_device.queue_family_properties().iter().enumerate()
+        .filter(|(_, q)| q.queue_flags.contains(QueueFlags::GRAPHICS))
+        .map(|(i, _)| i as u32)
+        .collect::<Vec<_>>();
+    if graphics.is_empty() {
+        return Err("no queue family supports graphics".to_string());
+    }
 
-    return (device, queue);
+    match surface {
+        Some(surface) => graphics.into_iter()
+            .find(|i| physical_device.surface_support(*i, surface).unwrap_or(false))
+            .ok_or_else(|| "no graphics queue family can present to the window".to_string()),
+        None => Ok(graphics[0]),
+    }
 }
 
-pub fn init_swapchain(device: Arc<Device>, surface: Arc<Surface>) -> (Arc<Swapchain>, Vec<Arc<Image>>) {
-    let capabilities = device.physical_device().surface_capabilities(&surface, SurfaceInfo::default()).expect("VKC: Failed to get surface capabilities");
-    let usage = capabilities.supported_usage_flags;
+fn device_type_rank(device_type: PhysicalDeviceType) -> u32 {
+    match device_type {
+        PhysicalDeviceType::DiscreteGpu => 0,
+        PhysicalDeviceType::IntegratedGpu => 1,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 3,
+        PhysicalDeviceType::Other => 4,
+        _ => 5,
+    }
+}
+
+/// How the swapchain presents. Both fields are only requests, `init_swapchain` adapts them to what the surface supports.
+#[derive(Clone, Copy, Debug)]
+pub struct SwapchainSettings {
+    pub present_mode: PresentMode,
+    /// More images trade latency for throughput; `None` uses the surface's minimum.
+    pub image_count: Option<u32>,
+}
 
-    let image_format = device
-        .physical_device()
-        .surface_formats(&surface, SurfaceInfo::default())
-        .expect("VKC: Failed to get surface formats")
-        [0].0;
+impl Default for SwapchainSettings {
+    fn default() -> Self {
+        Self { present_mode: PresentMode::Fifo, image_count: None }
+    }
+}
+
+/// Whether `present_mode` waits for the vertical blank.
+pub fn is_vsync(present_mode: PresentMode) -> bool {
+    matches!(present_mode, PresentMode::Fifo | PresentMode::FifoRelaxed)
+}
+
+/// `wanted` if the surface supports it, otherwise the closest supported mode. FIFO is always supported.
+pub fn choose_present_mode(physical_device: &PhysicalDevice, surface: &Surface, wanted: PresentMode) -> PresentMode {
+    let supported: Vec<PresentMode> = match physical_device.surface_present_modes(surface, SurfaceInfo::default()) {
+        Ok(modes) => modes.collect(),
+        Err(_) => Vec::new(),
+    };
+    let preferences = match wanted {
+        PresentMode::Mailbox => vec![PresentMode::Mailbox, PresentMode::Immediate],
+        PresentMode::Immediate => vec![PresentMode::Immediate, PresentMode::Mailbox],
+        PresentMode::FifoRelaxed => vec![PresentMode::FifoRelaxed],
+        _ => vec![wanted],
+    };
+
+    let present_mode = preferences.into_iter().find(|mode| supported.contains(mode)).unwrap_or(PresentMode::Fifo);
+    if present_mode != wanted {
+        log::warn!("Present mode {:?} is not supported, using {:?}", wanted, present_mode);
+    }
+    present_mode
+}
+
+/// Prefers sRGB formats, then UNORM ones in the sRGB color space, see `surface_format_rank`, falling back to the next format
+/// if the swapchain can't be created with one.
+pub fn init_swapchain(
+    device: Arc<Device>,
+    surface: Arc<Surface>,
+    settings: SwapchainSettings,
+) -> Result<(Arc<Swapchain>, Vec<Arc<Image>>), InitError> {
+    let physical_device = device.physical_device();
+    let capabilities = physical_device.surface_capabilities(&surface, SurfaceInfo::default()).map_err(InitError::Surface)?;
+    let present_mode = choose_present_mode(physical_device, &surface, settings.present_mode);
+    let min_image_count = match settings.image_count {
+        Some(count) => {
+            let max = capabilities.max_image_count.unwrap_or(u32::MAX);
+            let clamped = count.clamp(capabilities.min_image_count, max);
+            if clamped != count {
+                log::warn!("{} swapchain images are not supported, using {}", count, clamped);
+            }
+            clamped
+        }
+        None => capabilities.min_image_count,
+    };
+    // Only what the frames are used for: sRGB formats often can't be storage images, so asking for every supported usage
+    // fails. Screenshots copy from the swapchain image when the surface allows it.
+    let usage = ImageUsage::COLOR_ATTACHMENT | (capabilities.supported_usage_flags & ImageUsage::TRANSFER_SRC);
+    let composite_alpha = if capabilities.supported_composite_alpha.contains_enum(CompositeAlpha::Opaque) {
+        CompositeAlpha::Opaque
+    } else {
+        capabilities.supported_composite_alpha.into_iter().next().unwrap_or(CompositeAlpha::Opaque)
+    };
+    let mut formats = physical_device.surface_formats(&surface, SurfaceInfo::default()).map_err(InitError::Surface)?;
+    formats.sort_by_key(surface_format_rank);
 
     let window = surface.object().unwrap().downcast_ref::<Window>().unwrap();
     let image_extent: [u32; 2] = window.inner_size().into();
 
-    Swapchain::new(
-        device.clone(),
-        surface.clone(),
-        SwapchainCreateInfo {
-            min_image_count: capabilities.min_image_count,
-            image_format,
-            image_extent,
-            image_usage: usage,
-            composite_alpha: CompositeAlpha::Opaque,
-            ..SwapchainCreateInfo::default()
+    let mut failures = Vec::new();
+    for (image_format, image_color_space) in formats {
+        let result = Swapchain::new(
+            device.clone(),
+            surface.clone(),
+            SwapchainCreateInfo {
+                min_image_count,
+                image_format,
+                image_color_space,
+                image_extent,
+                image_usage: usage,
+                composite_alpha,
+                present_mode,
+                ..SwapchainCreateInfo::default()
+            }
+        );
+        match result {
+            Ok(swapchain) => {
+                log::info!("Swapchain format {:?}, {:?}", image_format, image_color_space);
+                return Ok(swapchain);
+            }
+            Err(e) => {
+                eprintln!("Failed to create a swapchain with {:?}, trying the next format: {}", image_format, e);
+                failures.push((image_format, e));
+            }
         }
-    ).expect("VKC: Failed to create Swapchain")
+    }
+    Err(InitError::Swapchain(failures))
 }
 
-pub fn init_render_pass(device: Arc<Device>, swapchain: Arc<Swapchain>) -> Arc<RenderPass> {
+/// The shaders write linear colors. `_SRGB` formats encode them for display in hardware; UNORM formats in the sRGB color
+/// space are displayed the same way once the shaders encode themselves (`pipeline::ENCODE_SRGB_CONSTANT`), so they come
+/// next. Anything else, e.g. HDR color spaces, would be displayed wrong and comes last. The sort is stable, so the order the
+/// surface reports decides between equally ranked formats.
+fn surface_format_rank(&(format, color_space): &(Format, ColorSpace)) -> u32 {
+    match (format.numeric_format_color(), color_space) {
+        (Some(NumericFormat::SRGB), ColorSpace::SrgbNonLinear) => 0,
+        (Some(NumericFormat::UNORM), ColorSpace::SrgbNonLinear) => 1,
+        _ => 2,
+    }
+}
+
+pub fn init_render_pass(device: Arc<Device>, format: Format) -> Result<Arc<RenderPass>, InitError> {
     single_pass_renderpass!(
         device,
         attachments: {
             color: {
-                format: swapchain.image_format(),
+                format: format,
                 samples: 1,
                 load_op: Clear,
                 store_op: Store,
@@ -120,25 +548,59 @@ pub fn init_render_pass(device: Arc<Device>, swapchain: Arc<Swapchain>) -> Arc<R
             color: [color],
             depth_stencil: {}
         }
-    ).unwrap()
+    ).map_err(InitError::RenderPass)
 }
 
 pub fn window_size_dependent_setup(
     images: &[Arc<Image>],
     render_pass: Arc<RenderPass>,
     viewport: &mut Viewport,
-) -> Vec<Arc<Framebuffer>> {
+) -> Result<Vec<Arc<Framebuffer>>, InitError> {
     let extent = images[0].extent();
     viewport.extent = [extent[0] as f32, extent[1] as f32];
 
-    images.iter().map(|image| {
-        let view = ImageView::new_default(image.clone()).expect("VKC: Failed to create ImageView");
-        Framebuffer::new(
+    images.iter().enumerate().map(|(i, image)| {
+        let view = ImageView::new_default(image.clone()).map_err(InitError::Framebuffer)?;
+        let framebuffer = Framebuffer::new(
             render_pass.clone(),
             FramebufferCreateInfo {
                 attachments: vec![view],
                 ..FramebufferCreateInfo::default()
             }
-        ).expect("VKC: Failed to create Framebuffer")
+        ).map_err(InitError::Framebuffer)?;
+        set_name(framebuffer.as_ref(), &format!("framebuffer {}", i));
+        Ok(framebuffer)
     }).collect()
-}
\ No newline at end of file
+}
+
+/// Offscreen replacement for the swapchain images: a single color target that can be copied back to the host.
+pub fn init_offscreen_target(
+    buffer_allocator: Arc<StandardMemoryAllocator>,
+    render_pass: Arc<RenderPass>,
+    extent: [u32; 2],
+    viewport: &mut Viewport,
+) -> Result<(Arc<Image>, Arc<Framebuffer>), InitError> {
+    let format = render_pass.attachments()[0].format;
+
+    let image = Image::new(
+        buffer_allocator,
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format,
+            extent: [extent[0], extent[1], 1],
+            usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::TRANSFER_SRC,
+            ..ImageCreateInfo::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter::PREFER_DEVICE,
+            ..AllocationCreateInfo::default()
+        }
+    ).map_err(InitError::Image)?;
+    set_name(image.as_ref(), "offscreen target");
+
+    let framebuffer = window_size_dependent_setup(std::slice::from_ref(&image), render_pass, viewport)?
+        .pop()
+        .unwrap();
+
+    Ok((image, framebuffer))
+}
@@ -0,0 +1,201 @@
+//! Generates a GLSL `map()` specialized to one scene: shapes, transforms and blend radii become constants,
+//! identity transforms and the instruction dispatch of the interpreter disappear.
+//! Colors are read from the material table in their storage buffer, so editing a material doesn't need a new shader,
+//! and travel in `.yzw` of the result just like in the interpreter, so the lighting code is shared unchanged.
+
+use std::fmt::Write;
+use nalgebra::Matrix4;
+use crate::scene::{material_index, MaterialData, Node, Op, Scene, SceneError, Shape};
+
+/// Lines in `shaders/raymarching-3d.frag` around the interpreting `map()`, which the generated one replaces.
+pub const BEGIN_MARKER: &str = "// BEGIN SCENE";
+pub const END_MARKER: &str = "// END SCENE";
+
+/// What an empty group of nodes evaluates to: farther than any ray goes, so nothing is hit. `Scene::compile` rejects
+/// empty groups, but scenes built in code can skip it.
+const NOTHING: &str = "vec4(1e10, 0.0, 0.0, 0.0)";
+
+/// Returns `template` with the section between the scene markers replaced by a `map()` generated for `scene`.
+/// A `#line` directive after the generated code keeps compile errors in the rest of the template pointing at the right lines.
+pub fn fragment_source(template: &str, scene: &Scene) -> Result<String, SceneError> {
+    let begin = template.find(BEGIN_MARKER);
+    let end = template.find(END_MARKER);
+    match (begin, end) {
+        (Some(begin), Some(end)) if begin < end => {
+            let rest = template[end..].find('\n').map_or(template.len(), |i| end + i + 1);
+            let next_line = template[..rest].lines().count() + 1;
+            Ok(format!(
+                "{}{}\n#line {}\n{}",
+                &template[..begin],
+                generate_map(scene),
+                next_line,
+                &template[rest..]
+            ))
+        }
+        _ => Err(SceneError::Invalid(format!(
+            "shader template has no '{}' ... '{}' section",
+            BEGIN_MARKER, END_MARKER
+        ))),
+    }
+}
+
+/// A complete `vec4 map( in vec3 p )` returning the distance in `.x` and the surface color in `.yzw`.
+pub fn generate_map(scene: &Scene) -> String {
+    let mut emitter = Emitter { body: String::new(), next_id: 0, materials: Vec::new() };
+    let result = emitter.children(&scene.nodes, Op::Union, "p", 1.0);
+
+    format!(
+        "// Generated by scene::codegen.\nvec4 map( in vec3 p )\n{{\n{}    return {};\n}}",
+        emitter.body, result
+    )
+}
+
+/// Emits one local per node, so every value is computed once and the compiler sees plain straight-line code.
+struct Emitter {
+    body: String,
+    next_id: usize,
+    /// The material table as `Scene::compile` builds it, to look up the indices.
+    materials: Vec<MaterialData>,
+}
+
+impl Emitter {
+    fn define(&mut self, ty: &str, prefix: char, value: String) -> String {
+        let name = format!("{}{}", prefix, self.next_id);
+        self.next_id += 1;
+        writeln!(self.body, "    {} {} = {};", ty, name, value).unwrap();
+        name
+    }
+
+    /// Folds `children` with `op` and returns the variable holding the result, `NOTHING` without children.
+    fn children(&mut self, children: &[Node], op: Op, point: &str, scale: f32) -> String {
+        let Some((first, rest)) = children.split_first() else {
+            return self.define("vec4", 'd', NOTHING.to_string());
+        };
+        let mut result = self.node(first, point, scale);
+        for child in rest {
+            let next = self.node(child, point, scale);
+            let combined = match op {
+                Op::Union => format!("opU({}, {})", result, next),
+                Op::SmoothUnion { k } => format!("opSmoothUnion({}, {}, {})", result, next, float(k * scale)),
+                Op::Subtraction => format!("opSubtraction({}, {})", result, next),
+                Op::Intersection => format!("opIntersection({}, {})", result, next),
+            };
+            result = self.define("vec4", 'd', combined);
+        }
+        result
+    }
+
+    /// `point` is the variable holding the point in the node's frame, `scale` the factor from that frame's distances to world units;
+    /// blend radii are given in the frame's units too.
+    fn node(&mut self, node: &Node, point: &str, scale: f32) -> String {
+        match node {
+            Node::Combine { op, children } => self.children(children, *op, point, scale),
+            Node::Transform { transform, children } => {
+                let local = self.define("vec3", 'p', format!("({} * vec4({}, 1.0)).xyz", mat4(&transform.inverse()), point));
+                self.children(children, Op::Union, &local, scale * transform.scale)
+            }
+            Node::Repeat { period, children } => {
+                let local = self.define("vec3", 'p', format!("opRepeat({}, {})", point, vec3(*period)));
+                self.children(children, Op::Union, &local, scale)
+            }
+            Node::Primitive { shape, transform, material } => {
+                let q = if transform.is_identity() {
+                    point.to_string()
+                } else {
+                    self.define("vec3", 'p', format!("({} * vec4({}, 1.0)).xyz", mat4(&transform.inverse()), point))
+                };
+                let color = format!("scene_materials.materials[{}].color.rgb", material_index(&mut self.materials, material));
+                let value = match *shape {
+                    Shape::Sphere { radius } => format!("vec4(length({}) - {}, {})", q, float(radius), color),
+                    Shape::Capsule { height, radius } => {
+                        format!("vec4(sdVerticalCapsule({}, {}, {}), {})", q, float(height), float(radius), color)
+                    }
+                    Shape::Box { size } => format!("vec4(sdBox({}, {}), {})", q, vec3(size), color),
+                    Shape::Torus { major_radius, minor_radius } => {
+                        format!("vec4(sdTorus({}, vec2({}, {})), {})", q, float(major_radius), float(minor_radius), color)
+                    }
+                    Shape::Plane => format!("vec4({}.y, {})", q, color),
+                    Shape::Terrain { max_height, offset, frequency, snow_color } => format!(
+                        "terrain({}, vec4({}, {}, {}, 0.0), {}, {})",
+                        q, float(max_height), float(offset), float(frequency), vec3(snow_color), color
+                    ),
+                };
+
+                let result = self.define("vec4", 'd', value);
+                let scale = scale * transform.scale;
+                if scale != 1.0 {
+                    writeln!(self.body, "    {}.x *= {};", result, float(scale)).unwrap();
+                }
+                result
+            }
+        }
+    }
+}
+
+/// `Debug` keeps the decimal point or exponent GLSL needs for a float literal, e.g. `1.0` and `1e-5`.
+fn float(value: f32) -> String {
+    format!("{:?}", value)
+}
+
+fn vec3([x, y, z]: [f32; 3]) -> String {
+    format!("vec3({}, {}, {})", float(x), float(y), float(z))
+}
+
+/// Column-major, like GLSL's `mat4` constructor.
+fn mat4(matrix: &Matrix4<f32>) -> String {
+    let values: Vec<String> = matrix.iter().map(|v| float(*v)).collect();
+    format!("mat4({})", values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "#version 450\n// BEGIN SCENE\nvec4 map( in vec3 p ) { return vec4(0.0); }\n// END SCENE\nvoid main() {}\n";
+
+    #[test]
+    fn replaces_the_scene_section() {
+        let source = fragment_source(TEMPLATE, &Scene::default_scene()).unwrap();
+        assert!(source.starts_with("#version 450\n// Generated by scene::codegen."));
+        assert!(!source.contains(BEGIN_MARKER) && !source.contains(END_MARKER));
+        assert!(!source.contains("return vec4(0.0);"));
+    }
+
+    #[test]
+    fn line_directive_names_the_line_after_the_section() {
+        let source = fragment_source(TEMPLATE, &Scene::default_scene()).unwrap();
+        assert!(source.ends_with("\n#line 5\nvoid main() {}\n"), "{}", source);
+    }
+
+    #[test]
+    fn line_directive_matches_the_shader_template() {
+        let template = crate::render_core::shaders::FRAGMENT_TEMPLATE;
+        let source = fragment_source(template, &Scene::default_scene()).unwrap();
+        let (_, rest) = source.split_once("\n#line ").unwrap();
+        let (line, rest) = rest.split_once('\n').unwrap();
+        let line: usize = line.parse().unwrap();
+        let template_rest = template.lines().skip(line - 1).collect::<Vec<_>>();
+        assert_eq!(rest.lines().collect::<Vec<_>>(), template_rest);
+    }
+
+    #[test]
+    fn empty_groups_hit_nothing() {
+        let empty_combine = Node::Combine { op: Op::Intersection, children: Vec::new() };
+        for nodes in [Vec::new(), vec![empty_combine]] {
+            let scene = Scene { nodes, lights: Vec::new(), params: Vec::new() };
+            assert!(generate_map(&scene).contains(&format!("vec4 d0 = {};", NOTHING)));
+        }
+    }
+
+    #[test]
+    fn rejects_templates_without_the_markers() {
+        let scene = Scene::default_scene();
+        let without_begin = TEMPLATE.replace(BEGIN_MARKER, "");
+        let without_end = TEMPLATE.replace(END_MARKER, "");
+        let swapped = TEMPLATE.replace(BEGIN_MARKER, "@").replace(END_MARKER, BEGIN_MARKER).replace('@', END_MARKER);
+        for template in [without_begin, without_end, swapped] {
+            let error = fragment_source(&template, &scene).unwrap_err();
+            assert!(matches!(error, SceneError::Invalid(_)), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,517 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use nalgebra::{Matrix4, Rotation3, Vector3};
+use serde::Deserialize;
+use vulkano::buffer::BufferContents;
+
+pub mod codegen;
+pub mod shapes_2d;
+
+/// Deepest operand stack the interpreting `map()` in the fragment shader can evaluate (`MAX_STACK`).
+pub const MAX_STACK: usize = 16;
+/// Deepest nesting of `Transform` and `Repeat` nodes the interpreter supports (`MAX_DOMAINS`).
+pub const MAX_DOMAINS: usize = 8;
+/// Size of the user parameter block in the per-frame uniforms, `params` in `shaders/raymarching-3d.frag`.
+pub const MAX_PARAMS: usize = 16;
+
+// Keep in sync with the defines in shaders/raymarching-3d.frag.
+pub const OP_PRIMITIVE: u32 = 0;
+pub const OP_UNION: u32 = 1;
+pub const OP_SMOOTH_UNION: u32 = 2;
+pub const OP_SUBTRACTION: u32 = 3;
+pub const OP_INTERSECTION: u32 = 4;
+pub const OP_PUSH_TRANSFORM: u32 = 5;
+pub const OP_PUSH_REPEAT: u32 = 6;
+pub const OP_POP_DOMAIN: u32 = 7;
+
+pub const SHAPE_SPHERE: u32 = 0;
+pub const SHAPE_CAPSULE: u32 = 1;
+pub const SHAPE_BOX: u32 = 2;
+pub const SHAPE_TORUS: u32 = 3;
+pub const SHAPE_PLANE: u32 = 4;
+pub const SHAPE_TERRAIN: u32 = 5;
+
+const DEFAULT_SCENE: &str = include_str!("../../scenes/default.ron");
+
+/// A scene file: the top-level nodes are combined with a plain union.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scene {
+    pub nodes: Vec<Node>,
+    /// Lights the surfaces; scene files without `lights` get the sun `main()` used to hard-code.
+    #[serde(default = "default_lights")]
+    pub lights: Vec<Light>,
+    /// Values for hand-written shaders, read with `param(i)`; not used by the built-in ones. At most `MAX_PARAMS`.
+    #[serde(default)]
+    pub params: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum Node {
+    Primitive {
+        shape: Shape,
+        #[serde(default)]
+        transform: Transform,
+        #[serde(default)]
+        material: Material,
+    },
+    /// Folds the children left to right with `op`; for `Subtraction` the first child is cut by the others.
+    Combine {
+        op: Op,
+        children: Vec<Node>,
+    },
+    /// Places the children, unioned, in a transformed frame.
+    Transform {
+        transform: Transform,
+        children: Vec<Node>,
+    },
+    /// Repeats the children, unioned, every `period` units; an axis with a period of 0 isn't repeated.
+    /// Children should fit into one cell, or the distance field gets discontinuous at the cell borders.
+    Repeat {
+        period: [f32; 3],
+        children: Vec<Node>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum Shape {
+    Sphere { radius: f32 },
+    /// Vertical, from the origin up to `height`.
+    Capsule { height: f32, radius: f32 },
+    /// `size` holds the half extents.
+    Box { size: [f32; 3] },
+    Torus { major_radius: f32, minor_radius: f32 },
+    /// The local XZ plane, solid below.
+    Plane,
+    /// Noise heightfield; the material color is used for the low ground, `snow_color` towards the peaks.
+    Terrain { max_height: f32, offset: f32, frequency: f32, snow_color: [f32; 3] },
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub enum Op {
+    Union,
+    /// `smin` with a blend radius of `k`.
+    SmoothUnion { k: f32 },
+    Subtraction,
+    Intersection,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    /// Euler angles in degrees, applied around X, then Y, then Z.
+    pub rotation: [f32; 3],
+    pub scale: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: 1.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let [x, y, z] = self.rotation.map(f32::to_radians);
+        Matrix4::new_translation(&Vector3::from(self.translation))
+            * Rotation3::from_euler_angles(x, y, z).to_homogeneous()
+            * Matrix4::new_scaling(self.scale)
+    }
+
+    /// Maps world space into the local frame; shapes are evaluated there and their distance is multiplied by `scale`.
+    pub fn inverse(&self) -> Matrix4<f32> {
+        self.matrix().try_inverse().unwrap()
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.translation == [0.0; 3] && self.rotation == [0.0; 3] && self.scale == 1.0
+    }
+
+    fn validate(&self) -> Result<(), SceneError> {
+        if self.scale <= 0.0 {
+            return Err(SceneError::Invalid(format!("scale must be positive, got {}", self.scale)));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Material {
+    pub color: [f32; 3],
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self { color: [0.8, 0.8, 0.8] }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum Light {
+    /// Light from infinitely far away; `direction` points from the surfaces towards the light.
+    Directional {
+        direction: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+    /// Falls off with the squared distance; shadows are traced up to the light or 10 units, whichever is closer.
+    Point {
+        position: [f32; 3],
+        #[serde(default = "white")]
+        color: [f32; 3],
+        #[serde(default = "one")]
+        intensity: f32,
+    },
+}
+
+fn white() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+fn default_lights() -> Vec<Light> {
+    vec![Light::Directional { direction: [-1.0, 1.0, -1.0], color: white(), intensity: 1.0 }]
+}
+
+impl Light {
+    fn data(&self) -> Result<LightData, SceneError> {
+        let (position, w, color, intensity) = match *self {
+            Light::Directional { direction, color, intensity } => {
+                let direction = Vector3::from(direction);
+                if direction.norm() == 0.0 {
+                    return Err(SceneError::Invalid("Directional light needs a non-zero direction".to_string()));
+                }
+                (direction.normalize(), 0.0, color, intensity)
+            }
+            Light::Point { position, color, intensity } => (Vector3::from(position), 1.0, color, intensity),
+        };
+        if intensity < 0.0 {
+            return Err(SceneError::Invalid(format!("light intensity must not be negative, got {}", intensity)));
+        }
+        let [r, g, b] = color.map(|c| c * intensity);
+        Ok(LightData { position: [position.x, position.y, position.z, w], color: [r, g, b, 0.0] })
+    }
+}
+
+/// One step of the postfix scene program, laid out like `Instruction` in the fragment shader (std430).
+#[repr(C)]
+#[derive(BufferContents, Clone, Copy, Debug)]
+pub struct Instruction {
+    pub inverse_transform: [[f32; 4]; 4],
+    pub params: [f32; 4],
+    pub extra: [f32; 4],
+    pub opcode: u32,
+    pub shape: u32,
+    /// Index into `CompiledScene::materials`.
+    pub material: u32,
+    pub scale: f32,
+    pub k: f32,
+    pub padding: [u32; 3],
+}
+
+impl Instruction {
+    fn op(opcode: u32, k: f32) -> Self {
+        Self {
+            inverse_transform: Matrix4::identity().into(),
+            params: [0.0; 4],
+            extra: [0.0; 4],
+            opcode,
+            shape: 0,
+            material: 0,
+            scale: 1.0,
+            k,
+            padding: [0; 3],
+        }
+    }
+}
+
+/// An entry of the material table, laid out like `Material` in the fragment shader (std430).
+#[repr(C)]
+#[derive(BufferContents, Clone, Copy, Debug, PartialEq)]
+pub struct MaterialData {
+    pub color: [f32; 4],
+}
+
+/// A light as the fragment shader's `Light` (std430) sees it: `.w` of `position` is 0 for directional lights, whose
+/// normalized direction is in `.xyz`, and 1 for point lights; `color` is premultiplied by the intensity.
+#[repr(C)]
+#[derive(BufferContents, Clone, Copy, Debug)]
+pub struct LightData {
+    pub position: [f32; 4],
+    pub color: [f32; 4],
+}
+
+/// What the shaders read from the scene's storage buffers, and what the CPU reference renderer evaluates.
+#[derive(Clone, Debug)]
+pub struct CompiledScene {
+    /// Only used by the interpreting shader, a generated `map()` has the program built in.
+    pub program: Vec<Instruction>,
+    pub materials: Vec<MaterialData>,
+    /// Never empty: storage buffers can't be, so a scene without lights gets a black one.
+    pub lights: Vec<LightData>,
+}
+
+/// Index of `material` in `materials`, appending it if it isn't in the table yet. The compiler and `codegen` both visit
+/// the primitives in order, so they build the same table.
+pub(crate) fn material_index(materials: &mut Vec<MaterialData>, material: &Material) -> u32 {
+    let [r, g, b] = material.color;
+    let data = MaterialData { color: [r, g, b, 1.0] };
+    let index = materials.iter().position(|m| *m == data).unwrap_or_else(|| {
+        materials.push(data);
+        materials.len() - 1
+    });
+    index as u32
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, std::io::Error),
+    Ron(ron::error::SpannedError),
+    Json(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            SceneError::Ron(e) => write!(f, "{}", e),
+            SceneError::Json(e) => write!(f, "{}", e),
+            SceneError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Scene {
+    /// Loads a `.json` scene file, anything else is parsed as RON.
+    pub fn load(path: &Path) -> Result<Self, SceneError> {
+        let source = std::fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+        if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            serde_json::from_str(&source).map_err(SceneError::Json)
+        } else {
+            ron::from_str(&source).map_err(SceneError::Ron)
+        }
+    }
+
+    /// `params` padded with zeros and packed four to a `vec4`, as the per-frame uniforms hold them.
+    pub fn packed_params(&self) -> [[f32; 4]; 4] {
+        let mut packed = [[0.0; 4]; 4];
+        for (i, value) in self.params.iter().take(MAX_PARAMS).enumerate() {
+            packed[i / 4][i % 4] = *value;
+        }
+        packed
+    }
+
+    /// The floor and figure that used to be hard-coded in `map()`.
+    pub fn default_scene() -> Self {
+        ron::from_str(DEFAULT_SCENE).expect("scenes/default.ron is invalid")
+    }
+
+    /// Flattens the node tree into the postfix program the shader and the CPU reference renderer evaluate,
+    /// and collects the material table and the lights.
+    pub fn compile(&self) -> Result<CompiledScene, SceneError> {
+        if self.nodes.is_empty() {
+            return Err(SceneError::Invalid("scene has no nodes".to_string()));
+        }
+
+        let mut program = Vec::new();
+        let mut materials = Vec::new();
+        let depth = compile_children(&self.nodes, Op::Union, &mut program, &mut materials)?;
+        if depth > MAX_STACK {
+            return Err(SceneError::Invalid(format!(
+                "scene nests too deep: needs {} stack slots, the shader has {}",
+                depth, MAX_STACK
+            )));
+        }
+        if self.params.len() > MAX_PARAMS {
+            return Err(SceneError::Invalid(format!("scene has {} params, at most {} are supported", self.params.len(), MAX_PARAMS)));
+        }
+        let domains = self.nodes.iter().map(domain_depth).max().unwrap_or(0);
+        if domains > MAX_DOMAINS {
+            return Err(SceneError::Invalid(format!(
+                "scene nests {} transforms and repetitions, at most {} are supported",
+                domains, MAX_DOMAINS
+            )));
+        }
+
+        let mut lights = self.lights.iter().map(Light::data).collect::<Result<Vec<_>, _>>()?;
+        if lights.is_empty() {
+            lights.push(LightData { position: [0.0, 1.0, 0.0, 0.0], color: [0.0; 4] });
+        }
+        Ok(CompiledScene { program, materials, lights })
+    }
+}
+
+/// Emits the children followed by `op` after each but the first; returns the stack depth needed.
+fn compile_children(children: &[Node], op: Op, program: &mut Vec<Instruction>, materials: &mut Vec<MaterialData>) -> Result<usize, SceneError> {
+    let (opcode, k) = match op {
+        Op::Union => (OP_UNION, 0.0),
+        Op::SmoothUnion { k } if k > 0.0 => (OP_SMOOTH_UNION, k),
+        Op::SmoothUnion { k } => return Err(SceneError::Invalid(format!("SmoothUnion needs a positive k, got {}", k))),
+        Op::Subtraction => (OP_SUBTRACTION, 0.0),
+        Op::Intersection => (OP_INTERSECTION, 0.0),
+    };
+    if children.is_empty() {
+        return Err(SceneError::Invalid("Combine needs at least one child".to_string()));
+    }
+
+    let mut depth = 0;
+    for (i, child) in children.iter().enumerate() {
+        // Everything before this child has been folded into one stack entry.
+        let base = if i == 0 { 0 } else { 1 };
+        depth = depth.max(base + compile_node(child, program, materials)?);
+        if i > 0 {
+            program.push(Instruction::op(opcode, k));
+        }
+    }
+    Ok(depth)
+}
+
+fn domain_depth(node: &Node) -> usize {
+    match node {
+        Node::Primitive { .. } => 0,
+        Node::Combine { children, .. } => children.iter().map(domain_depth).max().unwrap_or(0),
+        Node::Transform { children, .. } | Node::Repeat { children, .. } => 1 + children.iter().map(domain_depth).max().unwrap_or(0),
+    }
+}
+
+fn compile_node(node: &Node, program: &mut Vec<Instruction>, materials: &mut Vec<MaterialData>) -> Result<usize, SceneError> {
+    match node {
+        Node::Combine { op, children } => compile_children(children, *op, program, materials),
+        Node::Transform { transform, children } => {
+            transform.validate()?;
+            program.push(Instruction {
+                inverse_transform: transform.inverse().into(),
+                scale: transform.scale,
+                ..Instruction::op(OP_PUSH_TRANSFORM, 0.0)
+            });
+            let depth = compile_children(children, Op::Union, program, materials)?;
+            program.push(Instruction::op(OP_POP_DOMAIN, 0.0));
+            Ok(depth)
+        }
+        Node::Repeat { period, children } => {
+            if period.iter().any(|p| *p < 0.0) {
+                return Err(SceneError::Invalid(format!("Repeat period must not be negative, got {:?}", period)));
+            }
+            let [x, y, z] = *period;
+            program.push(Instruction {
+                params: [x, y, z, 0.0],
+                ..Instruction::op(OP_PUSH_REPEAT, 0.0)
+            });
+            let depth = compile_children(children, Op::Union, program, materials)?;
+            program.push(Instruction::op(OP_POP_DOMAIN, 0.0));
+            Ok(depth)
+        }
+        Node::Primitive { shape, transform, material } => {
+            transform.validate()?;
+            let (shape, params, extra) = match *shape {
+                Shape::Sphere { radius } => (SHAPE_SPHERE, [radius, 0.0, 0.0, 0.0], [0.0; 4]),
+                Shape::Capsule { height, radius } => (SHAPE_CAPSULE, [height, radius, 0.0, 0.0], [0.0; 4]),
+                Shape::Box { size: [x, y, z] } => (SHAPE_BOX, [x, y, z, 0.0], [0.0; 4]),
+                Shape::Torus { major_radius, minor_radius } => (SHAPE_TORUS, [major_radius, minor_radius, 0.0, 0.0], [0.0; 4]),
+                Shape::Plane => (SHAPE_PLANE, [0.0; 4], [0.0; 4]),
+                Shape::Terrain { max_height, offset, frequency, snow_color: [r, g, b] } => {
+                    (SHAPE_TERRAIN, [max_height, offset, frequency, 0.0], [r, g, b, 0.0])
+                }
+            };
+            let material = material_index(materials, material);
+
+            program.push(Instruction {
+                inverse_transform: transform.inverse().into(),
+                params,
+                extra,
+                shape,
+                material,
+                scale: transform.scale,
+                ..Instruction::op(OP_PRIMITIVE, 0.0)
+            });
+            Ok(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere() -> Node {
+        Node::Primitive { shape: Shape::Sphere { radius: 1.0 }, transform: Transform::default(), material: Material::default() }
+    }
+
+    fn scene(nodes: Vec<Node>) -> Scene {
+        Scene { nodes, lights: Vec::new(), params: Vec::new() }
+    }
+
+    /// `depth` unions, each holding a sphere and the next one.
+    fn nested_unions(depth: usize) -> Node {
+        (0..depth).fold(sphere(), |inner, _| Node::Combine { op: Op::Union, children: vec![sphere(), inner] })
+    }
+
+    /// `depth` transforms around a sphere.
+    fn nested_transforms(depth: usize) -> Node {
+        (0..depth).fold(sphere(), |inner, _| Node::Transform { transform: Transform::default(), children: vec![inner] })
+    }
+
+    fn compile_error(scene: Scene) -> String {
+        match scene.compile() {
+            Err(SceneError::Invalid(message)) => message,
+            Err(e) => panic!("expected an invalid scene, got {}", e),
+            Ok(_) => panic!("expected an invalid scene, it compiled"),
+        }
+    }
+
+    #[test]
+    fn compiles_the_default_scene() {
+        let compiled = Scene::default_scene().compile().unwrap();
+        assert!(!compiled.program.is_empty());
+        assert!(!compiled.lights.is_empty());
+    }
+
+    #[test]
+    fn rejects_non_positive_blend_radius() {
+        for k in [0.0, -0.5] {
+            let node = Node::Combine { op: Op::SmoothUnion { k }, children: vec![sphere(), sphere()] };
+            assert!(compile_error(scene(vec![node])).contains("positive k"));
+        }
+    }
+
+    #[test]
+    fn rejects_non_positive_scale() {
+        for scale in [0.0, -1.0] {
+            let transform = Transform { scale, ..Transform::default() };
+            let primitive = Node::Primitive { shape: Shape::Plane, transform: transform.clone(), material: Material::default() };
+            assert!(compile_error(scene(vec![primitive])).contains("scale must be positive"));
+            let node = Node::Transform { transform, children: vec![sphere()] };
+            assert!(compile_error(scene(vec![node])).contains("scale must be positive"));
+        }
+    }
+
+    #[test]
+    fn limits_the_stack_depth() {
+        assert!(scene(vec![nested_unions(MAX_STACK - 1)]).compile().is_ok());
+        assert!(compile_error(scene(vec![nested_unions(MAX_STACK)])).contains("nests too deep"));
+    }
+
+    #[test]
+    fn limits_the_domain_depth() {
+        assert!(scene(vec![nested_transforms(MAX_DOMAINS)]).compile().is_ok());
+        assert!(compile_error(scene(vec![nested_transforms(MAX_DOMAINS + 1)])).contains("transforms and repetitions"));
+    }
+
+    #[test]
+    fn rejects_empty_scenes() {
+        assert!(compile_error(scene(Vec::new())).contains("no nodes"));
+        let node = Node::Combine { op: Op::Union, children: Vec::new() };
+        assert!(compile_error(scene(vec![node])).contains("at least one child"));
+    }
+}
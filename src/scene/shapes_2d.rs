@@ -0,0 +1,41 @@
+//! Shapes for the 2D SDF mode (`shaders/raymarching-2d.frag`), uploaded to a storage buffer like the 3D scene program.
+
+use vulkano::buffer::BufferContents;
+
+// Keep in sync with the defines in shaders/raymarching-2d.frag.
+pub const SHAPE_CIRCLE: u32 = 0;
+pub const SHAPE_RECT: u32 = 1;
+
+/// One shape, laid out like `Shape` in the 2D fragment shader (std430). Units are pixels at zoom 1, y points down.
+#[repr(C)]
+#[derive(BufferContents, Clone, Copy, Debug)]
+pub struct Shape2d {
+    /// Center in `.xy`, radius or half extents in `.zw`.
+    pub params: [f32; 4],
+    pub kind: u32,
+    _padding: [u32; 3],
+}
+
+impl Shape2d {
+    pub fn circle(center: [f32; 2], radius: f32) -> Self {
+        Self::new(SHAPE_CIRCLE, [center[0], center[1], radius, 0.0])
+    }
+
+    pub fn rect(center: [f32; 2], half_size: [f32; 2]) -> Self {
+        Self::new(SHAPE_RECT, [center[0], center[1], half_size[0], half_size[1]])
+    }
+
+    fn new(kind: u32, params: [f32; 4]) -> Self {
+        Self { params, kind, _padding: [0; 3] }
+    }
+}
+
+/// The circles that used to be hard-coded in the 2D shader, and a rectangle.
+pub fn default_shapes() -> Vec<Shape2d> {
+    vec![
+        Shape2d::circle([150.0, 150.0], 30.0),
+        Shape2d::circle([350.0, 255.0], 100.0),
+        Shape2d::circle([244.0, 400.0], 70.0),
+        Shape2d::rect([560.0, 140.0], [80.0, 40.0]),
+    ]
+}
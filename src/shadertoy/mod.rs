@@ -0,0 +1,276 @@
+//! Shadertoy prototypes: a project names the source of the Image pass, of up to four buffer passes (A to D) and of the
+//! common code, and what each pass reads in `iChannel0..3`. Passes are spliced into `shaders/shadertoy.frag`, which
+//! provides the Shadertoy uniforms and calls `mainImage`; `render_core::shadertoy` runs them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use exr::prelude::f16;
+use serde::Deserialize;
+use vulkano::format::Format;
+use crate::render_core::shaders::ShaderError;
+use crate::render_core::vulkano_core::InitError;
+
+/// Lines in `shaders/shadertoy.frag` around the placeholder `mainImage`, which a pass's source replaces.
+pub const BEGIN_MARKER: &str = "// BEGIN SHADERTOY";
+pub const END_MARKER: &str = "// END SHADERTOY";
+
+/// Channels per pass, `iChannel0` to `iChannel3`.
+pub const MAX_CHANNELS: usize = 4;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BufferName {
+    A,
+    B,
+    C,
+    D,
+}
+
+impl fmt::Display for BufferName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Buffer {:?}", self)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub enum Channel {
+    /// Reads black.
+    Empty,
+    /// A PNG or EXR file, relative to the project file, sampled with repeat wrapping.
+    Texture(PathBuf),
+    /// The output of a buffer pass, clamped at the edges. Passes run in the order A, B, C, D, Image, so a buffer that
+    /// comes earlier gives this frame's output, the pass's own buffer or a later one the previous frame's.
+    Buffer(BufferName),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Pass {
+    /// GLSL defining `void mainImage(out vec4 fragColor, in vec2 fragCoord)`, relative to the project file.
+    pub source: PathBuf,
+    /// `iChannel0` first; missing channels are `Empty`.
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+}
+
+/// A project file, or a single GLSL file as the Image pass without channels.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Project {
+    /// Code prepended to every pass, like Shadertoy's Common tab.
+    #[serde(default)]
+    pub common: Option<PathBuf>,
+    #[serde(default)]
+    pub buffers: BTreeMap<BufferName, Pass>,
+    pub image: Pass,
+    /// Directory the paths in the project are relative to.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+#[derive(Debug)]
+pub enum ShadertoyError {
+    Io(PathBuf, std::io::Error),
+    Ron(ron::error::SpannedError),
+    Json(serde_json::Error),
+    Png(PathBuf, png::DecodingError),
+    Exr(PathBuf, exr::error::Error),
+    Invalid(String),
+    /// A pass, named like `pass_name`, doesn't compile or its pipeline can't be created.
+    Shader(String, ShaderError),
+    Init(InitError),
+}
+
+impl fmt::Display for ShadertoyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShadertoyError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ShadertoyError::Ron(e) => write!(f, "{}", e),
+            ShadertoyError::Json(e) => write!(f, "{}", e),
+            ShadertoyError::Png(path, e) => write!(f, "{}: PNG: {}", path.display(), e),
+            ShadertoyError::Exr(path, e) => write!(f, "{}: EXR: {}", path.display(), e),
+            ShadertoyError::Invalid(message) => write!(f, "{}", message),
+            ShadertoyError::Shader(pass, e) => write!(f, "{}: {}", pass, e),
+            ShadertoyError::Init(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Project {
+    /// Loads a `.ron` or `.json` project file; any other file is taken as the GLSL of a single Image pass.
+    pub fn load(path: &Path) -> Result<Self, ShadertoyError> {
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new(".")).to_path_buf();
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        let mut project = match extension.as_deref() {
+            Some("ron") | Some("json") => {
+                let source = std::fs::read_to_string(path).map_err(|e| ShadertoyError::Io(path.to_path_buf(), e))?;
+                if extension.as_deref() == Some("json") {
+                    serde_json::from_str(&source).map_err(ShadertoyError::Json)?
+                } else {
+                    ron::from_str::<Project>(&source).map_err(ShadertoyError::Ron)?
+                }
+            }
+            _ => Project {
+                common: None,
+                buffers: BTreeMap::new(),
+                image: Pass { source: PathBuf::from(path.file_name().unwrap_or_default()), channels: Vec::new() },
+                dir: PathBuf::new(),
+            },
+        };
+        project.dir = dir;
+        Ok(project)
+    }
+
+    /// Checks the channel lists and that every buffer read is defined.
+    pub fn validate(&self) -> Result<(), ShadertoyError> {
+        for (name, pass) in self.passes() {
+            if pass.channels.len() > MAX_CHANNELS {
+                return Err(ShadertoyError::Invalid(format!(
+                    "{} has {} channels, at most {} are supported",
+                    pass_name(name), pass.channels.len(), MAX_CHANNELS
+                )));
+            }
+            for channel in &pass.channels {
+                if let Channel::Buffer(buffer) = channel {
+                    if !self.buffers.contains_key(buffer) {
+                        return Err(ShadertoyError::Invalid(format!("{} reads {}, which the project doesn't define", pass_name(name), buffer)));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The buffer passes in the order they run, then the Image pass with `None` as its name.
+    pub fn passes(&self) -> impl Iterator<Item = (Option<BufferName>, &Pass)> {
+        self.buffers.iter().map(|(name, pass)| (Some(*name), pass)).chain([(None, &self.image)])
+    }
+
+    /// Sets `channel` of the Image pass, e.g. from `--channel`; texture paths are relative to the project like its own.
+    pub fn set_image_channel(&mut self, channel: usize, input: Channel) {
+        if self.image.channels.len() <= channel {
+            self.image.channels.resize(channel + 1, Channel::Empty);
+        }
+        self.image.channels[channel] = input;
+    }
+
+    pub fn path(&self, relative: &Path) -> PathBuf {
+        self.dir.join(relative)
+    }
+
+    /// The files the project reads besides the project file: the common code, the pass sources and the channel textures.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let sources = self.passes().map(|(_, pass)| &pass.source);
+        let textures = self.passes().flat_map(|(_, pass)| &pass.channels).filter_map(|channel| match channel {
+            Channel::Texture(path) => Some(path),
+            _ => None,
+        });
+        self.common.iter().chain(sources).chain(textures).map(|file| self.path(file)).collect()
+    }
+
+    /// Returns `template` with the section between the markers replaced by the common code and the source of `pass`,
+    /// read from disk. `#line` directives keep compile errors pointing at the right file and line.
+    pub fn fragment_source(&self, template: &str, name: Option<BufferName>, pass: &Pass) -> Result<String, ShadertoyError> {
+        let (begin, end) = match (template.find(BEGIN_MARKER), template.find(END_MARKER)) {
+            (Some(begin), Some(end)) if begin < end => (begin, end),
+            _ => return Err(ShadertoyError::Invalid(format!("shader template has no '{}' ... '{}' section", BEGIN_MARKER, END_MARKER))),
+        };
+        let rest = template[end..].find('\n').map_or(template.len(), |i| end + i + 1);
+        let next_line = template[..rest].lines().count() + 1;
+
+        let mut spliced = String::new();
+        if name.is_none() {
+            spliced.push_str("#define SHADERTOY_IMAGE\n");
+        }
+        for file in self.common.iter().chain([&pass.source]) {
+            let path = self.path(file);
+            let source = std::fs::read_to_string(&path).map_err(|e| ShadertoyError::Io(path.clone(), e))?;
+            spliced.push_str(&format!("#line 1 \"{}\"\n{}\n", file.display(), source));
+        }
+        Ok(format!("{}{}#line {} \"shadertoy.frag\"\n{}", &template[..begin], spliced, next_line, &template[rest..]))
+    }
+}
+
+/// "Image" or "Buffer A", as Shadertoy calls the passes.
+pub fn pass_name(name: Option<BufferName>) -> String {
+    name.map_or("Image".to_string(), |name| name.to_string())
+}
+
+/// Pixels of a channel texture, ready to upload: rows bottom first, like Shadertoy flips its textures.
+pub struct TextureData {
+    pub extent: [u32; 2],
+    pub format: Format,
+    pub texels: Vec<u8>,
+}
+
+/// Loads a PNG as 8-bit RGBA, whose values are sampled as they are, without decoding sRGB, like Shadertoy does;
+/// or an EXR as half float RGBA, which every device can filter.
+pub fn load_texture(path: &Path) -> Result<TextureData, ShadertoyError> {
+    let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("png") => load_png(path),
+        Some("exr") => load_exr(path),
+        _ => Err(ShadertoyError::Invalid(format!("{}: channel textures have to be .png or .exr files", path.display()))),
+    }
+}
+
+fn load_png(path: &Path) -> Result<TextureData, ShadertoyError> {
+    let file = File::open(path).map_err(|e| ShadertoyError::Io(path.to_path_buf(), e))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| ShadertoyError::Png(path.to_path_buf(), e))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| ShadertoyError::Png(path.to_path_buf(), e))?;
+    let pixels = &buffer[..info.buffer_size()];
+
+    let rgba: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|g| [*g, *g, *g, 255]).collect(),
+        png::ColorType::Indexed => return Err(ShadertoyError::Invalid(format!("{}: indexed PNGs aren't expanded", path.display()))),
+    };
+    let extent = [info.width, info.height];
+    Ok(TextureData { extent, format: Format::R8G8B8A8_UNORM, texels: flip_rows(&rgba, info.width as usize * 4) })
+}
+
+fn load_exr(path: &Path) -> Result<TextureData, ShadertoyError> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| (resolution.width(), vec![[0.0f32; 4]; resolution.width() * resolution.height()]),
+        |(width, pixels), position, (r, g, b, a): (f32, f32, f32, f32)| pixels[position.y() * *width + position.x()] = [r, g, b, a],
+    ).map_err(|e| ShadertoyError::Exr(path.to_path_buf(), e))?;
+
+    let (width, pixels) = image.layer_data.channel_data.pixels;
+    let texels: Vec<u8> = pixels.iter().flatten().flat_map(|c| f16::from_f32(*c).to_le_bytes()).collect();
+    let height = pixels.len() / width.max(1);
+    Ok(TextureData {
+        extent: [width as u32, height as u32],
+        format: Format::R16G16B16A16_SFLOAT,
+        texels: flip_rows(&texels, width * 8),
+    })
+}
+
+fn flip_rows(texels: &[u8], row_size: usize) -> Vec<u8> {
+    texels.chunks_exact(row_size).rev().flatten().copied().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_lists_everything_the_project_reads() {
+        let mut project: Project = ron::from_str(
+            r#"(
+                common: Some("common.glsl"),
+                buffers: { A: (source: "a.glsl", channels: [Buffer(A), Texture("../noise.png")]) },
+                image: (source: "image.glsl", channels: [Buffer(A), Empty]),
+            )"#,
+        ).unwrap();
+        project.dir = PathBuf::from("project");
+        project.set_image_channel(1, Channel::Texture(PathBuf::from("/textures/wood.exr")));
+        let files = project.files();
+        let expected = ["project/common.glsl", "project/a.glsl", "project/image.glsl", "project/../noise.png", "/textures/wood.exr"];
+        assert_eq!(files, expected.map(PathBuf::from));
+    }
+}
@@ -1,10 +1,18 @@
 use std::sync::Arc;
+use winit::dpi::PhysicalSize;
 use winit::event_loop::EventLoop;
 use winit::window::{Window, WindowBuilder};
 
-pub(crate) fn init() -> (Arc<Window>, EventLoop<()>) {
+/// The window title; debug views append their legend.
+pub(crate) const TITLE: &str = "Vulkan Raymarching";
+
+pub(crate) fn init(resolution: Option<[u32; 2]>) -> (Arc<Window>, EventLoop<()>) {
     let event_loop = EventLoop::new().expect("failed to create event loop");
-    let window = Arc::new(WindowBuilder::new()
+    let mut window_builder = WindowBuilder::new().with_title(TITLE);
+    if let Some([width, height]) = resolution {
+        window_builder = window_builder.with_inner_size(PhysicalSize::new(width, height));
+    }
+    let window = Arc::new(window_builder
         .build(&event_loop).expect("failed to create window"));
 
     return (window, event_loop);